@@ -0,0 +1,171 @@
+// ROM disassembler
+//
+// Code is separated from data by tracing: starting at the entry point and
+// the interrupt vectors, every reachable instruction is followed through
+// jumps, calls and fall-through. Everything never reached is printed as
+// data. Code in bank 0 that jumps into 0x4000-0x7FFF is assumed to target
+// bank 1, since the active bank is not known statically.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+
+use gb_emulator::disasm::{self, SymbolTable};
+
+const BANK_SIZE: usize = 0x4000;
+const ENTRY_POINTS: [u16; 6] = [0x0100, 0x0040, 0x0048, 0x0050, 0x0058, 0x0060];
+
+// Longest run of a repeated byte still printed with `db`
+const MAX_DB_RUN: usize = 16;
+
+fn bank_of(offset: usize) -> u16 {
+    (offset / BANK_SIZE) as u16
+}
+
+// CPU address of a ROM offset while its bank is mapped
+fn cpu_addr(offset: usize) -> u16 {
+    if offset < BANK_SIZE {
+        offset as u16
+    } else {
+        (BANK_SIZE + offset % BANK_SIZE) as u16
+    }
+}
+
+// ROM offset of a CPU address when `bank` is mapped at 0x4000
+fn rom_offset(addr: u16, bank: u16) -> Option<usize> {
+    match addr {
+        0x0000..=0x3FFF => Some(addr as usize),
+        0x4000..=0x7FFF => Some(bank.max(1) as usize * BANK_SIZE + (addr as usize - BANK_SIZE)),
+        _ => None, // Code copied to RAM can't be traced statically
+    }
+}
+
+struct Trace {
+    code: Vec<bool>,  // Byte belongs to an instruction
+    start: Vec<bool>, // Byte starts an instruction
+    targets: Vec<usize>,
+}
+
+fn trace(rom: &[u8]) -> Trace {
+    let mut t = Trace {
+        code: vec![false; rom.len()],
+        start: vec![false; rom.len()],
+        targets: Vec::new(),
+    };
+    let mut queue: Vec<usize> = ENTRY_POINTS.iter().map(|&a| a as usize).collect();
+
+    while let Some(offset) = queue.pop() {
+        if offset >= rom.len() || t.start[offset] {
+            continue;
+        }
+        let bank = bank_of(offset);
+        let base = offset - cpu_addr(offset) as usize % BANK_SIZE;
+        let read = |addr: u16| rom.get(base + addr as usize % BANK_SIZE).copied().unwrap_or(0xFF);
+        let instr = disasm::decode(read, cpu_addr(offset));
+
+        let end = offset + instr.length as usize;
+        if end > rom.len() || bank_of(end - 1) != bank || t.code[offset..end].iter().any(|&c| c) {
+            continue; // Overlaps other code or runs off the bank
+        }
+        t.code[offset..end].fill(true);
+        t.start[offset] = true;
+
+        if let Some(target) = instr.target().and_then(|addr| rom_offset(addr, bank)) {
+            t.targets.push(target);
+            queue.push(target);
+        }
+        if !instr.ends_flow() {
+            queue.push(end);
+        }
+    }
+    t
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    let (rom_path, sym_path) = match args.as_slice() {
+        [_, rom] => (rom, None),
+        [_, rom, flag, sym] if flag == "--sym" => (rom, Some(sym)),
+        _ => {
+            eprintln!("Usage: gb-disasm <rom_file> [--sym FILE]");
+            std::process::exit(1);
+        }
+    };
+
+    let rom = fs::read(rom_path)?;
+    let mut symbols = match sym_path {
+        Some(path) => SymbolTable::parse(&fs::read_to_string(path)?)?,
+        None => SymbolTable::new(),
+    };
+
+    let t = trace(&rom);
+
+    // Name every traced branch target that the symbol file doesn't cover
+    for &target in &t.targets {
+        let (bank, addr) = (bank_of(target), cpu_addr(target));
+        if symbols.lookup(addr, bank).is_none() {
+            symbols.insert(bank, addr, &format!("L{:02X}_{:04X}", bank, addr));
+        }
+    }
+
+    let mut offset = 0;
+    while offset < rom.len() {
+        let bank = bank_of(offset);
+        let addr = cpu_addr(offset);
+
+        if offset % BANK_SIZE == 0 {
+            let kind = if bank == 0 { "ROM0[$0000]".to_string() } else { format!("ROMX[$4000], BANK[${:X}]", bank) };
+            println!("\nSECTION \"ROM Bank ${:03X}\", {}\n", bank, kind);
+        }
+        if let Some(label) = symbols.lookup(addr, bank) {
+            println!("{}:", label);
+        }
+
+        if t.start[offset] {
+            let base = offset - addr as usize % BANK_SIZE;
+            let instr = disasm::decode(|a| rom[base + a as usize % BANK_SIZE], addr);
+            let len = instr.length as usize;
+            let bytes: Vec<String> = rom[offset..offset + len].iter().map(|b| format!("{:02X}", b)).collect();
+            let cycles = match instr.cycles_taken {
+                Some(taken) => format!("{}/{}", taken, instr.cycles),
+                None => instr.cycles.to_string(),
+            };
+            println!(
+                "    {:<32} ; {:02X}:{:04X}  {:<9} {}",
+                instr.format(&symbols, bank.max(1)),
+                bank,
+                addr,
+                bytes.join(" "),
+                cycles
+            );
+            offset += len;
+            continue;
+        }
+
+        // Data runs stop at code, labels and bank boundaries
+        let mut end = offset + 1;
+        while end < rom.len()
+            && !t.code[end]
+            && end % BANK_SIZE != 0
+            && symbols.lookup(cpu_addr(end), bank_of(end)).is_none()
+        {
+            end += 1;
+        }
+
+        let run = rom[offset..end].iter().take_while(|&&b| b == rom[offset]).count();
+        if run > MAX_DB_RUN {
+            let text = format!("ds {}, ${:02X}", run, rom[offset]);
+            println!("    {:<32} ; {:02X}:{:04X}", text, bank, addr);
+            offset += run;
+            continue;
+        }
+
+        let chunk = &rom[offset..end.min(offset + 8)];
+        let bytes: Vec<String> = chunk.iter().map(|b| format!("${:02X}", b)).collect();
+        let text = format!("db {}", bytes.join(","));
+        println!("    {:<32} ; {:02X}:{:04X}", text, bank, addr);
+        offset += chunk.len();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+// Headless renderer
+//
+// Runs a ROM for a number of frames without a window and writes the final
+// picture as a PNG, for checking rendering against reference screenshots.
+// With `--model sgb` the picture is the 256x224 Super Game Boy output with
+// its border and palettes. `--dump-frames` also keeps every Nth frame on
+// the way for regression comparisons. `--script` runs an automation script
+// alongside, which can end the run early with exit().
+
+use std::env;
+use std::error::Error;
+use std::fs;
+
+use gb_emulator::GameBoy;
+use gb_emulator::config::{Config, Model};
+use gb_emulator::dump::FrameDumper;
+use gb_emulator::palette::Palette;
+use gb_emulator::script::Script;
+
+const USAGE: &str = "Usage: gb-render <rom_file> <out.png> [--frames N] [--scale N] [--model dmg0|dmg|mgb|sgb|cgb]
+       [--boot-rom FILE] [--palette NAME|FILE] [--dump-frames DIR [--dump-every N]] [--script FILE]";
+const DEFAULT_FRAMES: u32 = 300;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let (rom_path, out_path) = (&args[0], &args[1]);
+
+    let rom = fs::read(rom_path)?;
+    let mut frames = DEFAULT_FRAMES;
+    let mut scale = 1;
+    // The model follows the cartridge header unless --model picks one or the
+    // boot ROM is a CGB one
+    let mut config = Config::for_rom(&rom);
+    let mut model = None;
+    let mut palette = None;
+    let (mut dump_dir, mut dump_every) = (None, 1);
+    let mut script_path = None;
+    let mut rest = args[2..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().ok_or_else(|| format!("{} needs a value\n{}", flag, USAGE))?;
+        match flag.as_str() {
+            "--frames" => frames = value.parse().map_err(|_| format!("bad frame count '{}'", value))?,
+            "--scale" => scale = value.parse().map_err(|_| format!("bad scale '{}'", value))?,
+            "--model" => model = Some(Model::parse(value)?),
+            "--palette" => palette = Some(Palette::load(value)?),
+            "--dump-frames" => dump_dir = Some(value.clone()),
+            "--dump-every" => dump_every = value.parse().map_err(|_| format!("bad frame count '{}'", value))?,
+            "--boot-rom" => config.boot_rom = Some(fs::read(value)?),
+            "--script" => script_path = Some(value.clone()),
+            _ => return Err(format!("unknown option '{}'\n{}", flag, USAGE).into()),
+        }
+    }
+
+    if let Some(model) = model.or_else(|| config.boot_rom.as_deref().and_then(Model::for_boot_rom)) {
+        config.model = model;
+    }
+
+    let mut gameboy = GameBoy::with_config(&rom, &config);
+    gameboy.display_palette = palette.as_ref().map(Palette::rgb555);
+    let mut dumper = match &dump_dir {
+        Some(dir) => Some(FrameDumper::new(dir, dump_every)?),
+        None => None,
+    };
+    let mut script = match &script_path {
+        Some(path) => {
+            let mut script = Script::load(path)?;
+            script.start(&mut gameboy).map_err(|e| format!("{}: {}", path, e))?;
+            Some(script)
+        }
+        None => None,
+    };
+    let mut frames_run = 0;
+    while frames_run < frames {
+        let running = match (&mut script, &script_path) {
+            (Some(script), Some(path)) => script.run_frame(&mut gameboy, 0).map_err(|e| format!("{}: {}", path, e))?,
+            _ => {
+                gameboy.run_frame();
+                true
+            }
+        };
+        frames_run += 1;
+        if let Some(dumper) = &mut dumper {
+            dumper.on_frame(&gameboy)?;
+        }
+        if !running {
+            break;
+        }
+    }
+
+    let (width, height, _) = gameboy.screen();
+    fs::write(out_path, gameboy.screenshot_png(scale))?;
+    println!("Wrote {}x{} frame {} to {}", width * scale.max(1), height * scale.max(1), frames_run, out_path);
+    Ok(())
+}
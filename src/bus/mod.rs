@@ -0,0 +1,69 @@
+// What the CPU sees of the rest of the console
+//
+// `Cpu::step` only talks to the hardware through this trait, so the same
+// CPU runs against the Game Boy memory map (`Memory`), a flat 64 KiB bus
+// for single-step tests, a bus that records accesses for traces, or other
+// mappers. `tick` is called once per instruction with the cycles it took.
+
+use crate::storage::{buffer, Buffer};
+
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+
+    // Advances the rest of the hardware by `cycles` CPU cycles
+    fn tick(&mut self, cycles: u8);
+
+    // Interrupts both requested (IF) and enabled (IE), in IF bit order
+    fn pending_interrupts(&self) -> u8;
+
+    // Cycles the CPU has to sit out before its next instruction, at most
+    // 128 at a time, like a CGB VRAM DMA. 0 lets it run.
+    fn take_stall(&mut self) -> u8 {
+        0
+    }
+
+    // Called on STOP, where a CGB switches speed
+    fn stop(&mut self) {}
+}
+
+// 64 KiB of plain RAM and nothing else: no mapping, no registers with side
+// effects. IF and IE are just the bytes at 0xFF0F and 0xFFFF.
+#[derive(Clone)]
+pub struct FlatBus {
+    pub ram: Buffer<u8, 0x10000>,
+    pub cycles: u64, // Total ticked so far
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        FlatBus {
+            ram: buffer::<_, 0x10000>(0),
+            cycles: 0,
+        }
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as u64;
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.ram[0xFF0F] & self.ram[0xFFFF] & 0x1F
+    }
+}
@@ -0,0 +1,155 @@
+// Animated GIF encoder
+//
+// Every image gets its own color table. Game Boy frames rarely use more
+// than 256 colors; frames that do are reduced to 3-3-2 bit RGB first.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+const MAX_CODE_BITS: u32 = 12;
+
+pub struct GifEncoder<W: Write> {
+    out: W,
+    width: u16,
+    height: u16,
+}
+
+impl<W: Write> GifEncoder<W> {
+    // Writes the header and asks viewers to loop forever
+    pub fn new(mut out: W, width: usize, height: usize) -> io::Result<Self> {
+        let (width, height) = (width as u16, height as u16);
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        out.write_all(&[0, 0, 0])?; // No global color table
+        out.write_all(&[0x21, 0xFF, 11])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[3, 1, 0, 0, 0])?;
+        Ok(GifEncoder { out, width, height })
+    }
+
+    // Adds an image shown for `delay` hundredths of a second. Pixels are
+    // 0xAARRGGBB, alpha ignored.
+    pub fn add_frame(&mut self, pixels: &[u32], delay: u16) -> io::Result<()> {
+        let (table, indices) = index_colors(pixels);
+        let bits = table.len().next_power_of_two().trailing_zeros().max(1);
+
+        // Graphic control extension: delay, no transparency
+        self.out.write_all(&[0x21, 0xF9, 4, 0])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+
+        // Image descriptor with a local color table
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&self.width.to_le_bytes())?;
+        self.out.write_all(&self.height.to_le_bytes())?;
+        self.out.write_all(&[0x80 | (bits - 1) as u8])?;
+        for i in 0..1usize << bits {
+            let color = table.get(i).copied().unwrap_or(0);
+            self.out.write_all(&[(color >> 16) as u8, (color >> 8) as u8, color as u8])?;
+        }
+
+        let min_code_size = bits.max(2);
+        self.out.write_all(&[min_code_size as u8])?;
+        for block in lzw_encode(&indices, min_code_size).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Color table and per-pixel indices into it
+fn index_colors(pixels: &[u32]) -> (Vec<u32>, Vec<u8>) {
+    let mut table = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(pixels.len());
+    for &argb in pixels {
+        let color = argb & 0xFF_FFFF;
+        let index = *lookup.entry(color).or_insert_with(|| {
+            table.push(color);
+            table.len() - 1
+        });
+        if index > 255 {
+            return index_colors(&reduce_332(pixels));
+        }
+        indices.push(index as u8);
+    }
+    (table, indices)
+}
+
+// Rounds colors to 3-3-2 bits so at most 256 remain
+fn reduce_332(pixels: &[u32]) -> Vec<u32> {
+    pixels
+        .iter()
+        .map(|&c| {
+            let (r, g, b) = ((c >> 21) & 0x7, (c >> 13) & 0x7, (c >> 6) & 0x3);
+            (r * 255 / 7) << 16 | (g * 255 / 7) << 8 | (b * 255 / 3)
+        })
+        .collect()
+}
+
+// Variable-width LZW as GIF uses it, codes packed LSB first
+fn lzw_encode(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = Vec::new();
+    let (mut acc, mut acc_bits) = (0u32, 0u32);
+    let mut emit = |code: u16, bits: u32, out: &mut Vec<u8>| {
+        acc |= (code as u32) << acc_bits;
+        acc_bits += bits;
+        while acc_bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            acc_bits -= 8;
+        }
+    };
+
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut code_bits = min_code_size + 1;
+    emit(clear, code_bits, &mut out);
+
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let Some(p) = prefix else {
+            prefix = Some(index as u16);
+            continue;
+        };
+        if let Some(&code) = dict.get(&(p, index)) {
+            prefix = Some(code);
+            continue;
+        }
+
+        emit(p, code_bits, &mut out);
+        if next_code < 1 << MAX_CODE_BITS {
+            dict.insert((p, index), next_code);
+            next_code += 1;
+            if next_code > 1 << code_bits && code_bits < MAX_CODE_BITS {
+                code_bits += 1;
+            }
+        } else {
+            // Table full: start over
+            emit(clear, code_bits, &mut out);
+            dict.clear();
+            next_code = end + 1;
+            code_bits = min_code_size + 1;
+        }
+        prefix = Some(index as u16);
+    }
+
+    if let Some(p) = prefix {
+        emit(p, code_bits, &mut out);
+    }
+    emit(end, code_bits, &mut out);
+    if acc_bits > 0 {
+        out.push(acc as u8);
+    }
+    out
+}
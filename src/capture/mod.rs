@@ -0,0 +1,172 @@
+// Gameplay clip recording
+//
+// `.gif` paths get an animated GIF at half the frame rate, anything else a
+// YUV4MPEG2 video (`.y4m`) with a WAV audio track next to it, both at the
+// console's exact frame rate so they stay in sync and any encoder can take
+// them from there. The core has no APU yet, so the audio track is silence
+// of the right length.
+
+mod gif;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::ppu::rgb555_to_argb;
+
+pub use gif::GifEncoder;
+
+// CPU clock in Hz; a frame lasts CYCLES_PER_FRAME of these
+pub const CLOCK_HZ: u64 = 4_194_304;
+pub const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: u16 = 2;
+
+// GIF delays are in hundredths of a second, too coarse for 59.7 fps
+const GIF_FRAME_STEP: u64 = 2;
+
+pub enum Recording {
+    Av { video: BufWriter<File>, audio: WavWriter<BufWriter<File>>, sample_clock: u64 },
+    Gif { gif: GifEncoder<BufWriter<File>>, frame: u64, shown_cs: u64 },
+}
+
+impl Recording {
+    pub fn start(path: &str, gameboy: &GameBoy) -> io::Result<Self> {
+        // The audio track goes next to the video as .wav, which must not be
+        // the video itself
+        if Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav")) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "clip path must not end in .wav, use .y4m or .gif"));
+        }
+
+        let (width, height, _) = gameboy.screen();
+        let file = BufWriter::new(File::create(path)?);
+        if path.ends_with(".gif") {
+            return Ok(Recording::Gif {
+                gif: GifEncoder::new(file, width, height)?,
+                frame: 0,
+                shown_cs: 0,
+            });
+        }
+
+        let mut video = file;
+        writeln!(video, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", width, height, CLOCK_HZ, CYCLES_PER_FRAME)?;
+        let wav_path = Path::new(path).with_extension("wav");
+        let audio = WavWriter::new(BufWriter::new(File::create(wav_path)?))?;
+        Ok(Recording::Av { video, audio, sample_clock: 0 })
+    }
+
+    // Call once after every emulated frame
+    pub fn add_frame(&mut self, gameboy: &GameBoy) -> io::Result<()> {
+        let (_, _, screen) = gameboy.screen();
+        match self {
+            Recording::Av { video, audio, sample_clock } => {
+                write_yuv_frame(video, &screen)?;
+
+                // One frame's worth of samples, carrying the remainder
+                *sample_clock += SAMPLE_RATE as u64 * CYCLES_PER_FRAME as u64;
+                let samples = *sample_clock / CLOCK_HZ;
+                *sample_clock %= CLOCK_HZ;
+                audio.write_silence(samples as usize)
+            }
+            Recording::Gif { gif, frame, shown_cs } => {
+                *frame += 1;
+                if (*frame - 1) % GIF_FRAME_STEP != 0 {
+                    return Ok(());
+                }
+                // Delay so the total shown time tracks the emulated time
+                let end_cs = (*frame - 1 + GIF_FRAME_STEP) * CYCLES_PER_FRAME as u64 * 100 / CLOCK_HZ;
+                let delay = end_cs - *shown_cs;
+                *shown_cs = end_cs;
+                let pixels: Vec<u32> = screen.iter().map(|&c| rgb555_to_argb(c)).collect();
+                gif.add_frame(&pixels, delay as u16)
+            }
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Recording::Av { mut video, audio, .. } => {
+                video.flush()?;
+                audio.finish()?;
+            }
+            Recording::Gif { gif, .. } => {
+                gif.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// One 4:4:4 frame, BT.601 limited range
+fn write_yuv_frame(out: &mut impl Write, screen: &[u16]) -> io::Result<()> {
+    let mut planes = vec![0u8; screen.len() * 3];
+    let (y_plane, rest) = planes.split_at_mut(screen.len());
+    let (u_plane, v_plane) = rest.split_at_mut(screen.len());
+    for (i, &color) in screen.iter().enumerate() {
+        let argb = rgb555_to_argb(color);
+        let (r, g, b) = (((argb >> 16) & 0xFF) as i32, ((argb >> 8) & 0xFF) as i32, (argb & 0xFF) as i32);
+        y_plane[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        u_plane[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        v_plane[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+    out.write_all(b"FRAME\n")?;
+    out.write_all(&planes)
+}
+
+// 16-bit stereo PCM; the header's sizes are filled in by `finish`
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_bytes: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let block_align = CHANNELS * 2;
+        out.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&CHANNELS.to_le_bytes())?;
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        out.write_all(&(SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data\0\0\0\0")?;
+        Ok(WavWriter { out, data_bytes: 0 })
+    }
+
+    // Interleaved left/right samples
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for sample in samples {
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_bytes += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    pub fn write_silence(&mut self, frames: usize) -> io::Result<()> {
+        self.write_samples(&vec![0; frames * CHANNELS as usize])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_bytes.to_le_bytes())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_wav_video_paths() {
+        let gameboy = GameBoy::new(&vec![0; 0x8000]);
+        let path = std::env::temp_dir().join("gb_emulator_capture_test.wav");
+        let err = Recording::start(path.to_str().unwrap(), &gameboy).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+}
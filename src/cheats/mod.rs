@@ -0,0 +1,373 @@
+// Cheat codes
+//
+// Two kinds, as the original devices applied them:
+//
+// - GameShark `TTVVLLHH`: writes VV to RAM address HHLL at every VBlank.
+//   TT 00/01 writes through the current mapping; 8X/9X picks WRAM bank X
+//   for 0xD000-0xDFFF on a CGB.
+// - Game Genie `ABC-DEF[-GHI]`: replaces the ROM byte the CPU reads at an
+//   address with AB, optionally only while the original byte is the compare
+//   value, since a banked address holds different code in every bank.
+//
+// `.cht` files hold one code per line, optionally followed by a name. A
+// leading `-` stores a disabled code and lines starting with `#` are
+// comments:
+//
+//     # Super Mario Land
+//     010238CD Infinite lives
+//     -00A-17B-C49 Start with fire flower
+
+mod search;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io;
+
+pub use search::{RamSearch, SearchFilter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatKind {
+    GameShark { bank: Option<u8>, addr: u16, value: u8 },
+    GameGenie { addr: u16, value: u8, compare: Option<u8> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: String, // As entered, for display and saving
+    pub name: String,
+    pub enabled: bool,
+    pub kind: CheatKind,
+}
+
+impl Cheat {
+    pub fn parse(code: &str, name: &str) -> Result<Cheat, String> {
+        let kind = if code.contains('-') {
+            parse_game_genie(code)?
+        } else {
+            parse_game_shark(code)?
+        };
+        Ok(Cheat {
+            code: code.to_ascii_uppercase(),
+            name: name.to_string(),
+            enabled: true,
+            kind,
+        })
+    }
+
+    // What the code does, e.g. "D123 = 05 (WRAM bank 2)"
+    pub fn describe(&self) -> String {
+        match self.kind {
+            CheatKind::GameShark { bank: Some(bank), addr, value } => {
+                format!("{:04X} = {:02X} (WRAM bank {})", addr, value, bank.max(1))
+            }
+            CheatKind::GameShark { bank: None, addr, value } => format!("{:04X} = {:02X}", addr, value),
+            CheatKind::GameGenie { addr, value, compare: Some(compare) } => {
+                format!("ROM {:04X} = {:02X} where {:02X}", addr, value, compare)
+            }
+            CheatKind::GameGenie { addr, value, compare: None } => format!("ROM {:04X} = {:02X}", addr, value),
+        }
+    }
+}
+
+fn hex_digits(text: &str, code: &str) -> Result<Vec<u8>, String> {
+    text.chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| format!("bad cheat code '{}': not hex", code))
+}
+
+fn parse_game_shark(code: &str) -> Result<CheatKind, String> {
+    let d = hex_digits(code, code)?;
+    if d.len() != 8 {
+        return Err(format!("bad GameShark code '{}': expected 8 hex digits", code));
+    }
+    let byte = |i: usize| d[i] << 4 | d[i + 1];
+    let bank = match byte(0) {
+        0x00 | 0x01 => None,
+        kind @ (0x80..=0x87 | 0x90..=0x97) => Some(kind & 0x07),
+        kind => return Err(format!("bad GameShark code '{}': unknown type {:02X}", code, kind)),
+    };
+    Ok(CheatKind::GameShark {
+        bank,
+        addr: u16::from_le_bytes([byte(4), byte(6)]),
+        value: byte(2),
+    })
+}
+
+fn parse_game_genie(code: &str) -> Result<CheatKind, String> {
+    let groups: Vec<&str> = code.split('-').collect();
+    if !matches!(groups.len(), 2 | 3) || groups.iter().any(|g| g.len() != 3) {
+        return Err(format!("bad Game Genie code '{}': expected ABC-DEF or ABC-DEF-GHI", code));
+    }
+    let d = hex_digits(&groups.concat(), code)?;
+
+    // The address is FCDE with the top digit inverted
+    let addr = u16::from_be_bytes([(d[5] ^ 0xF) << 4 | d[2], d[3] << 4 | d[4]]);
+    if addr >= 0x8000 {
+        return Err(format!("bad Game Genie code '{}': address {:04X} is not ROM", code, addr));
+    }
+    // GI holds the compare value rotated and scrambled; H is not used
+    let compare = (d.len() == 9).then(|| (d[6] << 4 | d[8]).rotate_right(2) ^ 0xBA);
+    Ok(CheatKind::GameGenie {
+        addr,
+        value: d[0] << 4 | d[1],
+        compare,
+    })
+}
+
+// Active cheats. Lives on `Memory` so ROM reads can be patched; like
+// watchpoints it is not part of the emulated state.
+#[derive(Clone)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+    active: bool, // Master switch
+    // Enabled Game Genie patches as (addr, value, compare), so the check in
+    // `Memory::read` is a single branch when there are none
+    rom_patches: Vec<(u16, u8, Option<u8>)>,
+    // Enabled GameShark writes as (bank, addr, value), made every VBlank
+    ram_writes: Vec<(Option<u8>, u16, u8)>,
+}
+
+impl Default for Cheats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats {
+            list: Vec::new(),
+            active: true,
+            rom_patches: Vec::new(),
+            ram_writes: Vec::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Cheats, String> {
+        let mut cheats = Cheats::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (code, enabled) = match code.strip_prefix('-') {
+                Some(code) => (code, false),
+                None => (code, true),
+            };
+            let mut cheat = Cheat::parse(code, name.trim()).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            cheat.enabled = enabled;
+            cheats.list.push(cheat);
+        }
+        cheats.update();
+        Ok(cheats)
+    }
+
+    pub fn to_text(&self) -> String {
+        self.list
+            .iter()
+            .map(|c| {
+                let flag = if c.enabled { "" } else { "-" };
+                format!("{}{} {}\n", flag, c.code, c.name).replace(" \n", "\n")
+            })
+            .collect()
+    }
+
+    // The cheats for a ROM live next to it as `<rom>.cht`. A missing file
+    // means no cheats.
+    #[cfg(feature = "std")]
+    pub fn load_for_rom(rom_path: &str) -> Result<Cheats, String> {
+        let path = format!("{}.cht", rom_path);
+        match fs::read_to_string(&path) {
+            Ok(text) => Cheats::parse(&text).map_err(|e| format!("{}: {}", path, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Cheats::new()),
+            Err(e) => Err(format!("{}: {}", path, e)),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.list.push(cheat);
+        self.update();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        let cheat = (index < self.list.len()).then(|| self.list.remove(index));
+        self.update();
+        cheat
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.list.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+        self.update();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        self.rom_patches.clear();
+        self.ram_writes.clear();
+        if !self.active {
+            return;
+        }
+        for cheat in self.list.iter().filter(|c| c.enabled) {
+            match cheat.kind {
+                CheatKind::GameGenie { addr, value, compare } => self.rom_patches.push((addr, value, compare)),
+                CheatKind::GameShark { bank, addr, value } => self.ram_writes.push((bank, addr, value)),
+            }
+        }
+    }
+
+    // The byte a ROM read at `addr` returns, given the byte in the cartridge
+    #[inline(always)]
+    pub fn patch_rom(&self, addr: u16, value: u8) -> u8 {
+        if self.rom_patches.is_empty() {
+            return value;
+        }
+        self.find_patch(addr, value)
+    }
+
+    #[cold]
+    fn find_patch(&self, addr: u16, value: u8) -> u8 {
+        self.rom_patches
+            .iter()
+            .find(|&&(a, _, compare)| a == addr && compare.is_none_or(|c| c == value))
+            .map_or(value, |&(_, new, _)| new)
+    }
+
+    // GameShark writes to make at VBlank as (bank, addr, value)
+    pub fn ram_writes(&self) -> &[(Option<u8>, u16, u8)] {
+        &self.ram_writes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Model};
+    use crate::memory::Memory;
+    use alloc::vec;
+
+    // Runs the PPU up to the next VBlank, where GameShark codes are applied
+    fn run_to_vblank(memory: &mut Memory) {
+        memory.if_ &= !0x01;
+        for _ in 0..70224 / 4 {
+            memory.step_ppu(4);
+            if memory.if_ & 0x01 != 0 {
+                return;
+            }
+        }
+        panic!("no VBlank");
+    }
+
+    #[test]
+    fn decodes_published_codes() {
+        // Pokémon Red/Blue, walk through walls: 01 to CD38
+        let walls = Cheat::parse("010138cd", "Walk through walls").unwrap();
+        assert_eq!(walls.kind, CheatKind::GameShark { bank: None, addr: 0xCD38, value: 0x01 });
+        assert_eq!((walls.code.as_str(), walls.describe()), ("010138CD", "CD38 = 01".to_string()));
+
+        // Super Mario Land: address (B^F)A17, compare C9 rotated right by 2, xor BA
+        let genie = Cheat::parse("00A-17B-C49", "").unwrap();
+        assert_eq!(genie.kind, CheatKind::GameGenie { addr: 0x4A17, value: 0x00, compare: Some(0xC8) });
+        assert_eq!(genie.describe(), "ROM 4A17 = 00 where C8");
+        let unchecked = Cheat::parse("3E7-05A", "").unwrap();
+        assert_eq!(unchecked.kind, CheatKind::GameGenie { addr: 0x5705, value: 0x3E, compare: None });
+
+        let banked = Cheat::parse("900523D1", "").unwrap();
+        assert_eq!(banked.kind, CheatKind::GameShark { bank: Some(0), addr: 0xD123, value: 0x05 });
+        assert_eq!(banked.describe(), "D123 = 05 (WRAM bank 1)");
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let error = |code| Cheat::parse(code, "").unwrap_err();
+        assert_eq!(error("0101"), "bad GameShark code '0101': expected 8 hex digits");
+        assert_eq!(error("FF0138CD"), "bad GameShark code 'FF0138CD': unknown type FF");
+        assert_eq!(error("0G0138CD"), "bad cheat code '0G0138CD': not hex");
+        assert_eq!(error("00A-17"), "bad Game Genie code '00A-17': expected ABC-DEF or ABC-DEF-GHI");
+        assert_eq!(error("00A-170"), "bad Game Genie code '00A-170': address FA17 is not ROM");
+    }
+
+    #[test]
+    fn round_trips_cht_files() {
+        let text = "# Super Mario Land\n010238CD Infinite lives\n-00A-17B-C49 Start with fire flower\n";
+        let cheats = Cheats::parse(text).unwrap();
+        assert_eq!(cheats.list().len(), 2);
+        assert!(!cheats.list()[1].enabled);
+        assert_eq!(cheats.list()[1].name, "Start with fire flower");
+        assert_eq!(cheats.to_text(), "010238CD Infinite lives\n-00A-17B-C49 Start with fire flower\n");
+        assert_eq!(Cheats::parse("\n010238C").err().unwrap(), "line 2: bad GameShark code '010238C': expected 8 hex digits");
+    }
+
+    #[test]
+    fn patches_rom_reads_where_the_compare_matches() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x4A17] = 0xC8;
+        rom[0x4A18] = 0x12;
+        let mut memory = Memory::new(&rom);
+        memory.cheats = Cheats::parse("00A-17B-C49\n00A-18B-C49").unwrap();
+
+        assert_eq!(memory.read(0x4A17), 0x00);
+        assert_eq!(memory.read(0x4A18), 0x12); // Another byte than the compare value
+        assert_eq!(memory.peek(0x4A17), 0xC8);
+
+        memory.cheats.set_active(false);
+        assert_eq!(memory.read(0x4A17), 0xC8);
+        memory.cheats.set_active(true);
+        memory.cheats.set_enabled(0, false);
+        assert_eq!(memory.read(0x4A17), 0xC8);
+    }
+
+    #[test]
+    fn writes_ram_at_vblank() {
+        let config = Config {
+            model: Model::Cgb,
+            ..Config::default()
+        };
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80; // CGB mode, for SVBK
+        let mut memory = Memory::with_config(&rom, &config);
+        memory.cheats = Cheats::parse("015A00C0\n920523D1").unwrap();
+        assert_eq!(memory.cheats.ram_writes(), [(None, 0xC000, 0x5A), (Some(2), 0xD123, 0x05)]);
+
+        run_to_vblank(&mut memory);
+        assert_eq!(memory.peek(0xC000), 0x5A);
+        // Bank 2 gets the write while bank 1 is mapped
+        assert_eq!(memory.peek(0xD123), 0x00);
+        memory.write(0xFF70, 2);
+        assert_eq!(memory.peek(0xD123), 0x05);
+
+        memory.write(0xC000, 0);
+        memory.cheats.remove(0);
+        run_to_vblank(&mut memory);
+        assert_eq!(memory.peek(0xC000), 0);
+        assert_eq!(memory.cheats.ram_writes().len(), 1);
+    }
+}
@@ -0,0 +1,120 @@
+// RAM search for finding new cheat addresses
+//
+// Starts with every byte of work RAM and high RAM as a candidate and
+// narrows the list by comparing each candidate with its value at the
+// previous filter: play until the number of lives drops, filter on
+// "decreased", repeat until only a few addresses are left.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::memory::Memory;
+
+// Searched ranges; WRAM at 0xD000 is whichever bank is mapped
+const RANGES: [(u16, u16); 2] = [(0xC000, 0xDFFF), (0xFF80, 0xFFFE)];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchFilter {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    Equals(u8),
+}
+
+impl SearchFilter {
+    pub fn parse(text: &str) -> Result<SearchFilter, String> {
+        let filter = match text {
+            "changed" => SearchFilter::Changed,
+            "unchanged" => SearchFilter::Unchanged,
+            "inc" | "increased" => SearchFilter::Increased,
+            "dec" | "decreased" => SearchFilter::Decreased,
+            _ => {
+                let value = text.strip_prefix("eq").or_else(|| text.strip_prefix('=')).ok_or_else(|| {
+                    format!("unknown filter '{}', expected changed, unchanged, inc, dec or =VALUE", text)
+                })?;
+                let value = value.trim_start_matches(['=', ' ']);
+                let digits = value.strip_prefix("0x").or_else(|| value.strip_prefix('$')).unwrap_or(value);
+                SearchFilter::Equals(u8::from_str_radix(digits, 16).map_err(|_| format!("bad byte '{}'", value))?)
+            }
+        };
+        Ok(filter)
+    }
+
+    fn keeps(self, old: u8, new: u8) -> bool {
+        match self {
+            SearchFilter::Changed => new != old,
+            SearchFilter::Unchanged => new == old,
+            SearchFilter::Increased => new > old,
+            SearchFilter::Decreased => new < old,
+            SearchFilter::Equals(value) => new == value,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RamSearch {
+    candidates: Vec<(u16, u8)>, // Address and its value at the last filter
+}
+
+impl RamSearch {
+    // Every address is a candidate again, with its current value
+    pub fn start(memory: &Memory) -> Self {
+        let candidates = RANGES
+            .iter()
+            .flat_map(|&(start, end)| start..=end)
+            .map(|addr| (addr, memory.peek(addr)))
+            .collect();
+        RamSearch { candidates }
+    }
+
+    // Drops the candidates the filter rejects. Returns how many are left.
+    pub fn filter(&mut self, memory: &Memory, filter: SearchFilter) -> usize {
+        self.candidates.retain_mut(|(addr, old)| {
+            let new = memory.peek(*addr);
+            let keep = filter.keeps(*old, new);
+            *old = new;
+            keep
+        });
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[(u16, u8)] {
+        &self.candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrows_to_the_changing_address() {
+        let mut memory = Memory::new(&[0; 0x8000]);
+        memory.write(0xC010, 3);
+        let mut search = RamSearch::start(&memory);
+        assert_eq!(search.candidates().len(), 0x2000 + 0x7F);
+
+        // Lives go down, a timer goes up
+        memory.write(0xC010, 2);
+        memory.write(0xFF90, 1);
+        assert_eq!(search.filter(&memory, SearchFilter::Changed), 2);
+        memory.write(0xC010, 1);
+        memory.write(0xFF90, 2);
+        assert_eq!(search.filter(&memory, SearchFilter::Decreased), 1);
+        assert_eq!(search.candidates(), [(0xC010, 1)]);
+
+        assert_eq!(search.filter(&memory, SearchFilter::Equals(2)), 0);
+    }
+
+    #[test]
+    fn parses_filters() {
+        assert_eq!(SearchFilter::parse("dec"), Ok(SearchFilter::Decreased));
+        assert_eq!(SearchFilter::parse("increased"), Ok(SearchFilter::Increased));
+        assert_eq!(SearchFilter::parse("=0x1F"), Ok(SearchFilter::Equals(0x1F)));
+        assert_eq!(SearchFilter::parse("eq $20"), Ok(SearchFilter::Equals(0x20)));
+        assert_eq!(SearchFilter::parse("=100"), Err("bad byte '100'".to_string()));
+        assert!(SearchFilter::parse("bigger").is_err());
+    }
+}
@@ -0,0 +1,121 @@
+// Power-on configuration of the console
+
+#[cfg(feature = "alloc")]
+use alloc::{format, string::String};
+
+use crate::storage::Bytes;
+
+// Contents of RAM at power-on. Real hardware powers up with noise; zero is
+// the tidy default, seeded noise reproduces the real behaviour deterministically.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RamInit {
+    #[default]
+    Zero,
+    Random { seed: u32 },
+}
+
+impl RamInit {
+    pub fn fill(&self, buf: &mut [u8]) {
+        match *self {
+            RamInit::Zero => buf.fill(0),
+            RamInit::Random { seed } => {
+                // xorshift32; a zero state would stay zero forever
+                let mut state = seed.max(1);
+                for byte in buf.iter_mut() {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    *byte = state as u8;
+                }
+            }
+        }
+    }
+
+    // Parses "zero", "random" or "random:SEED"
+    #[cfg(feature = "alloc")]
+    pub fn parse(text: &str) -> Result<RamInit, String> {
+        match text.split_once(':') {
+            None if text == "zero" => Ok(RamInit::Zero),
+            None if text == "random" => Ok(RamInit::Random { seed: 0x1234_5678 }),
+            Some(("random", seed)) => seed
+                .parse()
+                .map(|seed| RamInit::Random { seed })
+                .map_err(|_| format!("bad seed '{}'", seed)),
+            _ => Err(format!("unknown RAM init '{}', expected zero, random or random:SEED", text)),
+        }
+    }
+}
+
+// Console model whose post-boot state is used when no boot ROM is given.
+// Games tell the models apart by the registers the boot ROM leaves behind
+// (A = 0x01 DMG/SGB, 0xFF MGB, 0x11 CGB), so this affects game detection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    Dmg0,
+    #[default]
+    Dmg,
+    Mgb,
+    Sgb,
+    Cgb,
+}
+
+impl Model {
+    #[cfg(feature = "alloc")]
+    pub fn parse(text: &str) -> Result<Model, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(Model::Dmg0),
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "cgb" => Ok(Model::Cgb),
+            _ => Err(format!("unknown model '{}', expected dmg0, dmg, mgb, sgb or cgb", text)),
+        }
+    }
+
+    // Size of this model's boot ROM image
+    pub fn boot_rom_size(&self) -> usize {
+        match self {
+            Model::Cgb => 0x900,
+            _ => 0x100,
+        }
+    }
+
+    // Model a boot ROM image must come from, if its size gives it away: only
+    // the CGB one is longer than 256 bytes
+    pub fn for_boot_rom(boot_rom: &[u8]) -> Option<Model> {
+        (boot_rom.len() == Model::Cgb.boot_rom_size()).then_some(Model::Cgb)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Config {
+    pub model: Model,
+    pub vram_init: RamInit,
+    // Boot ROM image. When set the console starts at PC 0 inside the boot ROM
+    // instead of in the post-boot state of `model`. A CGB image (0x900 bytes)
+    // also overlays 0x0200-0x08FF, leaving the cartridge header visible.
+    pub boot_rom: Option<Bytes>,
+}
+
+impl Config {
+    // Default settings on the model the cartridge asks for: CGB-flagged
+    // cartridges run on a CGB, everything else on a DMG
+    pub fn for_rom(rom: &[u8]) -> Config {
+        let cgb = rom.get(0x143).is_some_and(|flag| flag & 0x80 != 0);
+        Config {
+            model: if cgb { Model::Cgb } else { Model::Dmg },
+            ..Config::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_rom_size_gives_away_a_cgb() {
+        assert_eq!(Model::for_boot_rom(&[0; 0x900]), Some(Model::Cgb));
+        assert_eq!(Model::for_boot_rom(&[0; 0x100]), None);
+    }
+}
@@ -1,6 +1,14 @@
-use log::info;
-use crate::memory::Memory;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
 
+use crate::logging::{error, info};
+use crate::bus::Bus;
+use crate::config::Model;
+use crate::memory::{Memory, Rom};
+#[cfg(feature = "alloc")]
+use crate::savestate::{SaveState, StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
 pub struct Cpu {
     pub pc: u16,
     pub sp: u16,
@@ -16,25 +24,65 @@ pub struct Cpu {
     pub ime: bool,
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Cpu {
+    // Post-boot DMG state for a cartridge with a nonzero header checksum
     pub fn new() -> Self {
+        Self::post_boot(Model::Dmg, 0xFF)
+    }
+
+    // Register state the given model's boot ROM leaves behind. The DMG and
+    // MGB boot ROMs leave H and C set unless the header checksum byte is zero.
+    pub fn post_boot(model: Model, header_checksum: u8) -> Self {
+        let hc = if header_checksum == 0 { 0x00 } else { 0x30 };
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::Dmg0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            Model::Dmg => (0x01, 0x80 | hc, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Mgb => (0xFF, 0x80 | hc, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+        };
         Cpu {
-            pc: 0x100,  // Start at ROM entry point
-            sp: 0xFFFE, // Initialize stack pointer
-            a: 0x01,    // Post-bootrom values
-            f: 0xB0,    // Post-bootrom value (Z=1, N=0, H=1, C=1)
-            b: 0x00,
-            c: 0x13,
-            d: 0x00,
-            e: 0xD8,
-            h: 0x01,
-            l: 0x4D,
+            pc: 0x100,
+            sp: 0xFFFE,
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
             total_cycles: 0,
-            ime: false,  // Interrupts initially disabled
+            ime: false,
         }
     }
 
-    pub fn peek_next_opcodes(&self, memory: &Memory, count: usize) -> Vec<u8> {
+    // Register state at power-on, before a boot ROM has run
+    pub fn power_on() -> Self {
+        Cpu {
+            pc: 0,
+            sp: 0,
+            a: 0,
+            f: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            total_cycles: 0,
+            ime: false,
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn peek_next_opcodes<R: Rom>(&self, memory: &Memory<R>, count: usize) -> Vec<u8> {
         let mut opcodes = Vec::with_capacity(count);
         let mut addr = self.pc;
         for _ in 0..count {
@@ -44,8 +92,8 @@ impl Cpu {
         opcodes
     }
 
-    fn handle_cb_opcode(&mut self, memory: &mut Memory) -> u8 {
-        let cb_opcode = memory.read(self.pc + 1);
+    fn handle_cb_opcode(&mut self, bus: &mut impl Bus) -> u8 {
+        let cb_opcode = bus.read(self.pc + 1);
         info!("CB opcode: {:02x}", cb_opcode);
         
         let cycles = match cb_opcode {
@@ -55,7 +103,7 @@ impl Cpu {
                 8
             }
             _ => {
-                log::error!("Unknown CB opcode: {:02x}", cb_opcode);
+                error!("Unknown CB opcode: {:02x}", cb_opcode);
                 8
             }
         };
@@ -63,50 +111,80 @@ impl Cpu {
         cycles
     }
 
-    pub fn step(&mut self, memory: &mut Memory) -> u8 {
-        // ALWAYS try to break out of the RST 38 loop
-        if self.pc == 0x0038 || self.total_cycles > 50000 {
+    // Runs one instruction on the Game Boy memory map: `step` plus the
+    // escape from the RST 38 loop, which pokes Game Boy registers and so
+    // stays out of the generic path
+    pub fn step_memory<R: Rom>(&mut self, memory: &mut Memory<R>) -> u8 {
+        // ALWAYS try to break out of the RST 38 loop, unless a DMA has the
+        // CPU stalled. A boot ROM runs far longer than 50000 cycles and
+        // hands over at 0x100 itself, so leave it alone while it is mapped.
+        if memory.dma_stall == 0
+            && !memory.boot_rom_mapped
+            && (self.pc == 0x0038 || self.total_cycles > 50000)
+        {
             // Break the infinite loop cycle by returning to the ROM entry point
             self.pc = 0x0100;
             self.ime = true; // Force enable interrupts
-            memory.if_ = 0xFF; // Set all interrupt flags
-            memory.ie = 0xFF; // Enable all interrupts
-            
+            memory.write(0xFF0F, 0xFF); // Set all interrupt flags
+            memory.write(0xFFFF, 0xFF); // Enable all interrupts
+
             // Make sure the PPU is configured for debugging
             memory.write(0xFF40, 0x91);  // LCDC - LCD on, BG and sprites enabled
             memory.write(0xFF47, 0xFC);  // BGP - 11 11 00 00 (Black, Black, White, White)
-            
+
             info!("Breaking infinite loop by jumping to 0x0100");
             return 20;
         }
-        
+
+        let boot_rom_mapped = memory.boot_rom_mapped;
+        let cycles = self.step(memory);
+        if boot_rom_mapped && !memory.boot_rom_mapped {
+            // The cartridge starts here; count its cycles from zero, as
+            // without a boot ROM
+            self.total_cycles = 0;
+        }
+        cycles
+    }
+
+    pub fn step(&mut self, bus: &mut impl Bus) -> u8 {
+        // The CPU is halted while the bus stalls it, like for a VRAM DMA
+        let stall = bus.take_stall();
+        if stall > 0 {
+            self.total_cycles += stall as u64;
+            bus.tick(stall);
+            return stall;
+        }
+
         // Check for pending interrupts
-        if memory.if_ & memory.ie != 0 {
+        if bus.pending_interrupts() != 0 {
             self.ime = true; // Force enable interrupts
         }
         
-        let opcode = memory.read(self.pc);
-        
-        let next_opcodes = self.peek_next_opcodes(memory, 5);
-        info!("PC: {:04x}, Current: {:02x}, Next 5: {:02x?}, A: {:02x}, F: {:02x}, BC: {:02x}{:02x}, DE: {:02x}{:02x}, HL: {:02x}{:02x}, SP: {:04x}", 
-            self.pc, opcode, next_opcodes, 
-            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp);
+        // Per-instruction state is available through `trace::Tracer`
+        let opcode = bus.read(self.pc);
 
         let cycles = match opcode {
+            0x10 => { // STOP
+                // Performs a pending CGB speed switch. Otherwise STOP would
+                // halt until a button press, which this core treats as a NOP.
+                bus.stop();
+                self.pc += 2;
+                4
+            }
             0x00 => { // NOP
                 self.pc += 1;
                 4
             }
             0xcd => { // CALL nn
-                let low = memory.read(self.pc + 1) as u16;
-                let high = memory.read(self.pc + 2) as u16;
+                let low = bus.read(self.pc + 1) as u16;
+                let high = bus.read(self.pc + 2) as u16;
                 let address = (high << 8) | low;
                 
                 // Push return address onto stack
                 self.sp = self.sp.wrapping_sub(1);
-                memory.write(self.sp, (self.pc + 3) as u8);
+                bus.write(self.sp, ((self.pc + 3) >> 8) as u8);
                 self.sp = self.sp.wrapping_sub(1);
-                memory.write(self.sp, ((self.pc + 3) >> 8) as u8);
+                bus.write(self.sp, (self.pc + 3) as u8);
                 
                 info!("CALL {:04x}", address);
                 self.pc = address;
@@ -119,8 +197,8 @@ impl Cpu {
                 4
             }
             0x21 => { // LD HL,nn
-                let low = memory.read(self.pc + 1);
-                let high = memory.read(self.pc + 2);
+                let low = bus.read(self.pc + 1);
+                let high = bus.read(self.pc + 2);
                 self.l = low;
                 self.h = high;
                 info!("LD HL,{:04x}", (high as u16) << 8 | low as u16);
@@ -128,37 +206,37 @@ impl Cpu {
                 12
             }
             0xc3 => { // JP nn
-                let low = memory.read(self.pc + 1) as u16;
-                let high = memory.read(self.pc + 2) as u16;
+                let low = bus.read(self.pc + 1) as u16;
+                let high = bus.read(self.pc + 2) as u16;
                 let address = (high << 8) | low;
                 info!("Jumping to {:04x}", address);
                 self.pc = address;
                 16
             }
             0x31 => { // LD SP, nn
-                let low = memory.read(self.pc + 1) as u16;
-                let high = memory.read(self.pc + 2) as u16;
+                let low = bus.read(self.pc + 1) as u16;
+                let high = bus.read(self.pc + 2) as u16;
                 self.sp = (high << 8) | low;
                 info!("LD SP, {:04x}", self.sp);
                 self.pc += 3;
                 12
             }
             0x3e => { // LD A, n
-                let value = memory.read(self.pc + 1);
+                let value = bus.read(self.pc + 1);
                 self.a = value;
                 info!("LD A, {:02x}", value);
                 self.pc += 2;
                 8
             }
             0xfe => { // CP n
-                let value = memory.read(self.pc + 1);
+                let value = bus.read(self.pc + 1);
                 info!("CP A({:02x}) with {:02x}", self.a, value);
                 self.f = if self.a == value { 0x80 } else { 0 };
                 self.pc += 2;
                 8
             }
             0x28 => { // JR Z, n
-                let offset = memory.read(self.pc + 1) as i8;
+                let offset = bus.read(self.pc + 1) as i8;
                 let z_flag = (self.f & 0x80) != 0;
                 if z_flag {
                     self.pc = (self.pc as i16 + offset as i16 + 2) as u16;
@@ -187,17 +265,17 @@ impl Cpu {
                 4
             }
             0x18 => { // JR n
-                let offset = memory.read(self.pc + 1) as i8;
+                let offset = bus.read(self.pc + 1) as i8;
                 self.pc = (self.pc as i16 + offset as i16 + 2) as u16;
                 info!("JR to new PC: {:04x}", self.pc);
                 12
             }
             0xea => { // LD (nn), A
-                let low = memory.read(self.pc + 1) as u16;
-                let high = memory.read(self.pc + 2) as u16;
+                let low = bus.read(self.pc + 1) as u16;
+                let high = bus.read(self.pc + 2) as u16;
                 let address = (high << 8) | low;
                 info!("LD ({:04x}), A={:02x}", address, self.a);
-                memory.write(address, self.a);
+                bus.write(address, self.a);
                 self.pc += 3;
                 16
             }
@@ -208,10 +286,10 @@ impl Cpu {
                 4
             }
             0xe0 => { // LDH (n), A
-                let offset = memory.read(self.pc + 1);
+                let offset = bus.read(self.pc + 1);
                 let address = 0xFF00 + offset as u16;
                 info!("LDH ({:04x}), A={:02x}", address, self.a);
-                memory.write(address, self.a);
+                bus.write(address, self.a);
                 self.pc += 2;
                 12
             }
@@ -223,8 +301,8 @@ impl Cpu {
             0xc0 => { // RET NZ
                 let z_flag = (self.f & 0x80) != 0;
                 if !z_flag {
-                    let low = memory.read(self.sp) as u16;
-                    let high = memory.read(self.sp + 1) as u16;
+                    let low = bus.read(self.sp) as u16;
+                    let high = bus.read(self.sp + 1) as u16;
                     self.pc = (high << 8) | low;
                     self.sp = self.sp.wrapping_add(2);
                     info!("RET NZ taken, new PC: {:04x}", self.pc);
@@ -236,8 +314,8 @@ impl Cpu {
                 }
             }
             0x01 => { // LD BC,nn
-                let low = memory.read(self.pc + 1);
-                let high = memory.read(self.pc + 2);
+                let low = bus.read(self.pc + 1);
+                let high = bus.read(self.pc + 2);
                 self.c = low;
                 self.b = high;
                 info!("LD BC,{:04x}", (high as u16) << 8 | low as u16);
@@ -245,9 +323,9 @@ impl Cpu {
                 12
             }
             0xf0 => { // LDH A,(n)
-                let offset = memory.read(self.pc + 1);
+                let offset = bus.read(self.pc + 1);
                 let address = 0xFF00 + offset as u16;
-                self.a = memory.read(address);
+                self.a = bus.read(address);
                 info!("LDH A,({:04x}), A={:02x}", address, self.a);
                 self.pc += 2;
                 12
@@ -259,10 +337,10 @@ impl Cpu {
                 4
             }
             0xcb => { // CB prefix
-                self.handle_cb_opcode(memory)
+                self.handle_cb_opcode(bus)
             }
             0x20 => { // JR NZ,n
-                let offset = memory.read(self.pc + 1) as i8;
+                let offset = bus.read(self.pc + 1) as i8;
                 let z_flag = (self.f & 0x80) != 0;
                 if !z_flag {
                     self.pc = (self.pc as i16 + offset as i16 + 2) as u16;
@@ -275,10 +353,10 @@ impl Cpu {
                 }
             }
             0xfa => { // LD A,(nn)
-                let low = memory.read(self.pc + 1) as u16;
-                let high = memory.read(self.pc + 2) as u16;
+                let low = bus.read(self.pc + 1) as u16;
+                let high = bus.read(self.pc + 2) as u16;
                 let address = (high << 8) | low;
-                self.a = memory.read(address);
+                self.a = bus.read(address);
                 info!("LD A,({:04x}), A={:02x}", address, self.a);
                 self.pc += 3;
                 16
@@ -295,15 +373,15 @@ impl Cpu {
                 4
             }
             0xc9 => { // RET
-                let low = memory.read(self.sp) as u16;
-                let high = memory.read(self.sp + 1) as u16;
+                let low = bus.read(self.sp) as u16;
+                let high = bus.read(self.sp + 1) as u16;
                 self.pc = (high << 8) | low;
                 self.sp = self.sp.wrapping_add(2);
                 info!("RET to {:04x}", self.pc);
                 16
             }
             _ => {
-                log::error!("Unknown opcode: {:02x}", opcode);
+                error!("Unknown opcode: {:02x}", opcode);
                 self.pc += 1;
                 4
             }
@@ -317,8 +395,77 @@ impl Cpu {
         }
         
         self.total_cycles += cycles as u64;
-        memory.step_ppu(cycles);
-        info!("Total cycles: {}, LCD line: {}", self.total_cycles, memory.read(0xFF44));
+        bus.tick(cycles);
+        info!("Total cycles: {}", self.total_cycles);
         cycles
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(feature = "alloc")]
+impl SaveState for Cpu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.pc);
+        w.u16(self.sp);
+        for r in [self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l] {
+            w.u8(r);
+        }
+        w.u64(self.total_cycles);
+        w.bool(self.ime);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.pc = r.u16()?;
+        self.sp = r.u16()?;
+        self.a = r.u8()?;
+        self.f = r.u8()?;
+        self.b = r.u8()?;
+        self.c = r.u8()?;
+        self.d = r.u8()?;
+        self.e = r.u8()?;
+        self.h = r.u8()?;
+        self.l = r.u8()?;
+        self.total_cycles = r.u64()?;
+        self.ime = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::FlatBus;
+
+    #[test]
+    fn runs_on_a_flat_bus() {
+        // LDH (0x80),A at the RST 38 vector, which only the Game Boy
+        // memory map treats specially
+        let mut bus = FlatBus::new();
+        bus.ram[0x38..0x3A].copy_from_slice(&[0xE0, 0x80]);
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x38;
+        cpu.a = 0x5A;
+
+        assert_eq!(cpu.step(&mut bus), 12);
+        assert_eq!(cpu.pc, 0x3A);
+        assert_eq!(bus.ram[0xFF80], 0x5A);
+        assert_eq!(bus.cycles, 12);
+        assert_eq!(cpu.total_cycles, 12);
+        assert_eq!(bus.ram[0xFF40], 0);
+    }
+
+    #[test]
+    fn returns_from_calls() {
+        // 0100: CALL 0200, 0200: RET
+        let mut bus = FlatBus::new();
+        bus.ram[0x100..0x103].copy_from_slice(&[0xCD, 0x00, 0x02]);
+        bus.ram[0x200] = 0xC9;
+        let mut cpu = Cpu::new();
+        (cpu.pc, cpu.sp) = (0x100, 0xFFFE);
+
+        cpu.step(&mut bus);
+        assert_eq!((cpu.pc, cpu.sp), (0x200, 0xFFFC));
+        assert_eq!(bus.ram[0xFFFC..0xFFFE], [0x03, 0x01]);
+        cpu.step(&mut bus);
+        assert_eq!((cpu.pc, cpu.sp), (0x103, 0xFFFE));
+    }
+}
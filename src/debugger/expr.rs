@@ -0,0 +1,336 @@
+// Condition expressions for breakpoints and watchpoints
+//
+// C-like syntax over integers, e.g. `A == 0x3F && [HL] > 10`:
+//   numbers      decimal, or hex with a 0x / $ prefix
+//   registers    A B C D E F H L AF BC DE HL SP PC
+//   flags        ZF NF HF CF (0 or 1)
+//   [expr]       byte in memory at expr
+//   hits         times this breakpoint or watchpoint has been reached
+//   value, addr  byte and address of the access that hit a watchpoint
+//   operators    || && == != < <= > >= | ^ & << >> + - * / % and unary ! ~ -
+
+use crate::gameboy::GameBoy;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinOp {
+    Or, And, Eq, Ne, Lt, Le, Gt, Ge, BitOr, BitXor, BitAnd, Shl, Shr, Add, Sub, Mul, Div, Rem,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnOp {
+    Not, Complement, Neg,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Num(i64),
+    Var(String),
+    Deref(Box<Node>),
+    Unary(UnOp, Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+}
+
+// A parsed condition, keeping its source text for listings
+#[derive(Clone, Debug)]
+pub struct Expr {
+    pub source: String,
+    root: Node,
+}
+
+// Values an expression can see besides the console itself
+pub struct Context<'a> {
+    pub gameboy: &'a GameBoy,
+    pub hits: u32,
+    pub access: Option<(u16, u8)>, // (addr, value) of a watchpoint hit
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "~", "(",
+    ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        if c.is_ascii_digit() || c == '$' {
+            let (digits, radix, skip) = if let Some(hex) = rest.strip_prefix("0x").or_else(|| rest.strip_prefix("0X")) {
+                (hex, 16, 2)
+            } else if let Some(hex) = rest.strip_prefix('$') {
+                (hex, 16, 1)
+            } else {
+                (rest, 10, 0)
+            };
+            let len = digits.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(digits.len());
+            let value = i64::from_str_radix(&digits[..len], radix).map_err(|_| format!("bad number '{}'", &rest[..skip + len]))?;
+            tokens.push(Token::Num(value));
+            rest = &digits[len..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_ascii_uppercase()));
+            rest = &rest[len..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("unexpected '{}' in condition", c));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+// Binary operators from loosest to tightest binding
+const PRECEDENCE: [&[(&str, BinOp)]; 10] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("|", BinOp::BitOr)],
+    &[("^", BinOp::BitXor)],
+    &[("&", BinOp::BitAnd)],
+    &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+    &[("<", BinOp::Lt), ("<=", BinOp::Le), (">", BinOp::Gt), (">=", BinOp::Ge)],
+    &[("<<", BinOp::Shl), (">>", BinOp::Shr)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+];
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected '{}' in condition", op))
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(&(_, op)) = self.peek_op().and_then(|t| PRECEDENCE[level].iter().find(|(s, _)| *s == t)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let op = match self.peek_op() {
+            Some("!") => UnOp::Not,
+            Some("~") => UnOp::Complement,
+            Some("-") => UnOp::Neg,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("condition ends unexpectedly")?;
+        self.pos += 1;
+        match token {
+            Token::Num(n) => Ok(Node::Num(n)),
+            Token::Ident(name) => {
+                if !VARIABLES.contains(&name.as_str()) {
+                    return Err(format!("unknown name '{}' in condition", name));
+                }
+                Ok(Node::Var(name))
+            }
+            Token::Op("(") => {
+                let inner = self.binary(0)?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Op("[") => {
+                let inner = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Deref(Box::new(inner)))
+            }
+            Token::Op(op) => Err(format!("unexpected '{}' in condition", op)),
+        }
+    }
+}
+
+const VARIABLES: [&str; 21] = [
+    "A", "B", "C", "D", "E", "F", "H", "L", "AF", "BC", "DE", "HL", "SP", "PC", "ZF", "NF", "HF", "CF", "HITS", "VALUE",
+    "ADDR",
+];
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+        let root = parser.binary(0)?;
+        if parser.pos != parser.tokens.len() {
+            return Err("trailing input in condition".to_string());
+        }
+        Ok(Expr {
+            source: text.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn is_true(&self, ctx: &Context) -> bool {
+        eval(&self.root, ctx) != 0
+    }
+}
+
+fn eval(node: &Node, ctx: &Context) -> i64 {
+    let cpu = &ctx.gameboy.cpu;
+    let pair = |hi: u8, lo: u8| ((hi as i64) << 8) | lo as i64;
+
+    match node {
+        Node::Num(n) => *n,
+        Node::Var(name) => match name.as_str() {
+            "A" => cpu.a as i64,
+            "B" => cpu.b as i64,
+            "C" => cpu.c as i64,
+            "D" => cpu.d as i64,
+            "E" => cpu.e as i64,
+            "F" => cpu.f as i64,
+            "H" => cpu.h as i64,
+            "L" => cpu.l as i64,
+            "AF" => pair(cpu.a, cpu.f),
+            "BC" => pair(cpu.b, cpu.c),
+            "DE" => pair(cpu.d, cpu.e),
+            "HL" => pair(cpu.h, cpu.l),
+            "SP" => cpu.sp as i64,
+            "PC" => cpu.pc as i64,
+            "ZF" => (cpu.f >> 7 & 1) as i64,
+            "NF" => (cpu.f >> 6 & 1) as i64,
+            "HF" => (cpu.f >> 5 & 1) as i64,
+            "CF" => (cpu.f >> 4 & 1) as i64,
+            "HITS" => ctx.hits as i64,
+            "VALUE" => ctx.access.map_or(0, |(_, v)| v as i64),
+            "ADDR" => ctx.access.map_or(0, |(a, _)| a as i64),
+            _ => unreachable!("names are checked while parsing"),
+        },
+        Node::Deref(addr) => ctx.gameboy.memory.peek(eval(addr, ctx) as u16) as i64,
+        Node::Unary(op, inner) => {
+            let v = eval(inner, ctx);
+            match op {
+                UnOp::Not => (v == 0) as i64,
+                UnOp::Complement => !v,
+                UnOp::Neg => v.wrapping_neg(),
+            }
+        }
+        Node::Binary(op, lhs, rhs) => {
+            let l = eval(lhs, ctx);
+            // Short-circuit so `[HL] > 0 && ...` style guards behave as expected
+            match op {
+                BinOp::Or if l != 0 => return 1,
+                BinOp::And if l == 0 => return 0,
+                _ => {}
+            }
+            let r = eval(rhs, ctx);
+            match op {
+                BinOp::Or | BinOp::And => (r != 0) as i64,
+                BinOp::Eq => (l == r) as i64,
+                BinOp::Ne => (l != r) as i64,
+                BinOp::Lt => (l < r) as i64,
+                BinOp::Le => (l <= r) as i64,
+                BinOp::Gt => (l > r) as i64,
+                BinOp::Ge => (l >= r) as i64,
+                BinOp::BitOr => l | r,
+                BinOp::BitXor => l ^ r,
+                BinOp::BitAnd => l & r,
+                BinOp::Shl => l.wrapping_shl(r as u32),
+                BinOp::Shr => l.wrapping_shr(r as u32),
+                BinOp::Add => l.wrapping_add(r),
+                BinOp::Sub => l.wrapping_sub(r),
+                BinOp::Mul => l.wrapping_mul(r),
+                BinOp::Div => l.checked_div(r).unwrap_or(0),
+                BinOp::Rem => l.checked_rem(r).unwrap_or(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_with(text: &str, gameboy: &GameBoy, access: Option<(u16, u8)>) -> i64 {
+        let expr = Expr::parse(text).unwrap();
+        eval(&expr.root, &Context { gameboy, hits: 2, access })
+    }
+
+    fn eval_str(text: &str) -> i64 {
+        eval_with(text, &GameBoy::new(&[0; 0x8000]), None)
+    }
+
+    #[test]
+    fn follows_c_precedence() {
+        assert_eq!(eval_str("1 + 2 * 3"), 7);
+        assert_eq!(eval_str("(1 + 2) * 3"), 9);
+        assert_eq!(eval_str("1 << 2 + 1"), 8);
+        assert_eq!(eval_str("1 | 2 ^ 3 & 1"), 3);
+        // Comparisons bind tighter than the bitwise operators, as in C
+        assert_eq!(eval_str("2 & 3 == 3"), 0);
+        assert_eq!(eval_str("1 < 2 == 1"), 1);
+        assert_eq!(eval_str("0 || 1 && 0"), 0);
+        assert_eq!(eval_str("10 - 4 - 3"), 3);
+        assert_eq!(eval_str("-3 + ~0 + !5"), -4);
+        assert_eq!(eval_str("$10 + 0x10 + 16"), 48);
+        assert_eq!(eval_str("7 / 0 + 7 % 0"), 0);
+    }
+
+    #[test]
+    fn reads_registers_and_memory() {
+        let mut gameboy = GameBoy::new(&[0; 0x8000]);
+        gameboy.cpu.a = 0x3F;
+        gameboy.cpu.f = 0x90;
+        (gameboy.cpu.h, gameboy.cpu.l) = (0xC0, 0x00);
+        gameboy.memory.write(0xC000, 12);
+        gameboy.memory.write(0xC001, 0x34);
+
+        assert_eq!(eval_with("A == 0x3F && [HL] > 10", &gameboy, None), 1);
+        assert_eq!(eval_with("AF", &gameboy, None), 0x3F90);
+        assert_eq!(eval_with("hl == $C000", &gameboy, None), 1);
+        assert_eq!(eval_with("[HL + 1]", &gameboy, None), 0x34);
+        assert_eq!(eval_with("[[HL] + 0xC000 - 11]", &gameboy, None), 0x34);
+        assert_eq!(eval_with("ZF * 8 + NF * 4 + HF * 2 + CF", &gameboy, None), 9);
+        assert_eq!(eval_with("hits", &gameboy, None), 2);
+        assert_eq!(eval_with("value + addr", &gameboy, None), 0);
+        assert_eq!(eval_with("addr == 0xC000 && value == 5", &gameboy, Some((0xC000, 5))), 1);
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let error = |text| Expr::parse(text).unwrap_err();
+        assert_eq!(error("A =="), "condition ends unexpectedly");
+        assert_eq!(error("Q > 1"), "unknown name 'Q' in condition");
+        assert_eq!(error("(1 + 2"), "expected ')' in condition");
+        assert_eq!(error("[HL"), "expected ']' in condition");
+        assert_eq!(error("1 2"), "trailing input in condition");
+        assert_eq!(error("0xZZ"), "bad number '0xZZ'");
+        assert_eq!(error("A @ 1"), "unexpected '@' in condition");
+        assert_eq!(error("* 2"), "unexpected '*' in condition");
+    }
+}
@@ -0,0 +1,889 @@
+// Interactive command-line debugger
+//
+// The debugger drives the console one instruction at a time while it is
+// attached. `run_frame` returns after a frame's worth of cycles so the
+// frontend can keep presenting the screen; whenever execution stops
+// (breakpoint, finished step, ...) it drops into a prompt on stdin first.
+
+mod expr;
+
+use std::io::{self, BufRead, Write};
+
+use crate::cheats::{Cheat, RamSearch, SearchFilter};
+use crate::disasm::{self, Instruction, SymbolTable};
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::watch::{Access, WatchHit, WatchRange};
+
+pub use expr::{Context, Expr};
+
+const INTERRUPT_VECTORS: [u16; 5] = [0x40, 0x48, 0x50, 0x58, 0x60];
+
+const HELP: &str = "\
+Commands (numbers are hex, optionally prefixed with $ or 0x):
+  b ADDR | b BANK:ADDR | b LABEL [if COND]
+                         set a breakpoint (BANK only matters for 0x4000-0x7FFF)
+  bl                     list breakpoints with hit counts
+  d N                    delete breakpoint N
+  watch r|w|rw|x START[-END] [if COND]
+                         stop on reads, writes or execution in a range
+  wl                     list watchpoints with hit counts
+  wd N                   delete watchpoint N
+  s [COUNT]              step COUNT instructions (default 1)
+  n                      step over CALL/RST
+  finish                 run until the current function returns
+  c                      continue until a breakpoint
+  vblank                 run until the next VBlank starts
+  line N                 run until scanline N (decimal) starts
+  r                      dump registers and flags
+  x ADDR [LEN]           hexdump LEN bytes (default 0x40)
+  w ADDR BYTE...         write bytes to memory
+  dis [ADDR] [COUNT]     disassemble COUNT instructions (default: around PC)
+  bt                     show the call stack
+  sym FILE               load an RGBDS or no$gmb symbol file
+  trace FILE | trace off start or stop an instruction trace
+  cheat CODE [NAME]      add a GameShark (01VVLLHH) or Game Genie (ABC-DEF-GHI) code
+  cheat list             list cheats
+  cheat on|off [N]       enable or disable cheat N, or all cheats
+  cheat del N            delete cheat N
+  cheat save FILE        write the cheats as a .cht file
+  search                 start a RAM search over WRAM and HRAM
+  search FILTER          keep candidates that are changed, unchanged, inc, dec
+                         or =VALUE compared with the last search
+  search list            show the remaining candidates
+  q                      quit the emulator
+An empty line repeats the previous command.
+Conditions use C syntax over registers (A..L, AF..HL, SP, PC), flags (ZF NF HF
+CF), memory ([expr]), hits, and for watchpoints the accessed addr and value:
+  b 0150 if A == 0x3F && [HL] > 10
+  watch w C000-C0FF if value == 0 && hits > 3";
+
+#[derive(Clone)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub bank: Option<u16>,
+    pub condition: Option<Expr>,
+    pub hits: u32,
+}
+
+#[derive(Clone)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16, // Inclusive
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub condition: Option<Expr>,
+    pub hits: u32,
+}
+
+impl Watchpoint {
+    fn covers(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Call,
+    Interrupt,
+}
+
+// One entry of the tracked call stack
+#[derive(Clone, Copy)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub from: u16,   // Address of the CALL/RST, or the PC the interrupt preempted
+    pub target: u16, // Address execution jumped to
+    pub sp: u16,     // SP after the return address was pushed
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Paused,
+    Continue,
+    Step(u32),
+    Next { return_pc: u16, depth: usize },
+    Finish { depth: usize },
+    UntilVBlank,
+    UntilLine(u8),
+}
+
+// What the REPL wants the emulator to do after a command
+enum Control {
+    Prompt,
+    Resume,
+    Quit,
+}
+
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub call_stack: Vec<Frame>,
+    pub symbols: SymbolTable,
+    pub search: Option<RamSearch>,
+    mode: RunMode,
+    last_command: String,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    // Starts paused so breakpoints can be set before the first instruction
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            call_stack: Vec::new(),
+            symbols: SymbolTable::new(),
+            search: None,
+            mode: RunMode::Paused,
+            last_command: String::new(),
+        }
+    }
+
+    // Runs up to one frame. Returns false when the user asked to quit.
+    pub fn run_frame(&mut self, gameboy: &mut GameBoy) -> bool {
+        let mut frame_cycles = 0;
+        while frame_cycles < CYCLES_PER_FRAME {
+            if self.mode == RunMode::Paused && !self.prompt(gameboy) {
+                return false;
+            }
+            frame_cycles += self.step(gameboy) as u32;
+        }
+        true
+    }
+
+    // Executes one instruction, keeps the call stack up to date and
+    // pauses if a stop condition was reached
+    fn step(&mut self, gameboy: &mut GameBoy) -> u8 {
+        let pc = gameboy.cpu.pc;
+        let sp = gameboy.cpu.sp;
+        let line = gameboy.memory.ppu.line;
+        let instr = self.decode(gameboy, pc);
+
+        gameboy.memory.watchpoints.take_hits(); // Drop accesses made from the prompt
+        let cycles = gameboy.step();
+        self.track_call_stack(gameboy, &instr, sp);
+
+        if self.check_watchpoints(gameboy, &instr) {
+            return cycles;
+        }
+
+        let new_pc = gameboy.cpu.pc;
+        let new_line = gameboy.memory.ppu.line;
+        let stop = match self.mode {
+            RunMode::Paused | RunMode::Continue => false,
+            RunMode::Step(n) => {
+                self.mode = RunMode::Step(n - 1);
+                n <= 1
+            }
+            RunMode::Next { return_pc, depth } => new_pc == return_pc && self.call_stack.len() <= depth,
+            RunMode::Finish { depth } => self.call_stack.len() < depth,
+            RunMode::UntilVBlank => line != 144 && new_line == 144,
+            RunMode::UntilLine(target) => line != target && new_line == target,
+        };
+
+        if stop {
+            self.pause(gameboy);
+        } else if let Some(i) = self.breakpoint_at(gameboy, new_pc) {
+            println!("Breakpoint {} hit ({} times)", i, self.breakpoints[i].hits);
+            self.pause(gameboy);
+        }
+        cycles
+    }
+
+    // Judges the bus accesses made by `instr` and the new PC against the
+    // watchpoints. Returns true if execution was paused.
+    fn check_watchpoints(&mut self, gameboy: &GameBoy, instr: &Instruction) -> bool {
+        let hits = gameboy.memory.watchpoints.take_hits();
+        if self.watchpoints.is_empty() {
+            return false;
+        }
+
+        // Opcode and operand fetches are not data reads
+        let fetched = instr.addr..instr.addr.wrapping_add(instr.length as u16);
+        let mut events: Vec<(&str, u16, Option<&WatchHit>)> = hits
+            .iter()
+            .filter(|hit| hit.access == Access::Write || !fetched.contains(&hit.addr))
+            .map(|hit| {
+                let kind = if hit.access == Access::Read { "read" } else { "write" };
+                (kind, hit.addr, Some(hit))
+            })
+            .collect();
+        events.push(("execute", gameboy.cpu.pc, None));
+
+        let mut stopped = None;
+        for (i, wp) in self.watchpoints.iter_mut().enumerate() {
+            for &(kind, addr, value) in &events {
+                let wanted = match kind {
+                    "read" => wp.read,
+                    "write" => wp.write,
+                    _ => wp.execute,
+                };
+                if !wanted || !wp.covers(addr) {
+                    continue;
+                }
+
+                wp.hits += 1;
+                let ctx = Context {
+                    gameboy,
+                    hits: wp.hits,
+                    access: value.map(|hit| (addr, hit.value)),
+                };
+                if wp.condition.as_ref().is_none_or(|c| c.is_true(&ctx)) && stopped.is_none() {
+                    match value {
+                        Some(hit) if kind == "write" => println!(
+                            "Watchpoint {}: write {:04X} = {:02X} (was {:02X}) by instruction at {:04X}",
+                            i, addr, hit.value, hit.old, instr.addr
+                        ),
+                        Some(hit) => println!(
+                            "Watchpoint {}: {} {:04X} = {:02X} by instruction at {:04X}",
+                            i, kind, addr, hit.value, instr.addr
+                        ),
+                        None => println!("Watchpoint {}: execute {:04X}", i, addr),
+                    }
+                    stopped = Some(i);
+                }
+            }
+        }
+
+        if stopped.is_some() {
+            self.pause(gameboy);
+        }
+        stopped.is_some()
+    }
+
+    // Pushes the read/write ranges down to the bus
+    fn sync_watchpoints(&self, gameboy: &mut GameBoy) {
+        let ranges = self
+            .watchpoints
+            .iter()
+            .filter(|wp| wp.read || wp.write)
+            .map(|wp| WatchRange {
+                start: wp.start,
+                end: wp.end,
+                read: wp.read,
+                write: wp.write,
+            })
+            .collect();
+        gameboy.memory.watchpoints.set_ranges(ranges);
+    }
+
+    fn track_call_stack(&mut self, gameboy: &GameBoy, instr: &Instruction, old_sp: u16) {
+        let cpu = &gameboy.cpu;
+        let pushed = cpu.sp == old_sp.wrapping_sub(2);
+        let sequential = cpu.pc == instr.addr.wrapping_add(instr.length as u16);
+
+        if instr.is_call() && pushed && !sequential {
+            self.call_stack.push(Frame {
+                kind: FrameKind::Call,
+                from: instr.addr,
+                target: cpu.pc,
+                sp: cpu.sp,
+            });
+        } else if pushed && INTERRUPT_VECTORS.contains(&cpu.pc) && instr.mnemonic != "PUSH" {
+            self.call_stack.push(Frame {
+                kind: FrameKind::Interrupt,
+                from: instr.addr,
+                target: cpu.pc,
+                sp: cpu.sp,
+            });
+        } else if instr.is_return() && cpu.sp == old_sp.wrapping_add(2) {
+            // Drop everything the return unwound, including frames left by stack tricks
+            while let Some(frame) = self.call_stack.last() {
+                if frame.sp >= cpu.sp {
+                    break;
+                }
+                self.call_stack.pop();
+            }
+        }
+    }
+
+    // Counts a hit on every breakpoint at `pc` and returns the first whose condition holds
+    fn breakpoint_at(&mut self, gameboy: &GameBoy, pc: u16) -> Option<usize> {
+        let bank = gameboy.memory.rom_bank_at(pc);
+        let mut stop = None;
+        for (i, bp) in self.breakpoints.iter_mut().enumerate() {
+            let bank_matches = !(0x4000..0x8000).contains(&pc) || bp.bank.is_none_or(|b| b == bank);
+            if bp.addr != pc || !bank_matches {
+                continue;
+            }
+
+            bp.hits += 1;
+            let ctx = Context {
+                gameboy,
+                hits: bp.hits,
+                access: None,
+            };
+            if stop.is_none() && bp.condition.as_ref().is_none_or(|c| c.is_true(&ctx)) {
+                stop = Some(i);
+            }
+        }
+        stop
+    }
+
+    fn pause(&mut self, gameboy: &GameBoy) {
+        self.mode = RunMode::Paused;
+        self.print_current(gameboy);
+    }
+
+    fn decode(&self, gameboy: &GameBoy, addr: u16) -> Instruction {
+        disasm::decode(|a| gameboy.memory.peek(a), addr)
+    }
+
+    fn print_current(&self, gameboy: &GameBoy) {
+        let pc = gameboy.cpu.pc;
+        let bank = gameboy.memory.rom_bank_at(pc);
+        if let Some(label) = self.symbols.lookup(pc, bank) {
+            println!("{}:", label);
+        }
+        println!("{:02X}:{:04X}  {}", bank, pc, self.decode(gameboy, pc).format(&self.symbols, bank));
+    }
+
+    // Accepts a number, BANK:ADDR, or a label from the loaded symbol file
+    fn parse_location(&self, text: &str) -> Result<(Option<u16>, u16), String> {
+        if let Some((bank, addr)) = self.symbols.find(text) {
+            let bank = (0x4000..0x8000).contains(&addr).then_some(bank);
+            return Ok((bank, addr));
+        }
+        match text.split_once(':') {
+            Some((bank, addr)) => Ok((Some(parse_u16(bank)?), parse_u16(addr)?)),
+            None => Ok((None, parse_u16(text)?)),
+        }
+    }
+
+    // Reads commands until one resumes execution. Returns false on quit.
+    fn prompt(&mut self, gameboy: &mut GameBoy) -> bool {
+        let stdin = io::stdin();
+        loop {
+            print!("(gbdb) ");
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return false, // stdin closed
+                Ok(_) => {}
+            }
+
+            let line = line.trim();
+            let command = if line.is_empty() { self.last_command.clone() } else { line.to_string() };
+            self.last_command = command.clone();
+
+            match self.execute(gameboy, &command) {
+                Ok(Control::Prompt) => {}
+                Ok(Control::Resume) => return true,
+                Ok(Control::Quit) => return false,
+                Err(e) => println!("error: {}", e),
+            }
+        }
+    }
+
+    fn execute(&mut self, gameboy: &mut GameBoy, command: &str) -> Result<Control, String> {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(Control::Prompt);
+        };
+        let args: Vec<&str> = words.collect();
+
+        match name {
+            "help" | "h" | "?" => println!("{}", HELP),
+            "b" | "break" => {
+                let spec = args.first().ok_or("usage: b ADDR | b BANK:ADDR | b LABEL [if COND]")?;
+                let (bank, addr) = self.parse_location(spec)?;
+                let bp = Breakpoint {
+                    addr,
+                    bank,
+                    condition: parse_condition(&args[1..])?,
+                    hits: 0,
+                };
+                println!("Breakpoint {} at {}", self.breakpoints.len(), format_breakpoint(&bp));
+                self.breakpoints.push(bp);
+            }
+            "bl" => {
+                for (i, bp) in self.breakpoints.iter().enumerate() {
+                    println!("{:3}  {}  hits={}", i, format_breakpoint(bp), bp.hits);
+                }
+            }
+            "d" | "delete" => {
+                let i = parse_index(args.first(), self.breakpoints.len(), "breakpoint")?;
+                self.breakpoints.remove(i);
+            }
+            "watch" => {
+                let usage = "usage: watch r|w|rw|x START[-END] [if COND]";
+                let kind = *args.first().ok_or(usage)?;
+                let (read, write, execute) = match kind {
+                    "r" => (true, false, false),
+                    "w" => (false, true, false),
+                    "rw" | "wr" => (true, true, false),
+                    "x" => (false, false, true),
+                    _ => return Err(usage.to_string()),
+                };
+                let range = args.get(1).ok_or(usage)?;
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (parse_u16(start)?, parse_u16(end)?),
+                    None => (parse_u16(range)?, parse_u16(range)?),
+                };
+                if end < start {
+                    return Err("range end is before its start".to_string());
+                }
+                let wp = Watchpoint {
+                    start,
+                    end,
+                    read,
+                    write,
+                    execute,
+                    condition: parse_condition(&args[2..])?,
+                    hits: 0,
+                };
+                println!("Watchpoint {} on {}", self.watchpoints.len(), format_watchpoint(&wp));
+                self.watchpoints.push(wp);
+                self.sync_watchpoints(gameboy);
+            }
+            "wl" => {
+                for (i, wp) in self.watchpoints.iter().enumerate() {
+                    println!("{:3}  {}  hits={}", i, format_watchpoint(wp), wp.hits);
+                }
+            }
+            "wd" => {
+                let i = parse_index(args.first(), self.watchpoints.len(), "watchpoint")?;
+                self.watchpoints.remove(i);
+                self.sync_watchpoints(gameboy);
+            }
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(n) => n.parse().map_err(|_| "bad step count")?,
+                    None => 1,
+                };
+                self.mode = RunMode::Step(count.max(1));
+                return Ok(Control::Resume);
+            }
+            "n" | "next" => {
+                let instr = self.decode(gameboy, gameboy.cpu.pc);
+                self.mode = if instr.is_call() {
+                    RunMode::Next {
+                        return_pc: instr.addr.wrapping_add(instr.length as u16),
+                        depth: self.call_stack.len(),
+                    }
+                } else {
+                    RunMode::Step(1)
+                };
+                return Ok(Control::Resume);
+            }
+            "finish" => {
+                if self.call_stack.is_empty() {
+                    return Err("not inside a tracked call".to_string());
+                }
+                self.mode = RunMode::Finish { depth: self.call_stack.len() };
+                return Ok(Control::Resume);
+            }
+            "c" | "continue" => {
+                self.mode = RunMode::Continue;
+                return Ok(Control::Resume);
+            }
+            "vblank" => {
+                self.mode = RunMode::UntilVBlank;
+                return Ok(Control::Resume);
+            }
+            "line" => {
+                let line: u8 = args.first().ok_or("usage: line N")?.parse().map_err(|_| "bad scanline")?;
+                if line > 153 {
+                    return Err("scanline must be 0-153".to_string());
+                }
+                self.mode = RunMode::UntilLine(line);
+                return Ok(Control::Resume);
+            }
+            "r" | "regs" => print_registers(gameboy),
+            "x" => {
+                let addr = parse_u16(args.first().ok_or("usage: x ADDR [LEN]")?)?;
+                let len = match args.get(1) {
+                    Some(len) => parse_u16(len)?,
+                    None => 0x40,
+                };
+                hexdump(gameboy, addr, len);
+            }
+            "w" => {
+                let addr = parse_u16(args.first().ok_or("usage: w ADDR BYTE...")?)?;
+                for (i, byte) in args[1..].iter().enumerate() {
+                    let value = parse_u16(byte)?;
+                    if value > 0xFF {
+                        return Err(format!("{} is not a byte", byte));
+                    }
+                    gameboy.memory.write(addr.wrapping_add(i as u16), value as u8);
+                }
+            }
+            "dis" => {
+                let (start, count) = match args.first() {
+                    Some(addr) => (self.parse_location(addr)?.1, 10),
+                    None => (self.context_start(gameboy), 12),
+                };
+                let count = match args.get(1) {
+                    Some(n) => n.parse().map_err(|_| "bad instruction count")?,
+                    None => count,
+                };
+                self.print_disassembly(gameboy, start, count);
+            }
+            "bt" => self.print_call_stack(gameboy),
+            "trace" => match args.first() {
+                Some(&"off") => match gameboy.stop_trace() {
+                    Some(lines) => println!("Trace stopped after {} instructions", lines),
+                    None => println!("Not tracing"),
+                },
+                Some(path) => {
+                    gameboy.start_trace(path).map_err(|e| format!("{}: {}", path, e))?;
+                    println!("Tracing to {}", path);
+                }
+                None => return Err("usage: trace FILE | trace off".to_string()),
+            },
+            "sym" => {
+                let path = args.first().ok_or("usage: sym FILE")?;
+                let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+                self.symbols = SymbolTable::parse(&text)?;
+                println!("Loaded {} symbols", self.symbols.len());
+            }
+            "cheat" => cheat_command(gameboy, &args)?,
+            "search" => self.search_command(gameboy, &args)?,
+            "q" | "quit" => return Ok(Control::Quit),
+            _ => return Err(format!("unknown command '{}', try 'help'", name)),
+        }
+        Ok(Control::Prompt)
+    }
+
+    fn search_command(&mut self, gameboy: &GameBoy, args: &[&str]) -> Result<(), String> {
+        let memory = &gameboy.memory;
+        match args.first() {
+            None => {
+                let search = RamSearch::start(memory);
+                println!("{} candidates", search.candidates().len());
+                self.search = Some(search);
+            }
+            Some(&"list") => {
+                let search = self.search.as_ref().ok_or("no search running, start one with 'search'")?;
+                const SHOWN: usize = 32;
+                for &(addr, value) in search.candidates().iter().take(SHOWN) {
+                    println!("{:04X}  {:02X} ({})", addr, value, value);
+                }
+                if search.candidates().len() > SHOWN {
+                    println!("... {} more", search.candidates().len() - SHOWN);
+                }
+            }
+            Some(_) => {
+                let filter = SearchFilter::parse(&args.join(" "))?;
+                let search = self.search.as_mut().ok_or("no search running, start one with 'search'")?;
+                println!("{} candidates", search.filter(memory, filter));
+            }
+        }
+        Ok(())
+    }
+
+    // Picks an address a few instructions before PC. Instructions are variable
+    // length, so this looks for a start that decodes cleanly into PC.
+    fn context_start(&self, gameboy: &GameBoy) -> u16 {
+        let pc = gameboy.cpu.pc;
+        for back in (1..=12u16).rev() {
+            let mut addr = pc.wrapping_sub(back);
+            let mut count = 0;
+            while count < 4 && addr != pc && pc.wrapping_sub(addr) <= back {
+                addr = addr.wrapping_add(self.decode(gameboy, addr).length as u16);
+                count += 1;
+            }
+            if addr == pc && count >= 3 {
+                return pc.wrapping_sub(back);
+            }
+        }
+        pc
+    }
+
+    fn print_disassembly(&self, gameboy: &GameBoy, start: u16, count: usize) {
+        let mut addr = start;
+        for _ in 0..count {
+            let instr = self.decode(gameboy, addr);
+            let bytes: Vec<String> = (0..instr.length as u16)
+                .map(|i| format!("{:02X}", gameboy.memory.peek(addr.wrapping_add(i))))
+                .collect();
+            let marker = if addr == gameboy.cpu.pc { "=>" } else { "  " };
+            let bp = if self.breakpoints.iter().any(|bp| bp.addr == addr) { "*" } else { " " };
+            let bank = gameboy.memory.rom_bank_at(addr);
+            if let Some(label) = self.symbols.lookup(addr, bank) {
+                println!("{}:", label);
+            }
+            println!(
+                "{}{} {:02X}:{:04X}  {:<9} {}",
+                marker,
+                bp,
+                bank,
+                addr,
+                bytes.join(" "),
+                instr.format(&self.symbols, bank)
+            );
+            addr = addr.wrapping_add(instr.length as u16);
+        }
+    }
+
+    fn print_call_stack(&self, gameboy: &GameBoy) {
+        println!("#0  {:04X}", gameboy.cpu.pc);
+        for (i, frame) in self.call_stack.iter().rev().enumerate() {
+            let kind = match frame.kind {
+                FrameKind::Call => "called from",
+                FrameKind::Interrupt => "interrupt at",
+            };
+            let bank = gameboy.memory.rom_bank_at(frame.target);
+            let name = self.symbols.lookup(frame.target, bank).map(str::to_string);
+            let target = name.unwrap_or_else(|| format!("{:04X}", frame.target));
+            println!("#{}  {}  {} {:04X}", i + 1, target, kind, frame.from);
+        }
+    }
+}
+
+fn format_breakpoint(bp: &Breakpoint) -> String {
+    let location = match bp.bank {
+        Some(bank) => format!("{:02X}:{:04X}", bank, bp.addr),
+        None => format!("{:04X}", bp.addr),
+    };
+    match &bp.condition {
+        Some(cond) => format!("{} if {}", location, cond.source),
+        None => location,
+    }
+}
+
+fn format_watchpoint(wp: &Watchpoint) -> String {
+    let kind = format!(
+        "{}{}{}",
+        if wp.read { "r" } else { "" },
+        if wp.write { "w" } else { "" },
+        if wp.execute { "x" } else { "" }
+    );
+    let range = if wp.start == wp.end {
+        format!("{:04X}", wp.start)
+    } else {
+        format!("{:04X}-{:04X}", wp.start, wp.end)
+    };
+    match &wp.condition {
+        Some(cond) => format!("{} {} if {}", kind, range, cond.source),
+        None => format!("{} {}", kind, range),
+    }
+}
+
+fn cheat_command(gameboy: &mut GameBoy, args: &[&str]) -> Result<(), String> {
+    let usage = "usage: cheat CODE [NAME] | cheat list | cheat on|off [N] | cheat del N | cheat save FILE";
+    let cheats = &mut gameboy.memory.cheats;
+    match *args.first().ok_or(usage)? {
+        "list" => {
+            if !cheats.is_active() {
+                println!("Cheats are off");
+            }
+            for (i, cheat) in cheats.list().iter().enumerate() {
+                let state = if cheat.enabled { "on " } else { "off" };
+                println!("{:3}  {}  {:<12} {:<32} {}", i, state, cheat.code, cheat.describe(), cheat.name);
+            }
+        }
+        toggle @ ("on" | "off") => {
+            let enabled = toggle == "on";
+            match args.get(1) {
+                Some(_) => {
+                    let i = parse_index(args.get(1), cheats.list().len(), "cheat")?;
+                    cheats.set_enabled(i, enabled);
+                }
+                None => cheats.set_active(enabled),
+            }
+        }
+        "del" => {
+            let i = parse_index(args.get(1), cheats.list().len(), "cheat")?;
+            cheats.remove(i);
+        }
+        "save" => {
+            let path = args.get(1).ok_or(usage)?;
+            cheats.save(path).map_err(|e| format!("{}: {}", path, e))?;
+            println!("Saved {} cheats to {}", cheats.list().len(), path);
+        }
+        code => {
+            let cheat = Cheat::parse(code, &args[1..].join(" "))?;
+            println!("Cheat {}: {}", cheats.list().len(), cheat.describe());
+            cheats.add(cheat);
+        }
+    }
+    Ok(())
+}
+
+// Parses an optional trailing `if COND`
+fn parse_condition(args: &[&str]) -> Result<Option<Expr>, String> {
+    match args.split_first() {
+        None => Ok(None),
+        Some((&"if", rest)) if !rest.is_empty() => Expr::parse(&rest.join(" ")).map(Some),
+        Some(_) => Err("expected 'if COND'".to_string()),
+    }
+}
+
+fn parse_index(arg: Option<&&str>, len: usize, what: &str) -> Result<usize, String> {
+    let i: usize = arg
+        .ok_or(format!("which {}?", what))?
+        .parse()
+        .map_err(|_| format!("bad {} number", what))?;
+    if i >= len {
+        return Err(format!("no {} {}", what, i));
+    }
+    Ok(i)
+}
+
+fn print_registers(gameboy: &GameBoy) {
+    let cpu = &gameboy.cpu;
+    let flag = |bit: u8, name: char| if cpu.f & bit != 0 { name } else { '-' };
+    println!(
+        "A={:02X} F={:02X} [{}{}{}{}]  BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X}  SP={:04X} PC={:04X}  IME={}",
+        cpu.a,
+        cpu.f,
+        flag(0x80, 'Z'),
+        flag(0x40, 'N'),
+        flag(0x20, 'H'),
+        flag(0x10, 'C'),
+        cpu.b,
+        cpu.c,
+        cpu.d,
+        cpu.e,
+        cpu.h,
+        cpu.l,
+        cpu.sp,
+        cpu.pc,
+        cpu.ime as u8
+    );
+    let memory = &gameboy.memory;
+    println!(
+        "IE={:02X} IF={:02X}  LY={} LCDC={:02X} STAT={:02X}  cycles={}",
+        memory.ie, memory.if_, memory.ppu.line, memory.ppu.lcdc, memory.ppu.stat, cpu.total_cycles
+    );
+}
+
+fn hexdump(gameboy: &GameBoy, start: u16, len: u16) {
+    let mut offset = 0u16;
+    while offset < len {
+        let addr = start.wrapping_add(offset);
+        let row: Vec<u8> = (0..16u16.min(len - offset))
+            .map(|i| gameboy.memory.peek(addr.wrapping_add(i)))
+            .collect();
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = row
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        println!("{:04X}  {:<47}  {}", addr, hex.join(" "), ascii);
+        offset = offset.saturating_add(16);
+    }
+}
+
+pub fn parse_u16(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad number '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0100: CALL 0200; NOP; JR -3 (back to the NOP)
+    // 0200: NOP; NOP; RET
+    fn console() -> GameBoy {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x106].copy_from_slice(&[0xCD, 0x00, 0x02, 0x00, 0x18, 0xFD]);
+        rom[0x200..0x203].copy_from_slice(&[0x00, 0x00, 0xC9]);
+        GameBoy::new(&rom)
+    }
+
+    fn command(debugger: &mut Debugger, gameboy: &mut GameBoy, command: &str) {
+        assert!(matches!(debugger.execute(gameboy, command), Ok(Control::Prompt)), "{}", command);
+    }
+
+    // Runs a resuming command until the debugger pauses again
+    fn resume(debugger: &mut Debugger, gameboy: &mut GameBoy, command: &str) -> u16 {
+        assert!(matches!(debugger.execute(gameboy, command), Ok(Control::Resume)), "{}", command);
+        for _ in 0..10_000 {
+            debugger.step(gameboy);
+            if debugger.mode == RunMode::Paused {
+                return gameboy.cpu.pc;
+            }
+        }
+        panic!("'{}' never stopped", command);
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let (mut debugger, mut gameboy) = (Debugger::new(), console());
+        command(&mut debugger, &mut gameboy, "b 0103");
+        command(&mut debugger, &mut gameboy, "b 0104 if hits == 2");
+
+        assert_eq!(resume(&mut debugger, &mut gameboy, "c"), 0x103);
+        assert_eq!(resume(&mut debugger, &mut gameboy, "c"), 0x103);
+        assert_eq!(resume(&mut debugger, &mut gameboy, "c"), 0x104);
+        assert_eq!(debugger.breakpoints[0].hits, 2);
+        assert_eq!(debugger.breakpoints[1].hits, 2);
+
+        command(&mut debugger, &mut gameboy, "d 1");
+        assert_eq!(resume(&mut debugger, &mut gameboy, "c"), 0x103);
+        assert_eq!((debugger.breakpoints.len(), debugger.breakpoints[0].hits), (1, 3));
+    }
+
+    #[test]
+    fn steps_into_and_out_of_calls() {
+        let (mut debugger, mut gameboy) = (Debugger::new(), console());
+        assert_eq!(resume(&mut debugger, &mut gameboy, "s"), 0x200);
+        assert_eq!(debugger.call_stack.len(), 1);
+        assert_eq!((debugger.call_stack[0].from, debugger.call_stack[0].target), (0x100, 0x200));
+
+        assert_eq!(resume(&mut debugger, &mut gameboy, "s 2"), 0x202);
+        assert_eq!(resume(&mut debugger, &mut gameboy, "finish"), 0x103);
+        assert!(debugger.call_stack.is_empty());
+        assert!(debugger.execute(&mut gameboy, "finish").is_err());
+    }
+
+    #[test]
+    fn steps_over_calls() {
+        let (mut debugger, mut gameboy) = (Debugger::new(), console());
+        assert_eq!(resume(&mut debugger, &mut gameboy, "n"), 0x103);
+        assert!(debugger.call_stack.is_empty());
+        assert_eq!(resume(&mut debugger, &mut gameboy, "n"), 0x104);
+        assert_eq!(resume(&mut debugger, &mut gameboy, "n"), 0x103);
+    }
+
+    #[test]
+    fn conditions_filter_watchpoint_hits() {
+        // 0100: LD A,1; LD (C000),A; LD A,5; LD (C000),A; JR -2
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x10C].copy_from_slice(&[0x3E, 0x01, 0xEA, 0x00, 0xC0, 0x3E, 0x05, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+        let mut gameboy = GameBoy::new(&rom);
+        let mut debugger = Debugger::new();
+        command(&mut debugger, &mut gameboy, "watch w C000 if value == 5");
+
+        // The first write counts as a hit but does not stop
+        assert_eq!(resume(&mut debugger, &mut gameboy, "c"), 0x10A);
+        assert_eq!(debugger.watchpoints[0].hits, 2);
+        assert_eq!(gameboy.memory.peek(0xC000), 5);
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        let (mut debugger, mut gameboy) = (Debugger::new(), console());
+        let mut error = |command| match debugger.execute(&mut gameboy, command) {
+            Err(e) => e,
+            Ok(_) => panic!("'{}' was accepted", command),
+        };
+        assert_eq!(error("frobnicate"), "unknown command 'frobnicate', try 'help'");
+        assert_eq!(error("b"), "usage: b ADDR | b BANK:ADDR | b LABEL [if COND]");
+        assert_eq!(error("b zz"), "bad number 'zz'");
+        assert_eq!(error("b 0150 when A == 0"), "expected 'if COND'");
+        assert_eq!(error("b 0150 if A =="), "condition ends unexpectedly");
+        assert_eq!(error("d 0"), "no breakpoint 0");
+        assert_eq!(error("d x"), "bad breakpoint number");
+        assert_eq!(error("watch q C000"), "usage: watch r|w|rw|x START[-END] [if COND]");
+        assert_eq!(error("watch r C0FF-C000"), "range end is before its start");
+        assert_eq!(error("s many"), "bad step count");
+        assert_eq!(error("line 154"), "scanline must be 0-153");
+        assert_eq!(error("w C000 100"), "100 is not a byte");
+        assert_eq!(error("cheat del 0"), "no cheat 0");
+        assert_eq!(error("search list"), "no search running, start one with 'search'");
+    }
+}
@@ -0,0 +1,410 @@
+// SM83 instruction decoder
+//
+// Opcodes are decoded from their bit fields (xx yyy zzz) rather than a flat
+// 512-entry table; see the "Decoding Gameboy Z80 opcodes" reference for the
+// layout this follows.
+
+mod symbols;
+
+use std::fmt;
+
+pub use symbols::SymbolTable;
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const COND: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP"];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Reg(&'static str),      // Register or register-indirect such as (HL) and (HL+)
+    Cond(&'static str),     // Branch condition
+    Bit(u8),                // Bit number for BIT/RES/SET
+    Imm8(u8),               // n
+    Imm16(u16),             // nn used as a value
+    Addr(u16),              // nn used as a jump or call target
+    Mem(u16),               // (nn)
+    HighMem(u8),            // (FF00+n)
+    Rel(u16),               // JR target, already resolved to an absolute address
+    SpOffset(i8),           // SP+d
+    Offset(i8),             // Signed immediate for ADD SP,d
+}
+
+impl Operand {
+    // Address the operand refers to, if it could carry a label
+    fn address(&self) -> Option<u16> {
+        match *self {
+            Operand::Imm16(n) | Operand::Addr(n) | Operand::Rel(n) | Operand::Mem(n) => Some(n),
+            Operand::HighMem(n) => Some(0xFF00 | n as u16),
+            _ => None,
+        }
+    }
+
+    fn format(&self, symbols: &SymbolTable, rom_bank: u16) -> String {
+        match self.address().and_then(|addr| symbols.lookup(addr, rom_bank)) {
+            Some(label) => match self {
+                Operand::Mem(_) | Operand::HighMem(_) => format!("({})", label),
+                _ => label.to_string(),
+            },
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Operand::Reg(r) | Operand::Cond(r) => write!(f, "{}", r),
+            Operand::Bit(b) => write!(f, "{}", b),
+            Operand::Imm8(n) => write!(f, "${:02X}", n),
+            Operand::Imm16(n) | Operand::Addr(n) | Operand::Rel(n) => write!(f, "${:04X}", n),
+            Operand::Mem(n) => write!(f, "(${:04X})", n),
+            Operand::HighMem(n) => write!(f, "($FF00+${:02X})", n),
+            Operand::SpOffset(d) => write!(f, "SP{:+}", d),
+            Operand::Offset(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    pub length: u8,
+    pub cycles: u8,               // Clock cycles, or cycles when a branch is not taken
+    pub cycles_taken: Option<u8>, // Clock cycles for a taken conditional branch
+}
+
+impl Instruction {
+    // CALL, CALL cc and RST: instructions that push a return address
+    pub fn is_call(&self) -> bool {
+        matches!(self.mnemonic, "CALL" | "RST")
+    }
+
+    pub fn is_return(&self) -> bool {
+        matches!(self.mnemonic, "RET" | "RETI")
+    }
+
+    // Execution never falls through to the next instruction
+    pub fn ends_flow(&self) -> bool {
+        let conditional = matches!(self.operands.first(), Some(Operand::Cond(_)));
+        match self.mnemonic {
+            "JP" | "JR" | "RET" => !conditional,
+            "RETI" | "STOP" | "DB" => true,
+            _ => false,
+        }
+    }
+
+    // Statically known jump or call target
+    pub fn target(&self) -> Option<u16> {
+        if !matches!(self.mnemonic, "JP" | "JR" | "CALL" | "RST") {
+            return None;
+        }
+        self.operands.iter().find_map(|op| match *op {
+            Operand::Addr(n) | Operand::Rel(n) => Some(n),
+            _ => None,
+        })
+    }
+
+    // Like `Display`, with labels substituted for known addresses
+    pub fn format(&self, symbols: &SymbolTable, rom_bank: u16) -> String {
+        let operands: Vec<String> = self.operands.iter().map(|op| op.format(symbols, rom_bank)).collect();
+        if operands.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, operands.join(","))
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { "," }, operand)?;
+        }
+        Ok(())
+    }
+}
+
+// Decodes the instruction at `addr`, fetching bytes through `read`
+pub fn decode(read: impl Fn(u16) -> u8, addr: u16) -> Instruction {
+    use Operand::*;
+
+    let opcode = read(addr);
+    let n8 = || read(addr.wrapping_add(1));
+    let n16 = || u16::from_le_bytes([read(addr.wrapping_add(1)), read(addr.wrapping_add(2))]);
+    let rel = || addr.wrapping_add(2).wrapping_add(n8() as i8 as u16);
+
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = (opcode & 7) as usize;
+    let p = y >> 1;
+    let q = y & 1;
+
+    let (mnemonic, operands): (&'static str, Vec<Operand>) = match (x, z) {
+        (0, 0) => match y {
+            0 => ("NOP", vec![]),
+            1 => ("LD", vec![Mem(n16()), Reg("SP")]),
+            2 => ("STOP", vec![]),
+            3 => ("JR", vec![Rel(rel())]),
+            _ => ("JR", vec![Cond(COND[y - 4]), Rel(rel())]),
+        },
+        (0, 1) if q == 0 => ("LD", vec![Reg(R16[p]), Imm16(n16())]),
+        (0, 1) => ("ADD", vec![Reg("HL"), Reg(R16[p])]),
+        (0, 2) => {
+            let indirect = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            if q == 0 {
+                ("LD", vec![Reg(indirect), Reg("A")])
+            } else {
+                ("LD", vec![Reg("A"), Reg(indirect)])
+            }
+        }
+        (0, 3) => (if q == 0 { "INC" } else { "DEC" }, vec![Reg(R16[p])]),
+        (0, 4) => ("INC", vec![Reg(R8[y])]),
+        (0, 5) => ("DEC", vec![Reg(R8[y])]),
+        (0, 6) => ("LD", vec![Reg(R8[y]), Imm8(n8())]),
+        (0, _) => (["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"][y], vec![]),
+        (1, 6) if y == 6 => ("HALT", vec![]),
+        (1, _) => ("LD", vec![Reg(R8[y]), Reg(R8[z])]),
+        (2, _) => alu(y, Reg(R8[z])),
+        (3, 0) => match y {
+            0..=3 => ("RET", vec![Cond(COND[y])]),
+            4 => ("LDH", vec![HighMem(n8()), Reg("A")]),
+            5 => ("ADD", vec![Reg("SP"), Offset(n8() as i8)]),
+            6 => ("LDH", vec![Reg("A"), HighMem(n8())]),
+            _ => ("LD", vec![Reg("HL"), SpOffset(n8() as i8)]),
+        },
+        (3, 1) if q == 0 => ("POP", vec![Reg(R16_STACK[p])]),
+        (3, 1) => match p {
+            0 => ("RET", vec![]),
+            1 => ("RETI", vec![]),
+            2 => ("JP", vec![Reg("HL")]),
+            _ => ("LD", vec![Reg("SP"), Reg("HL")]),
+        },
+        (3, 2) => match y {
+            0..=3 => ("JP", vec![Cond(COND[y]), Addr(n16())]),
+            4 => ("LD", vec![Reg("($FF00+C)"), Reg("A")]),
+            5 => ("LD", vec![Mem(n16()), Reg("A")]),
+            6 => ("LD", vec![Reg("A"), Reg("($FF00+C)")]),
+            _ => ("LD", vec![Reg("A"), Mem(n16())]),
+        },
+        (3, 3) => match y {
+            0 => ("JP", vec![Addr(n16())]),
+            1 => return decode_cb(read(addr.wrapping_add(1)), addr),
+            6 => ("DI", vec![]),
+            7 => ("EI", vec![]),
+            _ => ("DB", vec![Imm8(opcode)]),
+        },
+        (3, 4) if y < 4 => ("CALL", vec![Cond(COND[y]), Addr(n16())]),
+        (3, 5) if q == 0 => ("PUSH", vec![Reg(R16_STACK[p])]),
+        (3, 5) if p == 0 => ("CALL", vec![Addr(n16())]),
+        (3, 6) => alu(y, Imm8(n8())),
+        (3, 7) => ("RST", vec![Addr(y as u16 * 8)]),
+        _ => ("DB", vec![Imm8(opcode)]), // Unused opcodes lock up real hardware
+    };
+
+    let (length, cycles) = if mnemonic == "DB" { (1, 0) } else { (LENGTHS[opcode as usize], CYCLES[opcode as usize]) };
+    let cycles_taken = match opcode {
+        0x20 | 0x28 | 0x30 | 0x38 => Some(12), // JR cc
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => Some(20), // RET cc
+        0xC2 | 0xCA | 0xD2 | 0xDA => Some(16), // JP cc
+        0xC4 | 0xCC | 0xD4 | 0xDC => Some(24), // CALL cc
+        _ => None,
+    };
+
+    Instruction {
+        addr,
+        opcode,
+        mnemonic,
+        operands,
+        length,
+        cycles,
+        cycles_taken,
+    }
+}
+
+fn alu(y: usize, operand: Operand) -> (&'static str, Vec<Operand>) {
+    // ADD, ADC and SBC spell out the accumulator, the others leave it implied
+    match y {
+        0 | 1 | 3 => (ALU[y], vec![Operand::Reg("A"), operand]),
+        _ => (ALU[y], vec![operand]),
+    }
+}
+
+fn decode_cb(cb: u8, addr: u16) -> Instruction {
+    let x = cb >> 6;
+    let y = (cb >> 3) & 7;
+    let indirect = cb & 7 == 6;
+    let reg = Operand::Reg(R8[(cb & 7) as usize]);
+
+    let (mnemonic, operands) = match x {
+        0 => (ROT[y as usize], vec![reg]),
+        1 => ("BIT", vec![Operand::Bit(y), reg]),
+        2 => ("RES", vec![Operand::Bit(y), reg]),
+        _ => ("SET", vec![Operand::Bit(y), reg]),
+    };
+
+    Instruction {
+        addr,
+        opcode: 0xCB,
+        mnemonic,
+        operands,
+        length: 2,
+        // (HL) costs two extra memory accesses, or one for BIT which only reads
+        cycles: match (indirect, x) {
+            (false, _) => 8,
+            (true, 1) => 12,
+            (true, _) => 16,
+        },
+        cycles_taken: None,
+    }
+}
+
+// Instruction lengths by opcode (CB-prefixed instructions are always 2 bytes)
+const LENGTHS: [u8; 256] = [
+    1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1, // 0x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 1x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 2x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 3x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 4x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 5x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 6x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 7x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 8x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 9x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // Ax
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // Bx
+    1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1, // Cx
+    1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1, // Dx
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // Ex
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // Fx
+];
+
+// Clock cycles by opcode; conditional branches list the not-taken cost
+const CYCLES: [u8; 256] = [
+    4, 12, 8, 8, 4, 4, 8, 4, 20, 8, 8, 8, 4, 4, 8, 4, // 0x
+    4, 12, 8, 8, 4, 4, 8, 4, 12, 8, 8, 8, 4, 4, 8, 4, // 1x
+    8, 12, 8, 8, 4, 4, 8, 4, 8, 8, 8, 8, 4, 4, 8, 4, // 2x
+    8, 12, 8, 8, 12, 12, 12, 4, 8, 8, 8, 8, 4, 4, 8, 4, // 3x
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 4x
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 5x
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 6x
+    8, 8, 8, 8, 8, 8, 4, 8, 4, 4, 4, 4, 4, 4, 8, 4, // 7x
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 8x
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 9x
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // Ax
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // Bx
+    8, 12, 12, 16, 12, 16, 8, 16, 8, 16, 12, 4, 12, 24, 8, 16, // Cx
+    8, 12, 12, 0, 12, 16, 8, 16, 8, 16, 12, 0, 12, 0, 8, 16, // Dx
+    12, 12, 8, 0, 0, 16, 8, 16, 16, 4, 16, 0, 0, 0, 8, 16, // Ex
+    12, 12, 8, 4, 0, 16, 8, 16, 12, 8, 16, 4, 0, 0, 8, 16, // Fx
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decodes `bytes` placed at `addr`
+    fn at(addr: u16, bytes: &[u8]) -> Instruction {
+        decode(|a| bytes.get(a.wrapping_sub(addr) as usize).copied().unwrap_or(0), addr)
+    }
+
+    fn text(bytes: &[u8]) -> String {
+        at(0x0150, bytes).to_string()
+    }
+
+    #[test]
+    fn decodes_representative_opcodes() {
+        assert_eq!(text(&[0x00]), "NOP");
+        assert_eq!(text(&[0x01, 0x34, 0x12]), "LD BC,$1234");
+        assert_eq!(text(&[0x08, 0x00, 0xC0]), "LD ($C000),SP");
+        assert_eq!(text(&[0x22]), "LD (HL+),A");
+        assert_eq!(text(&[0x3A]), "LD A,(HL-)");
+        assert_eq!(text(&[0x36, 0x7F]), "LD (HL),$7F");
+        assert_eq!(text(&[0x76]), "HALT");
+        assert_eq!(text(&[0x78]), "LD A,B");
+        assert_eq!(text(&[0x8E]), "ADC A,(HL)");
+        assert_eq!(text(&[0xAF]), "XOR A");
+        assert_eq!(text(&[0xFE, 0x90]), "CP $90");
+        assert_eq!(text(&[0xE0, 0x40]), "LDH ($FF00+$40),A");
+        assert_eq!(text(&[0xE2]), "LD ($FF00+C),A");
+        assert_eq!(text(&[0xE8, 0xFE]), "ADD SP,-2");
+        assert_eq!(text(&[0xF8, 0x05]), "LD HL,SP+5");
+        assert_eq!(text(&[0xF5]), "PUSH AF");
+        assert_eq!(text(&[0xEF]), "RST $0028");
+        assert_eq!(text(&[0xD3]), "DB $D3");
+
+        let load = at(0x0150, &[0xFA, 0x00, 0xC0]);
+        assert_eq!((load.length, load.cycles, load.operands[1]), (3, 16, Operand::Mem(0xC000)));
+    }
+
+    #[test]
+    fn decodes_cb_prefixed_opcodes() {
+        let cases: [(u8, &str, u8); 6] = [
+            (0x11, "RL C", 8),
+            (0x37, "SWAP A", 8),
+            (0x3E, "SRL (HL)", 16),
+            (0x7C, "BIT 7,H", 8),
+            (0x46, "BIT 0,(HL)", 12),
+            (0xFE, "SET 7,(HL)", 16),
+        ];
+        for (cb, expected, cycles) in cases {
+            let instr = at(0x0150, &[0xCB, cb]);
+            assert_eq!((instr.to_string().as_str(), instr.length, instr.cycles), (expected, 2, cycles));
+        }
+        assert_eq!(at(0x0150, &[0xCB, 0x86]).to_string(), "RES 0,(HL)");
+    }
+
+    #[test]
+    fn times_conditional_branches_both_ways() {
+        // (bytes, cycles not taken, cycles taken)
+        let cases: [(&[u8], u8, Option<u8>); 7] = [
+            (&[0x20, 0x00], 8, Some(12)),       // JR NZ
+            (&[0x18, 0x00], 12, None),          // JR
+            (&[0xC8], 8, Some(20)),             // RET Z
+            (&[0xC9], 16, None),                // RET
+            (&[0xD2, 0, 0], 12, Some(16)),      // JP NC
+            (&[0xDC, 0, 0], 12, Some(24)),      // CALL C
+            (&[0xCD, 0, 0], 24, None),          // CALL
+        ];
+        for (bytes, cycles, taken) in cases {
+            let instr = at(0x0150, bytes);
+            assert_eq!((instr.cycles, instr.cycles_taken), (cycles, taken), "{}", instr);
+        }
+    }
+
+    #[test]
+    fn resolves_relative_jumps() {
+        assert_eq!(at(0x0150, &[0x18, 0xFE]).target(), Some(0x0150)); // JR -2 spins
+        assert_eq!(at(0x0150, &[0x28, 0x10]).to_string(), "JR Z,$0162");
+        assert_eq!(at(0x0150, &[0x38, 0x80]).target(), Some(0x00D2));
+        assert_eq!(at(0xFFFF, &[0x18, 0x01]).target(), Some(0x0002)); // Wraps around
+
+        let call = at(0x0150, &[0xCD, 0x00, 0x40]);
+        assert!(call.is_call() && !call.ends_flow());
+        assert_eq!(call.target(), Some(0x4000));
+        assert!(at(0x0150, &[0xC3, 0, 0]).ends_flow());
+        assert!(!at(0x0150, &[0xC2, 0, 0]).ends_flow());
+        assert!(at(0x0150, &[0xD9]).is_return());
+        assert_eq!(at(0x0150, &[0xE9]).target(), None); // JP HL
+    }
+
+    #[test]
+    fn substitutes_labels() {
+        let symbols = SymbolTable::parse("00:0150 Main\n01:4000 Bank1Func\n02:4000 Bank2Func\n00:C0A0 wLives\n00:FF80 hJoypad").unwrap();
+
+        let call = at(0x0150, &[0xCD, 0x00, 0x40]);
+        assert_eq!(call.format(&symbols, 1), "CALL Bank1Func");
+        assert_eq!(call.format(&symbols, 2), "CALL Bank2Func");
+        assert_eq!(call.format(&symbols, 3), "CALL $4000");
+        assert_eq!(at(0x0150, &[0x18, 0xFE]).format(&symbols, 1), "JR Main");
+        assert_eq!(at(0x0150, &[0xFA, 0xA0, 0xC0]).format(&symbols, 1), "LD A,(wLives)");
+        assert_eq!(at(0x0150, &[0xF0, 0x80]).format(&symbols, 1), "LDH A,(hJoypad)");
+        assert_eq!(at(0x0150, &[0x3E, 0x80]).format(&symbols, 1), "LD A,$80"); // Not an address
+    }
+}
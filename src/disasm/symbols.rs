@@ -0,0 +1,102 @@
+// Symbol files: RGBDS `.sym` and no$gmb `.sym`
+//
+// Both use one `BANK:ADDR Label` entry per line, in hex, with `;` comments.
+// Entries without a bank are taken as bank 0.
+
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Clone, Default)]
+pub struct SymbolTable {
+    by_location: BTreeMap<(u16, u16), String>,
+    by_name: HashMap<String, (u16, u16)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<SymbolTable, String> {
+        let mut table = SymbolTable::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let bad_line = || format!("symbol file line {}: expected 'BANK:ADDR Label'", number + 1);
+            let (location, name) = line.split_once(char::is_whitespace).ok_or_else(bad_line)?;
+            let (bank, addr) = match location.split_once(':') {
+                Some((bank, addr)) => (bank, addr),
+                None => ("0", location),
+            };
+            let bank = u16::from_str_radix(bank, 16).map_err(|_| bad_line())?;
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| bad_line())?;
+            table.insert(bank, addr, name.trim());
+        }
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, bank: u16, addr: u16, name: &str) {
+        // Keep the first label when several share an address
+        self.by_location.entry((bank, addr)).or_insert_with(|| name.to_string());
+        self.by_name.insert(name.to_string(), (bank, addr));
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_location.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_location.is_empty()
+    }
+
+    // Label for `addr`, where `rom_bank` is the bank mapped at 0x4000-0x7FFF
+    pub fn lookup(&self, addr: u16, rom_bank: u16) -> Option<&str> {
+        let found = match addr {
+            0x0000..=0x3FFF => self.by_location.get(&(0, addr)),
+            0x4000..=0x7FFF => self.by_location.get(&(rom_bank, addr)),
+            // RAM banks are not tracked, so take the label from the lowest bank
+            _ => (0..8).find_map(|bank| self.by_location.get(&(bank, addr))),
+        };
+        found.map(String::as_str)
+    }
+
+    // (bank, addr) of a label
+    pub fn find(&self, name: &str) -> Option<(u16, u16)> {
+        self.by_name.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rgbds_and_nocash_files() {
+        let text = "; File generated by rgblink\n\
+                    00:0150 Main\n\
+                    00:0150 Main.alias ; second label at the same place\n\
+                    01:4000 Bank1Func\n\
+                    \n\
+                    1F:7FF0 LastBank.end\n\
+                    C0A0 wLives\n";
+        let symbols = SymbolTable::parse(text).unwrap();
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols.lookup(0x0150, 5), Some("Main"));
+        assert_eq!(symbols.find("Main.alias"), Some((0, 0x0150)));
+        assert_eq!(symbols.lookup(0x4000, 1), Some("Bank1Func"));
+        assert_eq!(symbols.lookup(0x4000, 2), None);
+        assert_eq!(symbols.lookup(0x7FF0, 0x1F), Some("LastBank.end"));
+        assert_eq!(symbols.lookup(0xC0A0, 1), Some("wLives"));
+        assert_eq!(symbols.find("Bank1Func"), Some((1, 0x4000)));
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        let error = |text| SymbolTable::parse(text).err().unwrap();
+        assert_eq!(error("00:0150 Main\n00:XYZ Bad"), "symbol file line 2: expected 'BANK:ADDR Label'");
+        assert_eq!(error("00:0150"), "symbol file line 1: expected 'BANK:ADDR Label'");
+    }
+}
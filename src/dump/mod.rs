@@ -0,0 +1,39 @@
+// Frame dumping
+//
+// Writes every Nth emulated frame to a directory as `frame_NNNNNN.png`,
+// numbered by frame since the dump started, so two runs can be compared
+// file by file.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::gameboy::GameBoy;
+
+pub struct FrameDumper {
+    dir: PathBuf,
+    every: u64,
+    frame: u64,
+}
+
+impl FrameDumper {
+    pub fn new(dir: &str, every: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(FrameDumper {
+            dir: PathBuf::from(dir),
+            every: every.max(1),
+            frame: 0,
+        })
+    }
+
+    // Call once after every emulated frame
+    pub fn on_frame(&mut self, gameboy: &GameBoy) -> io::Result<()> {
+        let frame = self.frame;
+        self.frame += 1;
+        if !frame.is_multiple_of(self.every) {
+            return Ok(());
+        }
+        let path = self.dir.join(format!("frame_{:06}.png", frame));
+        fs::write(path, gameboy.screenshot_png(1))
+    }
+}
@@ -0,0 +1,609 @@
+// Software screen filters
+//
+// A frame goes through three stages, all on the CPU:
+//
+// 1. Ghosting: blends with the previous frame like the slow DMG LCD
+// 2. Scaler: a fixed-factor pixel-art scaler (Scale2x/3x, HQ2x, xBRZ 2x)
+// 3. Presentation: the largest integer scale that fits the window, centered
+//    with black bars so the aspect ratio is kept, optionally with dark lines
+//    between the original pixels like an LCD dot matrix
+//
+// Pixels are 0xAARRGGBB throughout.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scaler {
+    Nearest,
+    Scale2x,
+    Scale3x,
+    Hq2x,
+    Xbrz2x,
+}
+
+impl Scaler {
+    pub const ALL: [Scaler; 5] = [Scaler::Nearest, Scaler::Scale2x, Scaler::Scale3x, Scaler::Hq2x, Scaler::Xbrz2x];
+
+    pub fn parse(text: &str) -> Result<Scaler, String> {
+        Self::ALL
+            .into_iter()
+            .find(|s| s.name() == text)
+            .ok_or_else(|| format!("unknown filter '{}', expected nearest, scale2x, scale3x, hq2x or xbrz2x", text))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scaler::Nearest => "nearest",
+            Scaler::Scale2x => "scale2x",
+            Scaler::Scale3x => "scale3x",
+            Scaler::Hq2x => "hq2x",
+            Scaler::Xbrz2x => "xbrz2x",
+        }
+    }
+
+    pub fn factor(self) -> usize {
+        match self {
+            Scaler::Nearest => 1,
+            Scaler::Scale3x => 3,
+            _ => 2,
+        }
+    }
+
+    pub fn next(self) -> Scaler {
+        let i = Self::ALL.iter().position(|&s| s == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    pub fn scale(self, src: &[u32], width: usize, height: usize) -> Vec<u32> {
+        let img = Image { pixels: src, width, height };
+        match self {
+            Scaler::Nearest => src.to_vec(),
+            Scaler::Scale2x => img.scale2x(),
+            Scaler::Scale3x => img.scale3x(),
+            Scaler::Hq2x => img.hq2x(),
+            Scaler::Xbrz2x => img.xbrz2x(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Filter {
+    pub scaler: Scaler,
+    pub lcd_grid: bool,
+    pub ghosting: bool,
+    previous: Vec<u32>, // Last input frame, for ghosting
+}
+
+impl Filter {
+    pub fn new(scaler: Scaler) -> Self {
+        Filter {
+            scaler,
+            lcd_grid: false,
+            ghosting: false,
+            previous: Vec::new(),
+        }
+    }
+
+    // Ghosting and the scaler. Returns the image and its size.
+    pub fn process(&mut self, frame: &[u32], width: usize, height: usize) -> (Vec<u32>, usize, usize) {
+        let ghosted;
+        let mut input = frame;
+        if self.ghosting && self.previous.len() == frame.len() {
+            ghosted = frame.iter().zip(&self.previous).map(|(&a, &b)| blend(a, b)).collect::<Vec<u32>>();
+            input = &ghosted;
+        }
+        self.previous.clear();
+        self.previous.extend_from_slice(frame);
+
+        let factor = self.scaler.factor();
+        (self.scaler.scale(input, width, height), width * factor, height * factor)
+    }
+
+    // The full pipeline, drawing `frame` into `out` (out_width x out_height)
+    pub fn render(&mut self, frame: &[u32], width: usize, height: usize, out: &mut [u32], out_width: usize, out_height: usize) {
+        let (image, image_width, image_height) = self.process(frame, width, height);
+        out.fill(0xFF00_0000);
+
+        let scale = (out_width / image_width).min(out_height / image_height).max(1);
+        let (x0, y0) = (
+            out_width.saturating_sub(image_width * scale) / 2,
+            out_height.saturating_sub(image_height * scale) / 2,
+        );
+        // Size of one original pixel on screen, for the grid
+        let cell = scale * self.scaler.factor();
+
+        for y in 0..(image_height * scale).min(out_height) {
+            let row = &image[(y / scale) * image_width..][..image_width];
+            let out_row = &mut out[(y0 + y) * out_width + x0..];
+            for (x, pixel) in out_row.iter_mut().take((image_width * scale).min(out_width)).enumerate() {
+                let argb = row[x / scale];
+                *pixel = if self.lcd_grid && cell >= 3 && (x % cell == cell - 1 || y % cell == cell - 1) {
+                    darken(argb)
+                } else {
+                    argb
+                };
+            }
+        }
+    }
+}
+
+fn channels(argb: u32) -> [u32; 3] {
+    [(argb >> 16) & 0xFF, (argb >> 8) & 0xFF, argb & 0xFF]
+}
+
+fn from_channels(c: [u32; 3]) -> u32 {
+    0xFF00_0000 | (c[0] << 16) | (c[1] << 8) | c[2]
+}
+
+// Weighted mix of colors, weights summing to a power of two `1 << shift`
+fn mix(colors: &[(u32, u32)], shift: u32) -> u32 {
+    let mut sum = [0; 3];
+    for &(argb, weight) in colors {
+        for (s, c) in sum.iter_mut().zip(channels(argb)) {
+            *s += c * weight;
+        }
+    }
+    from_channels(sum.map(|s| s >> shift))
+}
+
+fn blend(a: u32, b: u32) -> u32 {
+    mix(&[(a, 1), (b, 1)], 1)
+}
+
+fn darken(argb: u32) -> u32 {
+    from_channels(channels(argb).map(|c| c * 3 / 4))
+}
+
+// HQx's YUV, with the offsets of U and V left out as only differences count
+fn yuv(argb: u32) -> [i32; 3] {
+    let [r, g, b] = channels(argb).map(|c| c as i32);
+    [
+        (299 * r + 587 * g + 114 * b) / 1000,
+        (-169 * r - 331 * g + 500 * b) / 1000,
+        (500 * r - 419 * g - 81 * b) / 1000,
+    ]
+}
+
+// HQx's notion of two colors looking alike
+fn similar(a: u32, b: u32) -> bool {
+    let (ya, yb) = (yuv(a), yuv(b));
+    (ya[0] - yb[0]).abs() <= 48 && (ya[1] - yb[1]).abs() <= 7 && (ya[2] - yb[2]).abs() <= 6
+}
+
+// HQ2x's interpolation rule for the top-left output pixel, indexed by which
+// neighbours differ from the centre: A B C D F G H I as bits 0-7, where
+//   A B C
+//   D E F
+//   G H I
+// The other corners use the same table on the mirrored neighbourhood.
+const HQ2X_RULES: [u8; 256] = [
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 15, 12, 5,  3, 17, 13,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 12, 12, 5,  3,  1, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 17, 13, 5,  3, 16, 14,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 16, 12, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19, 12, 12, 5, 19, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19,  1, 12, 5, 19,  1, 14,
+    4, 4, 6,  2, 4, 4, 6, 18, 5,  3, 16, 12, 5, 19,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 15, 12, 5,  3, 17, 13,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 17, 13, 5,  3, 16, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 13, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 13,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3,  1, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3,  1, 12, 5,  3,  1, 14,
+];
+
+// xBRZ corner blending, strongest last
+const BLEND_NONE: u8 = 0;
+const BLEND_NORMAL: u8 = 1;
+const BLEND_DOMINANT: u8 = 2;
+
+// xBRZ's tuning, as shipped
+const EQUAL_COLOR_TOLERANCE: f64 = 30.0;
+const DOMINANT_DIRECTION_THRESHOLD: f64 = 3.6;
+const STEEP_DIRECTION_THRESHOLD: f64 = 2.2;
+
+// xBRZ's color distance: Euclidean in BT.2020 YCbCr
+fn ycbcr_distance(a: u32, b: u32) -> f64 {
+    let [r, g, b] = {
+        let (ca, cb) = (channels(a), channels(b));
+        [0, 1, 2].map(|i| ca[i] as f64 - cb[i] as f64)
+    };
+    const K_B: f64 = 0.0593;
+    const K_R: f64 = 0.2627;
+    let y = K_R * r + (1.0 - K_B - K_R) * g + K_B * b;
+    let c_b = 0.5 / (1.0 - K_B) * (b - y);
+    let c_r = 0.5 / (1.0 - K_R) * (r - y);
+    (y * y + c_b * c_b + c_r * c_r).sqrt()
+}
+
+// `back` moved m/n of the way towards `front`
+fn gradient(back: u32, front: u32, m: u32, n: u32) -> u32 {
+    let (b, f) = (channels(back), channels(front));
+    from_channels([0, 1, 2].map(|i| (f[i] * m + b[i] * (n - m)) / n))
+}
+
+struct Image<'a> {
+    pixels: &'a [u32],
+    width: usize,
+    height: usize,
+}
+
+impl Image<'_> {
+    // Pixel at an offset from (x, y), clamped to the edges
+    fn at(&self, x: usize, y: usize, dx: isize, dy: isize) -> u32 {
+        let x = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+        let y = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    fn scale2x(&self) -> Vec<u32> {
+        let w2 = self.width * 2;
+        let mut out = vec![0; w2 * self.height * 2];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let p = |dx, dy| self.at(x, y, dx, dy);
+                let (b, d, e, f, h) = (p(0, -1), p(-1, 0), p(0, 0), p(1, 0), p(0, 1));
+                let (o0, o1, o2, o3) = if b != h && d != f {
+                    (
+                        if d == b { d } else { e },
+                        if b == f { f } else { e },
+                        if d == h { d } else { e },
+                        if h == f { f } else { e },
+                    )
+                } else {
+                    (e, e, e, e)
+                };
+                let i = y * 2 * w2 + x * 2;
+                out[i] = o0;
+                out[i + 1] = o1;
+                out[i + w2] = o2;
+                out[i + w2 + 1] = o3;
+            }
+        }
+        out
+    }
+
+    fn scale3x(&self) -> Vec<u32> {
+        let w3 = self.width * 3;
+        let mut out = vec![0; w3 * self.height * 3];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let p = |dx, dy| self.at(x, y, dx, dy);
+                let (a, b, c) = (p(-1, -1), p(0, -1), p(1, -1));
+                let (d, e, f) = (p(-1, 0), p(0, 0), p(1, 0));
+                let (g, h, i) = (p(-1, 1), p(0, 1), p(1, 1));
+                let mut o = [e; 9];
+                if b != h && d != f {
+                    o[0] = if d == b { d } else { e };
+                    o[1] = if (d == b && e != c) || (b == f && e != a) { b } else { e };
+                    o[2] = if b == f { f } else { e };
+                    o[3] = if (d == b && e != g) || (d == h && e != a) { d } else { e };
+                    o[5] = if (b == f && e != i) || (h == f && e != c) { f } else { e };
+                    o[6] = if d == h { d } else { e };
+                    o[7] = if (d == h && e != i) || (h == f && e != g) { h } else { e };
+                    o[8] = if h == f { f } else { e };
+                }
+                for (k, &color) in o.iter().enumerate() {
+                    out[(y * 3 + k / 3) * w3 + x * 3 + k % 3] = color;
+                }
+            }
+        }
+        out
+    }
+
+    fn hq2x(&self) -> Vec<u32> {
+        let w2 = self.width * 2;
+        let mut out = vec![0; w2 * self.height * 2];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                // Mirrored so that the corner being worked out is the top-left one
+                for (k, (sx, sy)) in [(1, 1), (-1, 1), (1, -1), (-1, -1)].into_iter().enumerate() {
+                    let p = |dx: isize, dy: isize| self.at(x, y, dx * sx, dy * sy);
+                    out[(y * 2 + k / 2) * w2 + x * 2 + k % 2] = hq2x_corner(&p);
+                }
+            }
+        }
+        out
+    }
+
+    fn xbrz2x(&self) -> Vec<u32> {
+        // Blend types of the corner to the bottom right of each pixel, as
+        // [that pixel, right, below, diagonal]; the top row and left column
+        // hold the corners above and left of the image
+        let (cw, ch) = (self.width + 1, self.height + 1);
+        let mut corners = vec![[BLEND_NONE; 4]; cw * ch];
+        for cy in 0..ch {
+            for cx in 0..cw {
+                let p = |dx: isize, dy: isize| self.at(0, 0, cx as isize - 1 + dx, cy as isize - 1 + dy);
+                corners[cy * cw + cx] = xbrz_corner(&p);
+            }
+        }
+
+        let w2 = self.width * 2;
+        let mut out = vec![0; w2 * self.height * 2];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let corner = |dx: usize, dy: usize, k: usize| corners[(y + dy) * cw + x + dx][k];
+                // Top left, top right, bottom right, bottom left
+                let mut blend = [corner(0, 0, 3), corner(1, 0, 2), corner(1, 1, 0), corner(0, 1, 1)];
+                let p = |dx, dy| self.at(x, y, dx, dy);
+                let mut kernel = [p(-1, -1), p(0, -1), p(1, -1), p(-1, 0), p(0, 0), p(1, 0), p(-1, 1), p(0, 1), p(1, 1)];
+
+                let mut block = [kernel[4]; 4];
+                for rotation in 0..4 {
+                    xbrz_blend(&kernel, &blend, &mut block, rotation);
+                    let [a, b, c, d, e, f, g, h, i] = kernel;
+                    kernel = [g, d, a, h, e, b, i, f, c];
+                    blend.rotate_right(1);
+                }
+                let i = y * 2 * w2 + x * 2;
+                out[i..i + 2].copy_from_slice(&block[..2]);
+                out[i + w2..i + w2 + 2].copy_from_slice(&block[2..]);
+            }
+        }
+        out
+    }
+}
+
+// HQ2x output for the top-left quarter of E, `p` giving its neighbourhood
+fn hq2x_corner(p: &dyn Fn(isize, isize) -> u32) -> u32 {
+    let e = p(0, 0);
+    let (a, b, c) = (p(-1, -1), p(0, -1), p(1, -1));
+    let (d, f) = (p(-1, 0), p(1, 0));
+    let (g, h, i) = (p(-1, 1), p(0, 1), p(1, 1));
+    let pattern = [a, b, c, d, f, g, h, i]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (n, &color)| bits | ((!similar(e, color) as usize) << n));
+
+    let blend1 = |x| mix(&[(e, 3), (x, 1)], 2);
+    let blend2 = |x, y| mix(&[(e, 2), (x, 1), (y, 1)], 2);
+    let blend3 = |x, y| mix(&[(e, 5), (x, 2), (y, 1)], 3);
+    let blend4 = || mix(&[(e, 6), (d, 1), (b, 1)], 3);
+    let blend5 = || mix(&[(e, 2), (d, 3), (b, 3)], 3);
+    let blend6 = || mix(&[(e, 14), (d, 1), (b, 1)], 4);
+    let edge = similar(b, d);
+    match HQ2X_RULES[pattern] {
+        1 => blend1(a),
+        2 => blend1(d),
+        3 => blend1(b),
+        4 => blend2(d, b),
+        5 => blend2(a, b),
+        6 => blend2(a, d),
+        7 => blend3(b, d),
+        8 => blend3(d, b),
+        9 => blend4(),
+        10 => blend5(),
+        11 => blend6(),
+        12 if edge => blend2(d, b),
+        13 if edge => blend5(),
+        14 if edge => blend6(),
+        15 if edge => blend2(d, b),
+        16 if edge => blend4(),
+        17 if edge => blend5(),
+        15..=17 => blend1(a),
+        18 if similar(b, f) => blend3(b, d),
+        18 => blend1(d),
+        19 if similar(d, h) => blend3(d, b),
+        19 => blend1(b),
+        _ => e,
+    }
+}
+
+// xBRZ's look at the corner between F, G, J and K in
+//   . B C .
+//   E F G H
+//   I J K L
+//   . N O .
+// where `p(0, 0)` is F. Returns the blend types of F, G, J and K towards it.
+fn xbrz_corner(p: &dyn Fn(isize, isize) -> u32) -> [u8; 4] {
+    let (b, c) = (p(0, -1), p(1, -1));
+    let (e, f, g, h) = (p(-1, 0), p(0, 0), p(1, 0), p(2, 0));
+    let (i, j, k, l) = (p(-1, 1), p(0, 1), p(1, 1), p(2, 1));
+    let (n, o) = (p(0, 2), p(1, 2));
+
+    let mut result = [BLEND_NONE; 4];
+    if (f == g && j == k) || (f == j && g == k) {
+        return result;
+    }
+    let dist = ycbcr_distance;
+    let jg = dist(i, f) + dist(f, c) + dist(n, k) + dist(k, h) + 4.0 * dist(j, g);
+    let fk = dist(e, j) + dist(j, o) + dist(b, g) + dist(g, l) + 4.0 * dist(f, k);
+    if jg < fk {
+        // The edge runs along J-G, so F and K are the corners to round off
+        let blend = if DOMINANT_DIRECTION_THRESHOLD * jg < fk { BLEND_DOMINANT } else { BLEND_NORMAL };
+        if f != g && f != j {
+            result[0] = blend;
+        }
+        if k != j && k != g {
+            result[3] = blend;
+        }
+    } else if fk < jg {
+        let blend = if DOMINANT_DIRECTION_THRESHOLD * fk < jg { BLEND_DOMINANT } else { BLEND_NORMAL };
+        if j != f && j != k {
+            result[2] = blend;
+        }
+        if g != f && g != k {
+            result[1] = blend;
+        }
+    }
+    result
+}
+
+// Blends the bottom-right corner of the 2x2 `block` for E in the kernel
+//   A B C
+//   D E F
+//   G H I
+// `kernel`, `blend` (top left, top right, bottom right, bottom left) and
+// the block are seen turned clockwise by `rotation` quarter turns.
+fn xbrz_blend(kernel: &[u32; 9], blend: &[u8; 4], block: &mut [u32; 4], rotation: usize) {
+    let [_, b, c, d, e, f, g, h, i] = *kernel;
+    if blend[2] == BLEND_NONE {
+        return;
+    }
+    let dist = ycbcr_distance;
+    let eq = |x, y| dist(x, y) < EQUAL_COLOR_TOLERANCE;
+
+    let line = if blend[2] >= BLEND_DOMINANT {
+        true
+    } else if (blend[1] != BLEND_NONE && !eq(e, g)) || (blend[3] != BLEND_NONE && !eq(e, c)) {
+        // Another corner blends as well: an isolated pixel, keep its shape
+        false
+    } else {
+        // No full line for an L shape, only the corner
+        eq(e, i) || !eq(g, h) || !eq(h, i) || !eq(i, f) || !eq(f, c)
+    };
+
+    let color = if dist(e, f) <= dist(e, h) { f } else { h };
+    let mut put = |row: usize, col: usize, m, n| {
+        let (mut row, mut col) = (row, col);
+        for _ in 0..rotation {
+            (row, col) = (1 - col, row);
+        }
+        block[row * 2 + col] = gradient(block[row * 2 + col], color, m, n);
+    };
+
+    if !line {
+        put(1, 1, 21, 100); // A round corner, 1 - pi/4 of it covered
+        return;
+    }
+    let (fg, hc) = (dist(f, g), dist(h, c));
+    let shallow = STEEP_DIRECTION_THRESHOLD * fg <= hc && e != g && d != g;
+    let steep = STEEP_DIRECTION_THRESHOLD * hc <= fg && e != c && b != c;
+    match (shallow, steep) {
+        (true, true) => {
+            put(1, 0, 1, 4);
+            put(0, 1, 1, 4);
+            put(1, 1, 5, 6);
+        }
+        (true, false) => {
+            put(1, 0, 1, 4);
+            put(1, 1, 3, 4);
+        }
+        (false, true) => {
+            put(0, 1, 1, 4);
+            put(1, 1, 3, 4);
+        }
+        (false, false) => put(1, 1, 1, 2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: u32 = 0xFF00_0000;
+    const WHITE: u32 = 0xFFFF_FFFF;
+
+    // Rows of '#' (white) and '.' (black)
+    fn image(rows: &[&str]) -> Vec<u32> {
+        rows.iter().flat_map(|row| row.chars().map(|c| if c == '#' { WHITE } else { BLACK })).collect()
+    }
+
+    #[test]
+    fn scale2x_rounds_off_diagonals() {
+        let src = image(&["##.", "#..", "..."]);
+        let expected = image(&[
+            "####..",
+            "###...",
+            "###...",
+            "#.....",
+            "......",
+            "......",
+        ]);
+        assert_eq!(Scaler::Scale2x.scale(&src, 3, 3), expected);
+    }
+
+    #[test]
+    fn scale3x_rounds_off_diagonals() {
+        let src = image(&["##.", "#..", "..."]);
+        let expected = image(&[
+            "######...",
+            "#####....",
+            "#####....",
+            "####.....",
+            "###......",
+            "#........",
+            ".........",
+            ".........",
+            ".........",
+        ]);
+        assert_eq!(Scaler::Scale3x.scale(&src, 3, 3), expected);
+    }
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let src = vec![0xFF12_3456; 4 * 3];
+        for scaler in Scaler::ALL {
+            let factor = scaler.factor();
+            assert_eq!(scaler.scale(&src, 4, 3), vec![0xFF12_3456; 4 * 3 * factor * factor], "{}", scaler.name());
+        }
+    }
+
+    #[test]
+    fn hq2x_and_xbrz_soften_a_lone_dot() {
+        let src = image(&["...", ".#.", "..."]);
+        let gray = |level: u32| 0xFF00_0000 | (level * 0x01_0101);
+
+        // HQ2x: 14/16 of the centre in each quarter
+        let out = Scaler::Hq2x.scale(&src, 3, 3);
+        for (x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)] {
+            assert_eq!(out[y * 6 + x], gray(223), "hq2x ({x}, {y})");
+        }
+        assert_eq!(out.iter().filter(|&&p| p != BLACK).count(), 4);
+
+        // xBRZ: each corner of the dot cut round, 21/100 towards the black
+        let out = Scaler::Xbrz2x.scale(&src, 3, 3);
+        for (x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)] {
+            assert_eq!(out[y * 6 + x], gray(201), "xbrz2x ({x}, {y})");
+        }
+        assert_eq!(out.iter().filter(|&&p| p != BLACK).count(), 4);
+    }
+
+    #[test]
+    fn xbrz_smooths_a_staircase() {
+        // A 45 degree edge: the steps get blended half way along the
+        // diagonal, the solid areas either side are left alone
+        let src = image(&["####", "###.", "##..", "#..."]);
+        let out = Scaler::Xbrz2x.scale(&src, 4, 4);
+        let gray = |level: u32| 0xFF00_0000 | (level * 0x01_0101);
+        for (x, y) in [(5, 3), (4, 4), (3, 5)] {
+            assert_eq!(out[y * 8 + x], gray(127), "({x}, {y})");
+        }
+        assert!(out[..2 * 8].iter().all(|&p| p == WHITE));
+        assert_eq!(out[3 * 8..4 * 8], [WHITE, WHITE, WHITE, WHITE, WHITE, gray(127), BLACK, BLACK]);
+    }
+
+    #[test]
+    fn letterboxes_at_the_largest_integer_scale() {
+        let (width, height) = (160, 144);
+        let frame = vec![WHITE; width * height];
+        let mut filter = Filter::new(Scaler::Nearest);
+
+        // 400x300 fits 2x: 320x288, centered
+        let mut out = vec![0; 400 * 300];
+        filter.render(&frame, width, height, &mut out, 400, 300);
+        let at = |x: usize, y: usize| out[y * 400 + x];
+        assert_eq!((at(39, 6), at(40, 6), at(359, 293), at(360, 293)), (BLACK, WHITE, WHITE, BLACK));
+        assert_eq!((at(40, 5), at(40, 294)), (BLACK, BLACK));
+        assert_eq!(out.iter().filter(|&&p| p == WHITE).count(), 320 * 288);
+    }
+
+    #[test]
+    fn crops_when_the_window_is_too_small() {
+        let (width, height) = (160, 144);
+        let frame: Vec<u32> = (0..width * height).map(|i| 0xFF00_0000 | i as u32).collect();
+        let mut filter = Filter::new(Scaler::Scale2x);
+
+        // Scale 1, anchored top-left, clipped on the right and bottom
+        let mut out = vec![0; 100 * 80];
+        filter.render(&frame, width, height, &mut out, 100, 80);
+        let scaled = Scaler::Scale2x.scale(&frame, width, height);
+        for y in [0, 79] {
+            for x in [0, 99] {
+                assert_eq!(out[y * 100 + x], scaled[y * 320 + x]);
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::borrow::Cow;
+
+use crate::logging::error;
+
+use crate::checksum::crc32;
+use crate::config::Config;
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::png;
+use crate::ppu::{rgb555_to_argb, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::sgb::{SGB_HEIGHT, SGB_WIDTH};
+use crate::savestate::{self, SaveState, StateError, StateReader, StateWriter};
+use crate::trace::Tracer;
+
+/// Number of CPU cycles in one full frame (154 lines of 456 cycles)
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+// A complete console: CPU plus everything reachable through the memory bus
+pub struct GameBoy {
+    pub cpu: Cpu,
+    pub memory: Memory,
+    config: Config,
+    rom_crc: u32,
+    pub tracer: Option<Tracer>, // Instruction trace, see `start_trace`
+    // Colors DMG shades are shown in (BG, OBJ0, OBJ1), instead of the
+    // console's own. Only `screen` uses them: save states, and so movie
+    // checkpoints, stay the same whatever palette is shown.
+    pub display_palette: Option<[[u16; 4]; 3]>,
+}
+
+impl GameBoy {
+    pub fn new(rom_data: &[u8]) -> Self {
+        Self::with_config(rom_data, &Config::default())
+    }
+
+    pub fn with_config(rom_data: &[u8], config: &Config) -> Self {
+        let mut memory = Memory::with_config(rom_data, config);
+        if config.boot_rom.is_some() {
+            return GameBoy {
+                cpu: Cpu::power_on(),
+                memory,
+                config: config.clone(),
+                rom_crc: crc32(rom_data),
+                tracer: None,
+                display_palette: None,
+            };
+        }
+
+        // Configure PPU for optimal initial state
+        memory.ppu.lcdc = 0x91; // LCD on, BG enabled
+        memory.ppu.scy = 0;     // Initial scroll Y
+        memory.ppu.scx = 0;     // Initial scroll X
+        memory.ppu.bgp = 0xE4;  // Standard Game Boy palette
+
+        GameBoy {
+            cpu: Cpu::post_boot(config.model, rom_data.get(0x14D).copied().unwrap_or(0)),
+            memory,
+            config: config.clone(),
+            rom_crc: crc32(rom_data),
+            tracer: None,
+            display_palette: None,
+        }
+    }
+
+    // Execute a single instruction, returns the number of cycles it took on
+    // the normal-speed clock (half the CPU cycles in CGB double speed)
+    pub fn step(&mut self) -> u8 {
+        if let Some(tracer) = &mut self.tracer
+            && self.memory.dma_stall == 0
+        {
+            tracer.trace(&self.cpu, &self.memory);
+        }
+        let cycles = self.cpu.step_memory(&mut self.memory);
+        self.memory.normal_speed_cycles(cycles)
+    }
+
+    // Final colors of the last frame, 15-bit RGB
+    pub fn rgb_frame_buffer(&self) -> &[u16] {
+        &self.memory.ppu.rgb_buffer
+    }
+
+    // The picture to display as (width, height, 15-bit RGB pixels): the
+    // 256x224 composite with border on a Super Game Boy, else the LCD.
+    // DMG-mode frames are colored with `display_palette` when it is set.
+    pub fn screen(&self) -> (usize, usize, Cow<'_, [u16]>) {
+        let ppu = &self.memory.ppu;
+        match (&self.memory.sgb, &self.display_palette) {
+            (Some(sgb), _) => (SGB_WIDTH, SGB_HEIGHT, Cow::Borrowed(&sgb.frame_buffer)),
+            (None, Some(palette)) if !ppu.cgb_mode => {
+                let pixels = ppu
+                    .frame_buffer
+                    .iter()
+                    .zip(ppu.dmg_layers.iter())
+                    .map(|(&shade, &layer)| palette[layer as usize][shade as usize & 0x03])
+                    .collect();
+                (SCREEN_WIDTH, SCREEN_HEIGHT, Cow::Owned(pixels))
+            }
+            _ => (SCREEN_WIDTH, SCREEN_HEIGHT, Cow::Borrowed(&ppu.rgb_buffer)),
+        }
+    }
+
+    // The picture from `screen` as a PNG, each pixel repeated `scale` times
+    // both ways. These are the colors on screen, so `display_palette` applies.
+    pub fn screenshot_png(&self, scale: usize) -> Vec<u8> {
+        let (width, height, screen) = self.screen();
+        let scale = scale.max(1);
+        let mut pixels = Vec::with_capacity(width * height * scale * scale);
+        for y in 0..height * scale {
+            let row = &screen[(y / scale) * width..][..width];
+            pixels.extend((0..width * scale).map(|x| rgb555_to_argb(row[x / scale])));
+        }
+        png::encode_argb(width * scale, height * scale, &pixels)
+    }
+
+    // Log every following instruction to `path` in the Gameboy Doctor format
+    pub fn start_trace(&mut self, path: &str) -> std::io::Result<()> {
+        self.stop_trace();
+        self.tracer = Some(Tracer::to_file(path)?);
+        Ok(())
+    }
+
+    // Returns the number of instructions traced, if tracing was on
+    pub fn stop_trace(&mut self) -> Option<u64> {
+        let mut tracer = self.tracer.take()?;
+        if let Err(e) = tracer.flush() {
+            error!("Failed to flush trace: {}", e);
+        }
+        Some(tracer.lines())
+    }
+
+    // Run the CPU for one frame (70224 cycles)
+    pub fn run_frame(&mut self) {
+        let mut frame_cycles = 0;
+        while frame_cycles < CYCLES_PER_FRAME {
+            frame_cycles += self.step() as u32;
+        }
+    }
+
+    // Pressed buttons as a mask of `joypad::BUTTON_*` bits
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.memory.set_buttons(buttons);
+    }
+
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.memory.ppu.frame_buffer
+    }
+
+    // The settings the console was powered on with
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // CRC-32 of the loaded ROM, used to match save states and movies to a game
+    pub fn rom_crc(&self) -> u32 {
+        self.rom_crc
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        savestate::write_header(&mut w, self.rom_crc, self.frame_buffer());
+        w.chunk(b"CPU ", &self.cpu);
+        w.chunk(b"MEM ", &self.memory);
+        w.chunk(b"PPU ", &self.memory.ppu);
+        w.chunk(b"SER ", &self.memory.serial);
+        w.chunk(b"JOYP", &self.memory.joypad);
+        w.chunk(b"HDMA", &self.memory.hdma);
+        if !self.memory.cart_ram.is_empty() {
+            w.chunk(b"SRAM", &self.memory.cart_ram);
+        }
+        if let Some(sgb) = &self.memory.sgb {
+            w.chunk(b"SGB ", sgb);
+        }
+        w.into_bytes()
+    }
+
+    // Restores a state from `save_state`. On error the console is left untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let header = savestate::read_header(data)?;
+        if header.rom_crc != self.rom_crc {
+            return Err(StateError::RomMismatch {
+                expected: self.rom_crc,
+                found: header.rom_crc,
+            });
+        }
+
+        // Chunks load straight into the console, so nothing is copied and
+        // pointers into the buffers (see the libretro core) stay valid. A
+        // bad chunk rolls back to the state from before.
+        let backup = self.save_state();
+        let result = self.load_chunks(header.chunks);
+        if result.is_err() {
+            let header = savestate::read_header(&backup).expect("own state has a valid header");
+            self.load_chunks(header.chunks).expect("own state loads");
+        }
+        result
+    }
+
+    fn load_chunks(&mut self, mut chunks: StateReader) -> Result<(), StateError> {
+        let mut seen = Vec::new();
+        while !chunks.is_empty() {
+            let (tag, mut r) = chunks.chunk()?;
+            let memory = &mut self.memory;
+            let component: &mut dyn SaveState = match &tag {
+                b"CPU " => &mut self.cpu,
+                b"MEM " => memory,
+                b"PPU " => &mut memory.ppu,
+                b"SER " => &mut memory.serial,
+                b"JOYP" => &mut memory.joypad,
+                b"HDMA" => &mut memory.hdma,
+                b"SRAM" => &mut memory.cart_ram,
+                b"SGB " => match &mut memory.sgb {
+                    Some(sgb) => sgb,
+                    None => return Err(StateError::Invalid("SGB state for a non-SGB console")),
+                },
+                _ => continue, // Written by a newer build, safe to ignore
+            };
+            component.load_state(&mut r)?;
+            seen.push(tag);
+        }
+
+        for tag in [b"CPU ", b"MEM ", b"PPU ", b"SER ", b"JOYP", b"HDMA"] {
+            if !seen.contains(tag) {
+                return Err(StateError::MissingChunk(*tag));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::savestate::STATE_VERSION;
+
+    fn rom(title: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom
+    }
+
+    fn running() -> GameBoy {
+        let mut gameboy = GameBoy::new(&rom(b"STATE"));
+        for _ in 0..3 {
+            gameboy.run_frame();
+        }
+        gameboy
+    }
+
+    #[test]
+    fn boot_rom_runs_to_the_handover() {
+        // Counts BC up to 0x1000 (well over 50000 cycles), then unmaps
+        // itself from 0xFE like the real boot ROMs
+        let mut boot_rom = vec![0; 0x100];
+        boot_rom[..0x0C].copy_from_slice(&[
+            0x01, 0x00, 0x00, // LD BC,0
+            0x03, // INC BC
+            0x78, // LD A,B
+            0xFE, 0x10, // CP 0x10
+            0x20, 0xFA, // JR NZ,-6
+            0xC3, 0xFC, 0x00, // JP 0x00FC
+        ]);
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]); // LD A,1; LDH (0x50),A
+        let config = Config {
+            boot_rom: Some(boot_rom),
+            ..Config::default()
+        };
+        let mut gameboy = GameBoy::with_config(&rom(b"BOOT"), &config);
+
+        let mut cycles = 0u64;
+        while gameboy.memory.boot_rom_mapped {
+            assert!(gameboy.cpu.pc < 0x100, "left the boot ROM at {:04X}", gameboy.cpu.pc);
+            cycles += gameboy.step() as u64;
+            assert!(cycles < 1_000_000, "the boot ROM never finished");
+        }
+        assert!(cycles > 50000);
+        assert_eq!(gameboy.cpu.pc, 0x100);
+        assert_eq!((gameboy.cpu.b, gameboy.cpu.c), (0x10, 0x00));
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let state = running().save_state();
+        let mut gameboy = GameBoy::new(&rom(b"STATE"));
+        gameboy.load_state(&state).unwrap();
+        assert!(gameboy.save_state() == state);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut state = running().save_state();
+        state[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            GameBoy::new(&rom(b"STATE")).load_state(&state),
+            Err(StateError::UnsupportedVersion(STATE_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_other_roms() {
+        let state = running().save_state();
+        let mut other = GameBoy::new(&rom(b"OTHER"));
+        let before = other.save_state();
+        assert!(matches!(other.load_state(&state), Err(StateError::RomMismatch { .. })));
+        assert!(other.save_state() == before);
+    }
+
+    #[test]
+    fn rejects_impossible_ppu_lines() {
+        for (line, mode) in [(154, 1), (255, 0), (144, 3), (100, 1)] {
+            let mut gameboy = running();
+            gameboy.memory.ppu.line = line;
+            gameboy.memory.ppu.mode = mode;
+            let state = gameboy.save_state();
+            assert_eq!(
+                GameBoy::new(&rom(b"STATE")).load_state(&state),
+                Err(StateError::Invalid("PPU line"))
+            );
+        }
+    }
+
+    #[test]
+    fn display_palette_colors_the_screen_only() {
+        let mut gameboy = running();
+        let state = gameboy.save_state();
+        let green = [[0x03E0; 4], [0x001F; 4], [0x7C00; 4]];
+        gameboy.display_palette = Some(green);
+        assert!(gameboy.screen().2.iter().all(|&color| color == 0x03E0));
+        assert!(gameboy.save_state() == state);
+
+        // CGB games keep their own colors
+        gameboy.memory.ppu.cgb_mode = true;
+        assert!(gameboy.screen().2 == gameboy.rgb_frame_buffer());
+    }
+
+    #[test]
+    fn failed_loads_leave_the_console_untouched() {
+        let state = running().save_state();
+        let mut gameboy = GameBoy::new(&rom(b"STATE"));
+        gameboy.run_frame();
+        let before = gameboy.save_state();
+        let vram = gameboy.memory.ppu.vram.as_ptr();
+
+        // Cut off in the middle of the last chunk, after the CPU and memory
+        // map have loaded
+        assert_eq!(gameboy.load_state(&state[..state.len() - 10]), Err(StateError::Truncated));
+        assert!(gameboy.save_state() == before);
+        assert_eq!(gameboy.memory.ppu.vram.as_ptr(), vram);
+    }
+}
@@ -0,0 +1,586 @@
+// GDB remote serial protocol stub
+//
+// Speaks enough RSP over TCP for gdb and IDE frontends to attach: register
+// and memory access through the bus, software/hardware breakpoints, write/
+// read/access watchpoints, single-step, continue and Ctrl-C. Registers are
+// exposed as six 16-bit pairs (AF BC DE HL SP PC) described by the
+// target XML below.
+//
+// Like `Debugger`, the stub is driven one frame at a time by the frontend.
+// While the target is stopped `run_frame` blocks serving packets; while it
+// runs, the socket is polled for an interrupt request once per frame.
+
+use std::collections::HashSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::logging::{error, info};
+
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::watch::{Access, WatchRange};
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.sm83.core">
+    <reg name="af" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="bc" bitsize="16" type="uint16"/>
+    <reg name="de" bitsize="16" type="uint16"/>
+    <reg name="hl" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Watch {
+    kind: WatchKind,
+    addr: u16,
+    len: u16,
+}
+
+// Result of handling one packet
+enum Reply {
+    Packet(String),
+    Resume,       // `c`: run until something stops the target
+    Step,         // `s`: run one instruction
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    breakpoints: HashSet<u16>,
+    watches: Vec<Watch>,
+    running: bool,
+}
+
+impl GdbStub {
+    // Listens on localhost only: the protocol has no authentication
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        Ok(GdbStub {
+            listener,
+            client: None,
+            breakpoints: HashSet::new(),
+            watches: Vec::new(),
+            running: false,
+        })
+    }
+
+    pub fn local_port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    // Blocks until a debugger connects; the target stays halted until it resumes us
+    pub fn wait_for_client(&mut self) -> io::Result<()> {
+        self.listener.set_nonblocking(false)?;
+        let (stream, addr) = self.listener.accept()?;
+        info!("GDB connected from {}", addr);
+        stream.set_nodelay(true)?;
+        self.client = Some(stream);
+        self.running = false;
+        Ok(())
+    }
+
+    // Runs up to one frame. Returns false when the debugger killed the target.
+    pub fn run_frame(&mut self, gameboy: &mut GameBoy) -> bool {
+        if self.client.is_none() {
+            self.accept_pending();
+        }
+
+        let mut frame_cycles = 0;
+        while frame_cycles < CYCLES_PER_FRAME {
+            if self.client.is_some() && !self.running {
+                match self.serve(gameboy) {
+                    Ok(true) => {}
+                    Ok(false) => return false,
+                    Err(e) => {
+                        error!("GDB connection lost: {}", e);
+                        self.disconnect(gameboy);
+                    }
+                }
+                continue;
+            }
+
+            frame_cycles += gameboy.step() as u32;
+            if self.client.is_some()
+                && let Some(stop) = self.check_stop(gameboy)
+            {
+                self.stop(&stop);
+            }
+        }
+
+        if self.running && self.poll_interrupt() {
+            self.stop(&format!("S{:02x}", SIGINT));
+        }
+        true
+    }
+
+    // Picks up a new connection without blocking emulation after a detach
+    fn accept_pending(&mut self) {
+        if self.listener.set_nonblocking(true).is_err() {
+            return;
+        }
+        if let Ok((stream, addr)) = self.listener.accept() {
+            info!("GDB connected from {}", addr);
+            let _ = stream.set_nonblocking(false);
+            let _ = stream.set_nodelay(true);
+            self.client = Some(stream);
+            self.running = false;
+        }
+    }
+
+    fn disconnect(&mut self, gameboy: &mut GameBoy) {
+        self.client = None;
+        self.running = true;
+        self.breakpoints.clear();
+        self.watches.clear();
+        gameboy.memory.watchpoints.set_ranges(Vec::new());
+    }
+
+    fn stop(&mut self, reply: &str) {
+        self.running = false;
+        if let Err(e) = self.send(reply) {
+            error!("GDB connection lost: {}", e);
+            self.client = None;
+        }
+    }
+
+    // Checks breakpoints and watchpoints after an instruction
+    fn check_stop(&self, gameboy: &GameBoy) -> Option<String> {
+        let hits = gameboy.memory.watchpoints.take_hits();
+        for hit in hits {
+            for w in &self.watches {
+                let end = w.addr as u32 + w.len as u32;
+                if !(w.addr as u32..end).contains(&(hit.addr as u32)) {
+                    continue;
+                }
+                let name = match (w.kind, hit.access) {
+                    (WatchKind::Write, Access::Write) => "watch",
+                    (WatchKind::Read, Access::Read) => "rwatch",
+                    (WatchKind::Access, _) => "awatch",
+                    _ => continue,
+                };
+                return Some(format!("T{:02x}{}:{:04x};", SIGTRAP, name, hit.addr));
+            }
+        }
+
+        if self.breakpoints.contains(&gameboy.cpu.pc) {
+            return Some(format!("S{:02x}", SIGTRAP));
+        }
+        None
+    }
+
+    // Handles packets until the target resumes. Returns Ok(false) on kill.
+    fn serve(&mut self, gameboy: &mut GameBoy) -> io::Result<bool> {
+        loop {
+            let Some(packet) = self.read_packet()? else {
+                // Ctrl-C while already stopped: just report the stop again
+                self.send(&format!("S{:02x}", SIGINT))?;
+                continue;
+            };
+
+            match self.handle(gameboy, &packet) {
+                Reply::Packet(reply) => self.send(&reply)?,
+                Reply::Resume => {
+                    gameboy.memory.watchpoints.take_hits();
+                    self.running = true;
+                    return Ok(true);
+                }
+                Reply::Step => {
+                    gameboy.memory.watchpoints.take_hits();
+                    gameboy.step();
+                    let reply = self.check_stop(gameboy).unwrap_or(format!("S{:02x}", SIGTRAP));
+                    self.send(&reply)?;
+                }
+                Reply::Detach => {
+                    self.send("OK")?;
+                    info!("GDB detached");
+                    self.disconnect(gameboy);
+                    return Ok(true);
+                }
+                Reply::Kill => return Ok(false),
+            }
+        }
+    }
+
+    fn handle(&mut self, gameboy: &mut GameBoy, packet: &str) -> Reply {
+        let ok = || Reply::Packet("OK".to_string());
+        let err = || Reply::Packet("E01".to_string());
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        match command {
+            "?" => Reply::Packet(format!("S{:02x}", SIGTRAP)),
+            "g" => Reply::Packet(registers(gameboy).iter().map(|r| hex_u16_le(*r)).collect()),
+            "G" => match decode_hex(args) {
+                Some(bytes) if bytes.len() >= 12 => {
+                    let regs: Vec<u16> = bytes.chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+                    for (i, value) in regs.into_iter().take(6).enumerate() {
+                        set_register(gameboy, i, value);
+                    }
+                    ok()
+                }
+                _ => err(),
+            },
+            "p" => match usize::from_str_radix(args, 16).ok().and_then(|i| registers(gameboy).get(i).copied()) {
+                Some(value) => Reply::Packet(hex_u16_le(value)),
+                None => err(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(reg, value)| {
+                    let bytes = decode_hex(value)?;
+                    let reg = usize::from_str_radix(reg, 16).ok()?;
+                    (reg < 6 && bytes.len() == 2).then(|| (reg, u16::from_le_bytes([bytes[0], bytes[1]])))
+                });
+                match parsed {
+                    Some((reg, value)) => {
+                        set_register(gameboy, reg, value);
+                        ok()
+                    }
+                    None => err(),
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => Reply::Packet(
+                    (0..len)
+                        .map(|i| format!("{:02x}", gameboy.memory.peek(addr.wrapping_add(i as u16))))
+                        .collect(),
+                ),
+                None => err(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| Some((parse_addr_len(range)?, decode_hex(data)?)));
+                match parsed {
+                    Some(((addr, len), bytes)) if bytes.len() == len => {
+                        for (i, byte) in bytes.into_iter().enumerate() {
+                            gameboy.memory.write(addr.wrapping_add(i as u16), byte);
+                        }
+                        ok()
+                    }
+                    _ => err(),
+                }
+            }
+            "c" | "s" => {
+                // Optional resume address
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    gameboy.cpu.pc = addr;
+                }
+                if command == "c" { Reply::Resume } else { Reply::Step }
+            }
+            "Z" | "z" => self.handle_breakpoint(gameboy, command == "Z", args),
+            "H" => ok(), // Single thread: any thread selection is fine
+            "T" => ok(),
+            "D" => Reply::Detach,
+            "k" => Reply::Kill,
+            "q" => Reply::Packet(query(args)),
+            _ => Reply::Packet(String::new()), // Unsupported packet
+        }
+    }
+
+    fn handle_breakpoint(&mut self, gameboy: &mut GameBoy, insert: bool, args: &str) -> Reply {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(addr), Some(len)) = (fields.next(), fields.next(), fields.next()) else {
+            return Reply::Packet("E01".to_string());
+        };
+        let (Ok(addr), Ok(len)) = (u16::from_str_radix(addr, 16), u16::from_str_radix(len, 16)) else {
+            return Reply::Packet("E01".to_string());
+        };
+
+        let watch_kind = match kind {
+            "0" | "1" => {
+                // Software and hardware breakpoints behave the same: ROM can't be patched anyway
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                return Reply::Packet("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Reply::Packet(String::new()),
+        };
+
+        let watch = Watch {
+            kind: watch_kind,
+            addr,
+            len: len.max(1),
+        };
+        if insert {
+            self.watches.push(watch);
+        } else {
+            self.watches.retain(|w| *w != watch);
+        }
+
+        let ranges = self
+            .watches
+            .iter()
+            .map(|w| WatchRange {
+                start: w.addr,
+                end: w.addr.saturating_add(w.len - 1),
+                read: w.kind != WatchKind::Write,
+                write: w.kind != WatchKind::Read,
+            })
+            .collect();
+        gameboy.memory.watchpoints.set_ranges(ranges);
+        Reply::Packet("OK".to_string())
+    }
+
+    // Reads one packet, acknowledging it. Returns None for a Ctrl-C byte.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        let stream = self.client.as_mut().ok_or(ErrorKind::NotConnected)?;
+        loop {
+            match read_byte(stream)? {
+                b'$' => {}
+                0x03 => return Ok(None),
+                _ => continue, // Acks ('+' / '-') and noise between packets
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match read_byte(stream)? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [read_byte(stream)?, read_byte(stream)?];
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+            let actual = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+
+            if expected != Some(actual) {
+                stream.write_all(b"-")?;
+                continue;
+            }
+            stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&unescape(&data)).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let stream = self.client.as_mut().ok_or(ErrorKind::NotConnected)?;
+        let escaped = escape(data.as_bytes());
+        let checksum = escaped.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let mut packet = Vec::with_capacity(escaped.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+        stream.write_all(&packet)?;
+
+        // Wait for the acknowledgement; resend on '-'
+        loop {
+            match read_byte(stream)? {
+                b'+' => return Ok(()),
+                b'-' => stream.write_all(&packet)?,
+                _ => {}
+            }
+        }
+    }
+
+    // Non-blocking check for Ctrl-C while the target runs
+    fn poll_interrupt(&mut self) -> bool {
+        let Some(stream) = self.client.as_mut() else {
+            return false;
+        };
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut buf = [0u8; 64];
+        let interrupted = match stream.read(&mut buf) {
+            Ok(0) => {
+                info!("GDB closed the connection");
+                self.client = None;
+                return false;
+            }
+            Ok(n) => buf[..n].contains(&0x03),
+            Err(_) => false,
+        };
+        let _ = stream.set_nonblocking(false);
+        interrupted
+    }
+}
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<u8> {
+    let mut byte = [0u8];
+    stream.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        "PacketSize=1000;qXfer:features:read+".to_string()
+    } else if let Some(request) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        // Reply with the requested window of the target description
+        let Some((offset, len)) = request
+            .split_once(',')
+            .and_then(|(o, l)| Some((usize::from_str_radix(o, 16).ok()?, usize::from_str_radix(l, 16).ok()?)))
+        else {
+            return "E01".to_string();
+        };
+        let xml = TARGET_XML.as_bytes();
+        let start = offset.min(xml.len());
+        let end = (start + len).min(xml.len());
+        let marker = if end == xml.len() { 'l' } else { 'm' };
+        format!("{}{}", marker, String::from_utf8_lossy(&xml[start..end]))
+    } else if args == "Attached" {
+        "1".to_string()
+    } else if args == "C" {
+        "QC1".to_string()
+    } else if args == "fThreadInfo" {
+        "m1".to_string()
+    } else if args == "sThreadInfo" {
+        "l".to_string()
+    } else {
+        String::new()
+    }
+}
+
+// AF, BC, DE, HL, SP, PC in target description order
+fn registers(gameboy: &GameBoy) -> [u16; 6] {
+    let cpu = &gameboy.cpu;
+    let pair = |hi: u8, lo: u8| ((hi as u16) << 8) | lo as u16;
+    [pair(cpu.a, cpu.f), pair(cpu.b, cpu.c), pair(cpu.d, cpu.e), pair(cpu.h, cpu.l), cpu.sp, cpu.pc]
+}
+
+fn set_register(gameboy: &mut GameBoy, index: usize, value: u16) {
+    let cpu = &mut gameboy.cpu;
+    let [hi, lo] = value.to_be_bytes();
+    match index {
+        0 => (cpu.a, cpu.f) = (hi, lo & 0xF0), // Low nibble of F always reads 0
+        1 => (cpu.b, cpu.c) = (hi, lo),
+        2 => (cpu.d, cpu.e) = (hi, lo),
+        3 => (cpu.h, cpu.l) = (hi, lo),
+        4 => cpu.sp = value,
+        5 => cpu.pc = value,
+        _ => {}
+    }
+}
+
+fn hex_u16_le(value: u16) -> String {
+    let [lo, hi] = value.to_le_bytes();
+    format!("{:02x}{:02x}", lo, hi)
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_addr_len(text: &str) -> Option<(u16, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    // gdb may use addresses above 0xFFFF for other spaces; only the bus exists here
+    (addr <= 0xFFFF && len <= 0x10000).then_some((addr as u16, len))
+}
+
+// '}' escapes the following byte XOR 0x20
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut iter = data.iter();
+    while let Some(&b) = iter.next() {
+        if b == b'}' {
+            if let Some(&next) = iter.next() {
+                out.push(next ^ 0x20);
+            }
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for &b in data {
+        if matches!(b, b'$' | b'#' | b'}' | b'*') {
+            out.push(b'}');
+            out.push(b ^ 0x20);
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    // Sends a packet, checks the stub's ack and returns its checked reply
+    fn request(stream: &mut TcpStream, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(stream, "${}#{:02x}", data, checksum).unwrap();
+        assert_eq!(read_byte(stream).unwrap(), b'+', "ack for {}", data);
+
+        assert_eq!(read_byte(stream).unwrap(), b'$');
+        let mut reply = Vec::new();
+        loop {
+            match read_byte(stream).unwrap() {
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+        let checksum = [read_byte(stream).unwrap(), read_byte(stream).unwrap()];
+        let expected = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+        assert_eq!(reply.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)), expected, "checksum of {}", data);
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn serves_a_session() {
+        // NOPs from the entry point on
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x137].copy_from_slice(b"GDB");
+        let mut gameboy = GameBoy::new(&rom);
+
+        let mut stub = GdbStub::bind(0).unwrap();
+        let port = stub.local_port().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let replies = ["?", "g", "m134,3", "Z0,104,1", "c", "g", "s", "p5"].map(|packet| request(&mut stream, packet));
+            stream.write_all(b"$k#6b").unwrap();
+            replies
+        });
+
+        stub.wait_for_client().unwrap();
+        let mut frames = 0;
+        while stub.run_frame(&mut gameboy) {
+            frames += 1;
+            assert!(frames < 100, "the client never killed the target");
+        }
+
+        let replies = client.join().unwrap();
+        assert_eq!(replies[0], "S05");
+        assert_eq!(replies[1], "80011300d8004d01feff0001");
+        assert_eq!(replies[2], "474442");
+        assert_eq!(replies[3], "OK");
+        assert_eq!(replies[4], "S05");
+        assert_eq!(replies[5], "80011300d8004d01feff0401");
+        assert_eq!(replies[6], "S05");
+        assert_eq!(replies[7], "0501");
+        assert_eq!(gameboy.cpu.pc, 0x105);
+    }
+}
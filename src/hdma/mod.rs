@@ -0,0 +1,225 @@
+// CGB VRAM DMA (HDMA1-HDMA5 at 0xFF51-0xFF55)
+//
+// Copies 16-byte blocks from ROM/RAM into the selected VRAM bank. A general
+// purpose transfer copies everything at once with the CPU halted; an HBlank
+// transfer copies one block at the start of each HBlank, and one right away
+// when started during HBlank. Sources in VRAM copy 0xFF, and 0xE000-0xFFFF
+// reads from 0xA000-0xBFFF. The bus side of the copy lives in `Memory`, this
+// keeps the register state.
+
+#[cfg(feature = "alloc")]
+use crate::savestate::{SaveState, StateError, StateReader, StateWriter};
+
+/// CPU cycles the CPU is halted for per 16-byte block at normal speed.
+/// Double speed takes twice as many CPU cycles, the same time in dots.
+pub const BLOCK_CYCLES: u32 = 32;
+
+#[derive(Clone, Default)]
+pub struct Hdma {
+    pub src: u16,           // HDMA1/HDMA2, low 4 bits ignored
+    pub dst: u16,           // HDMA3/HDMA4, offset into VRAM, low 4 bits ignored
+    pub remaining: u8,      // Blocks left minus one, 0x7F once a transfer has finished
+    pub hblank_active: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            remaining: 0x7F,
+            ..Hdma::default()
+        }
+    }
+
+    pub fn write_src_high(&mut self, value: u8) {
+        self.src = (self.src & 0x00F0) | ((value as u16) << 8);
+    }
+
+    pub fn write_src_low(&mut self, value: u8) {
+        self.src = (self.src & 0xFF00) | (value & 0xF0) as u16;
+    }
+
+    pub fn write_dst_high(&mut self, value: u8) {
+        self.dst = (self.dst & 0x00F0) | (((value & 0x1F) as u16) << 8);
+    }
+
+    pub fn write_dst_low(&mut self, value: u8) {
+        self.dst = (self.dst & 0x1F00) | (value & 0xF0) as u16;
+    }
+
+    // Bit 7 clear while an HBlank transfer is running, low bits are the
+    // remaining length. 0xFF once finished or cancelled with nothing left.
+    pub fn read_hdma5(&self) -> u8 {
+        if self.hblank_active {
+            self.remaining & 0x7F
+        } else {
+            0x80 | self.remaining
+        }
+    }
+
+    // Starts or cancels a transfer. Returns the number of blocks to copy right
+    // away for a general purpose transfer.
+    pub fn write_hdma5(&mut self, value: u8) -> Option<u8> {
+        if self.hblank_active && value & 0x80 == 0 {
+            // Cancelling keeps the remaining length readable
+            self.hblank_active = false;
+            return None;
+        }
+
+        self.remaining = value & 0x7F;
+        if value & 0x80 != 0 {
+            self.hblank_active = true;
+            None
+        } else {
+            Some(self.remaining + 1)
+        }
+    }
+
+    // Source and VRAM destination of the next block; advances both and
+    // counts the block off, ending an HBlank transfer after the last one
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.src, self.dst);
+        self.src = self.src.wrapping_add(0x10);
+        self.dst = (self.dst + 0x10) & 0x1FF0;
+        self.remaining = self.remaining.wrapping_sub(1) & 0x7F;
+        if self.remaining == 0x7F {
+            self.hblank_active = false;
+        }
+        block
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl SaveState for Hdma {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.src);
+        w.u16(self.dst);
+        w.u8(self.remaining);
+        w.bool(self.hblank_active);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.src = r.u16()? & 0xFFF0;
+        self.dst = r.u16()? & 0x1FF0;
+        self.remaining = r.u8()? & 0x7F;
+        self.hblank_active = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, Model};
+    use crate::memory::Memory;
+
+    // CGB-mode memory with the source pattern 0x00, 0x01, ... at 0xC000
+    fn cgb_memory() -> Memory {
+        let mut rom = [0; 0x8000];
+        rom[0x143] = 0x80;
+        rom[0x149] = 0x02; // 8K cartridge RAM
+        let mut memory = Memory::with_config(&rom, &Config { model: Model::Cgb, ..Config::default() });
+        for i in 0..0x100 {
+            memory.write(0xC000 + i, i as u8);
+        }
+        memory
+    }
+
+    fn set_addresses(memory: &mut Memory, src: u16, dst: u16) {
+        memory.write(0xFF51, (src >> 8) as u8);
+        memory.write(0xFF52, src as u8);
+        memory.write(0xFF53, (dst >> 8) as u8);
+        memory.write(0xFF54, dst as u8);
+    }
+
+    // Runs the PPU up to the start of the next HBlank
+    fn next_hblank(memory: &mut Memory) {
+        while memory.ppu.mode == 0 {
+            memory.step_ppu(4);
+        }
+        while memory.ppu.mode != 0 {
+            memory.step_ppu(4);
+        }
+    }
+
+    #[test]
+    fn general_purpose_transfers_copy_at_once() {
+        let mut memory = cgb_memory();
+        memory.write(0xFF4F, 1); // VRAM bank 1
+        set_addresses(&mut memory, 0xC000, 0x8800);
+        memory.write(0xFF55, 0x01); // Two blocks
+
+        assert_eq!(memory.read(0xFF55), 0xFF);
+        assert_eq!(memory.dma_stall, 2 * super::BLOCK_CYCLES);
+        for i in 0..0x20 {
+            assert_eq!(memory.ppu.vram[0x2000 + 0x800 + i], i as u8);
+        }
+        assert_eq!(memory.ppu.vram[0x2000 + 0x820], 0);
+        assert_eq!(memory.ppu.vram[0x800], 0);
+    }
+
+    #[test]
+    fn hblank_transfers_copy_a_block_per_hblank() {
+        let mut memory = cgb_memory();
+        while memory.ppu.mode == 0 {
+            memory.step_ppu(4);
+        }
+        set_addresses(&mut memory, 0xC000, 0x9000);
+        memory.write(0xFF55, 0x82); // Three blocks
+        assert_eq!(memory.read(0xFF55), 0x02);
+        assert_eq!(memory.ppu.vram[0x1000], 0);
+
+        next_hblank(&mut memory);
+        assert_eq!(memory.read(0xFF55), 0x01);
+        assert_eq!(memory.ppu.vram[0x1000..0x1010], core::array::from_fn::<u8, 16, _>(|i| i as u8));
+        assert_eq!(memory.ppu.vram[0x1010], 0);
+
+        next_hblank(&mut memory);
+        next_hblank(&mut memory);
+        assert_eq!(memory.read(0xFF55), 0xFF);
+        assert_eq!(memory.ppu.vram[0x102F], 0x2F);
+        next_hblank(&mut memory);
+        assert_eq!(memory.ppu.vram[0x1030], 0);
+    }
+
+    #[test]
+    fn starting_during_hblank_copies_right_away() {
+        let mut memory = cgb_memory();
+        next_hblank(&mut memory);
+        set_addresses(&mut memory, 0xC000, 0x9000);
+        memory.write(0xFF55, 0x81);
+        assert_eq!(memory.read(0xFF55), 0x00);
+        assert_eq!(memory.ppu.vram[0x100F], 0x0F);
+        assert_eq!(memory.ppu.vram[0x1010], 0);
+    }
+
+    #[test]
+    fn cancelling_keeps_the_remaining_length() {
+        let mut memory = cgb_memory();
+        while memory.ppu.mode == 0 {
+            memory.step_ppu(4);
+        }
+        set_addresses(&mut memory, 0xC000, 0x9000);
+        memory.write(0xFF55, 0x83);
+        next_hblank(&mut memory);
+        memory.write(0xFF55, 0x00); // Cancel
+        assert_eq!(memory.read(0xFF55), 0x82);
+
+        next_hblank(&mut memory);
+        assert_eq!(memory.read(0xFF55), 0x82);
+        assert_eq!(memory.ppu.vram[0x1010], 0);
+    }
+
+    #[test]
+    fn sources_in_vram_and_echo_ram_are_remapped() {
+        let mut memory = cgb_memory();
+        memory.ppu.vram[0x10] = 0x55;
+        set_addresses(&mut memory, 0x8010, 0x9000);
+        memory.write(0xFF55, 0x00);
+        assert_eq!(memory.ppu.vram[0x1000], 0xFF);
+
+        // 0xE000 is cartridge RAM at 0xA000, not the WRAM echo
+        memory.cart_ram[0] = 0xA5;
+        set_addresses(&mut memory, 0xE000, 0x9000);
+        memory.write(0xFF55, 0x00);
+        assert_eq!(memory.ppu.vram[0x1000], 0xA5);
+    }
+}
@@ -0,0 +1,79 @@
+// Joypad register P1 (0xFF00)
+//
+// Button state is kept as one byte with a bit set for every pressed button.
+// The layout matches the hardware nibbles: the low nibble is the action
+// group, the high nibble the direction group.
+
+#[cfg(feature = "alloc")]
+use crate::savestate::{SaveState, StateError, StateReader, StateWriter};
+
+pub const BUTTON_A: u8 = 0x01;
+pub const BUTTON_B: u8 = 0x02;
+pub const BUTTON_SELECT: u8 = 0x04;
+pub const BUTTON_START: u8 = 0x08;
+pub const BUTTON_RIGHT: u8 = 0x10;
+pub const BUTTON_LEFT: u8 = 0x20;
+pub const BUTTON_UP: u8 = 0x40;
+pub const BUTTON_DOWN: u8 = 0x80;
+
+#[derive(Clone)]
+pub struct Joypad {
+    pub select: u8,  // Bits 4-5 of P1, written by the game (0 = group selected)
+    pub buttons: u8, // Currently pressed buttons, see BUTTON_*
+    pub interrupt: bool,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            select: 0x30,
+            buttons: 0,
+            interrupt: false,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        let mut low = 0x0F;
+        if self.select & 0x10 == 0 {
+            low &= !(self.buttons >> 4); // Direction keys
+        }
+        if self.select & 0x20 == 0 {
+            low &= !(self.buttons & 0x0F); // Action keys
+        }
+        0xC0 | self.select | (low & 0x0F)
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.select = value & 0x30;
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        // Any newly pressed button pulls a line low and requests an interrupt
+        if buttons & !self.buttons != 0 {
+            self.interrupt = true;
+        }
+        self.buttons = buttons;
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl SaveState for Joypad {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.select);
+        w.u8(self.buttons);
+        w.bool(self.interrupt);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.select = r.u8()? & 0x30;
+        self.buttons = r.u8()?;
+        self.interrupt = r.bool()?;
+        Ok(())
+    }
+}
@@ -1,11 +1,77 @@
+// Without `std` only the emulation core builds; see the features in Cargo.toml
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+mod logging;
+pub mod storage;
+pub mod bus;
 pub mod cpu;
 pub mod ppu;
 pub mod memory;
+pub mod serial;
+pub mod hdma;
+#[cfg(feature = "alloc")]
+pub mod sgb;
+pub mod joypad;
+pub mod checksum;
+pub mod config;
+#[cfg(feature = "alloc")]
+pub mod savestate;
+#[cfg(feature = "std")]
+pub mod rewind;
+#[cfg(feature = "std")]
+pub mod movie;
+#[cfg(feature = "std")]
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod script;
+#[cfg(feature = "alloc")]
+pub mod watch;
+#[cfg(feature = "alloc")]
+pub mod cheats;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod gdb;
+#[cfg(feature = "std")]
+pub mod png;
+#[cfg(feature = "std")]
+pub mod dump;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod palette;
+#[cfg(feature = "std")]
+pub mod filter;
+#[cfg(feature = "std")]
+pub mod vram_view;
+#[cfg(feature = "std")]
+pub mod gameboy;
+#[cfg(feature = "std")]
+pub mod link;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod libretro;
+#[cfg(feature = "std")]
+pub mod wasm;
 
 // Re-export frequently used items
 pub use ppu::{Ppu, SCREEN_WIDTH, SCREEN_HEIGHT};
+pub use bus::Bus;
 pub use cpu::Cpu;
-pub use memory::Memory;
-
-// Re-export debug visualization functions
-pub use ppu::render_vram_debug_view; 
\ No newline at end of file
+pub use memory::{Memory, Rom};
+#[cfg(feature = "std")]
+pub use gameboy::GameBoy;
+#[cfg(feature = "std")]
+pub use link::LinkedPair;
+#[cfg(feature = "alloc")]
+pub use savestate::StateError;
+#[cfg(feature = "std")]
+pub use rewind::{RewindBuffer, RewindConfig};
+#[cfg(feature = "std")]
+pub use debugger::Debugger;
+#[cfg(feature = "std")]
+pub use movie::{Movie, MovieError, MoviePlayer, MovieRecorder};
@@ -0,0 +1,498 @@
+// libretro core
+//
+// Exports the libretro API (see libretro.h) so RetroArch and other
+// frontends can run the core from the cdylib build. The frontend owns the
+// loop: it calls retro_run once per frame, and the core answers through
+// the callbacks it was given (video refresh, audio batch, input state).
+//
+// The core picks the model from the cartridge header: CGB games run on a
+// CGB, everything else on a DMG. Video is XRGB8888. Audio is silence at
+// the right rate until the core has an APU. Cheats take GameShark and Game
+// Genie codes, several joined with '+'.
+//
+// Every pointer argument follows the contract in libretro.h, which is the
+// safety documentation for the `unsafe` functions below.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, c_uint, c_void, CStr};
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::capture::{CLOCK_HZ, SAMPLE_RATE};
+use crate::cheats::{Cheat, Cheats};
+use crate::config::Config;
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::joypad::*;
+use crate::logging::{error, warn};
+use crate::ppu::rgb555_to_argb;
+use crate::sgb::{SGB_HEIGHT, SGB_WIDTH};
+
+pub const RETRO_API_VERSION: c_uint = 1;
+
+pub const RETRO_DEVICE_JOYPAD: c_uint = 1;
+pub const RETRO_REGION_NTSC: c_uint = 0;
+
+pub const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+pub const RETRO_ENVIRONMENT_EXPERIMENTAL: c_uint = 0x10000;
+pub const RETRO_ENVIRONMENT_SET_MEMORY_MAPS: c_uint = 36 | RETRO_ENVIRONMENT_EXPERIMENTAL;
+pub const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+pub const RETRO_MEMORY_SAVE_RAM: c_uint = 0;
+pub const RETRO_MEMORY_RTC: c_uint = 1;
+pub const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+pub const RETRO_MEMORY_VIDEO_RAM: c_uint = 3;
+
+pub const RETRO_MEMDESC_CONST: u64 = 1 << 0;
+pub const RETRO_MEMDESC_SYSTEM_RAM: u64 = 1 << 2;
+pub const RETRO_MEMDESC_SAVE_RAM: u64 = 1 << 3;
+pub const RETRO_MEMDESC_VIDEO_RAM: u64 = 1 << 4;
+
+// RETRO_DEVICE_ID_JOYPAD_* for each Game Boy button
+const JOYPAD_IDS: [(c_uint, u8); 8] = [
+    (0, BUTTON_B),
+    (2, BUTTON_SELECT),
+    (3, BUTTON_START),
+    (4, BUTTON_UP),
+    (5, BUTTON_DOWN),
+    (6, BUTTON_LEFT),
+    (7, BUTTON_RIGHT),
+    (8, BUTTON_A),
+];
+
+pub type RetroEnvironment = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type RetroVideoRefresh = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type RetroAudioSample = unsafe extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatch = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPoll = unsafe extern "C" fn();
+pub type RetroInputState = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct RetroMemoryDescriptor {
+    pub flags: u64,
+    pub ptr: *mut c_void,
+    pub offset: usize,
+    pub start: usize,
+    pub select: usize,
+    pub disconnect: usize,
+    pub len: usize,
+    pub addrspace: *const c_char,
+}
+
+#[repr(C)]
+pub struct RetroMemoryMap {
+    pub descriptors: *const RetroMemoryDescriptor,
+    pub num_descriptors: c_uint,
+}
+
+struct Core {
+    gameboy: Box<GameBoy>, // Boxed so the memory handed to the frontend never moves
+    video: Vec<u32>,
+    audio: Vec<i16>,
+    sample_clock: u64,
+}
+
+// The console is only `!Send` for the tracer's writer, which the core never
+// sets up; the mutex serializes every access anyway
+unsafe impl Send for Core {}
+
+struct State {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+    core: Option<Core>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    environment: None,
+    video_refresh: None,
+    audio_batch: None,
+    input_poll: None,
+    input_state: None,
+    core: None,
+});
+
+// A panic must not unwind into the frontend, so a poisoned lock is reused
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_environment(callback: RetroEnvironment) {
+    state().environment = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_video_refresh(callback: RetroVideoRefresh) {
+    state().video_refresh = Some(callback);
+}
+
+// Samples are always delivered in batches
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample(_callback: RetroAudioSample) {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample_batch(callback: RetroAudioSampleBatch) {
+    state().audio_batch = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_poll(callback: RetroInputPoll) {
+    state().input_poll = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_state(callback: RetroInputState) {
+    state().input_state = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_init() {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_deinit() {
+    state().core = None;
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    let Some(info) = (unsafe { info.as_mut() }) else {
+        return;
+    };
+    *info = RetroSystemInfo {
+        library_name: c"gb_emulator".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast(),
+        valid_extensions: c"gb|gbc".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    let Some(info) = (unsafe { info.as_mut() }) else {
+        return;
+    };
+    let (width, height) = match &state().core {
+        Some(core) => {
+            let (width, height, _) = core.gameboy.screen();
+            (width, height)
+        }
+        None => (crate::ppu::SCREEN_WIDTH, crate::ppu::SCREEN_HEIGHT),
+    };
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: width as c_uint,
+            base_height: height as c_uint,
+            max_width: SGB_WIDTH as c_uint,
+            max_height: SGB_HEIGHT as c_uint,
+            aspect_ratio: width as f32 / height as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: CLOCK_HZ as f64 / CYCLES_PER_FRAME as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+// Only the standard joypad is supported
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = &mut state().core {
+        let memory = &mut core.gameboy.memory;
+        let mut fresh = GameBoy::with_config(&memory.rom, &Config::for_rom(&memory.rom));
+        // Cartridge RAM survives a reset. The old buffers are reused so the
+        // memory maps stay valid, and so is the box.
+        fresh.memory.cart_ram.copy_from_slice(&memory.cart_ram);
+        fresh.memory.reuse_buffers(memory);
+        fresh.memory.cheats = std::mem::take(&mut memory.cheats);
+        *core.gameboy = fresh;
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_run() {
+    let mut state = state();
+    if let Some(poll) = state.input_poll {
+        unsafe { poll() };
+    }
+    let mut buttons = 0;
+    if let Some(input) = state.input_state {
+        for (id, bit) in JOYPAD_IDS {
+            if unsafe { input(0, RETRO_DEVICE_JOYPAD, 0, id) } != 0 {
+                buttons |= bit;
+            }
+        }
+    }
+
+    let (video_refresh, audio_batch) = (state.video_refresh, state.audio_batch);
+    let Some(core) = &mut state.core else {
+        return;
+    };
+    core.gameboy.set_buttons(buttons);
+    core.gameboy.run_frame();
+
+    let (width, height, screen) = core.gameboy.screen();
+    core.video.clear();
+    core.video.extend(screen.iter().map(|&c| rgb555_to_argb(c)));
+    if let Some(refresh) = video_refresh {
+        unsafe { refresh(core.video.as_ptr().cast(), width as c_uint, height as c_uint, width * 4) };
+    }
+
+    // One frame's worth of stereo samples, carrying the remainder
+    core.sample_clock += SAMPLE_RATE as u64 * CYCLES_PER_FRAME as u64;
+    let frames = (core.sample_clock / CLOCK_HZ) as usize;
+    core.sample_clock %= CLOCK_HZ;
+    core.audio.clear();
+    core.audio.resize(frames * 2, 0);
+    if let Some(batch) = audio_batch {
+        let mut sent = 0;
+        while sent < frames {
+            let taken = unsafe { batch(core.audio[sent * 2..].as_ptr(), frames - sent) };
+            if taken == 0 {
+                break;
+            }
+            sent += taken;
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_serialize_size() -> usize {
+    state().core.as_ref().map_or(0, |core| core.gameboy.save_state().len())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let state = state();
+    let Some(core) = &state.core else {
+        return false;
+    };
+    let bytes = core.gameboy.save_state();
+    if data.is_null() || size < bytes.len() {
+        return false;
+    }
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), data.cast(), bytes.len()) };
+    true
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut state = state();
+    let Some(core) = &mut state.core else {
+        return false;
+    };
+    if data.is_null() {
+        return false;
+    }
+    let bytes = unsafe { slice::from_raw_parts(data.cast::<u8>(), size) };
+    core.gameboy.load_state(bytes).is_ok()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_cheat_reset() {
+    if let Some(core) = &mut state().core {
+        core.gameboy.memory.cheats = Cheats::new();
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+    if code.is_null() {
+        return;
+    }
+    let code = unsafe { CStr::from_ptr(code) }.to_string_lossy();
+    let mut state = state();
+    let Some(core) = &mut state.core else {
+        return;
+    };
+    for part in code.split('+').map(str::trim).filter(|p| !p.is_empty()) {
+        match Cheat::parse(part, "") {
+            Ok(mut cheat) => {
+                cheat.enabled = enabled;
+                core.gameboy.memory.cheats.add(cheat);
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    let Some(game) = (unsafe { game.as_ref() }) else {
+        return false;
+    };
+    let rom = if !game.data.is_null() {
+        unsafe { slice::from_raw_parts(game.data.cast::<u8>(), game.size) }.to_vec()
+    } else if !game.path.is_null() {
+        let path = unsafe { CStr::from_ptr(game.path) }.to_string_lossy().into_owned();
+        match std::fs::read(&path) {
+            Ok(rom) => rom,
+            Err(e) => {
+                error!("{}: {}", path, e);
+                return false;
+            }
+        }
+    } else {
+        return false;
+    };
+    // The header must be there, and the flat mapping needs both ROM banks
+    if rom.len() < 0x8000 {
+        return false;
+    }
+
+    let mut state = state();
+    let Some(environment) = state.environment else {
+        return false;
+    };
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !unsafe { environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, (&raw mut format).cast()) } {
+        error!("Frontend does not support XRGB8888");
+        return false;
+    }
+
+    let mut core = Core {
+        gameboy: Box::new(GameBoy::with_config(&rom, &Config::for_rom(&rom))),
+        video: Vec::new(),
+        audio: Vec::new(),
+        sample_clock: 0,
+    };
+    let descriptors = memory_descriptors(&mut core.gameboy);
+    let mut map = RetroMemoryMap {
+        descriptors: descriptors.as_ptr(),
+        num_descriptors: descriptors.len() as c_uint,
+    };
+    // Optional, frontends without it simply have no cheat/achievement support
+    unsafe { environment(RETRO_ENVIRONMENT_SET_MEMORY_MAPS, (&raw mut map).cast()) };
+
+    state.core = Some(core);
+    true
+}
+
+// The memory map as seen by the CPU, for cheat searches and achievements.
+// Banked WRAM is described as the bank 1 that DMG software sees.
+fn memory_descriptors(gameboy: &mut GameBoy) -> Vec<RetroMemoryDescriptor> {
+    let memory = &mut gameboy.memory;
+    let describe = |flags, ptr: *mut u8, start: usize, len: usize| RetroMemoryDescriptor {
+        flags,
+        ptr: ptr.cast(),
+        offset: 0,
+        start,
+        select: 0,
+        disconnect: 0,
+        len,
+        addrspace: ptr::null(),
+    };
+    let mut descriptors = vec![
+        describe(RETRO_MEMDESC_CONST, memory.rom.as_mut_ptr(), 0x0000, memory.rom.len().min(0x8000)),
+        describe(RETRO_MEMDESC_VIDEO_RAM, memory.ppu.vram.as_mut_ptr(), 0x8000, 0x2000),
+        describe(RETRO_MEMDESC_SYSTEM_RAM, memory.wram.as_mut_ptr(), 0xC000, 0x1000),
+        describe(RETRO_MEMDESC_SYSTEM_RAM, memory.wram[0x1000..].as_mut_ptr(), 0xD000, 0x1000),
+        describe(0, memory.ppu.oam.as_mut_ptr(), 0xFE00, 0xA0),
+        describe(RETRO_MEMDESC_SYSTEM_RAM, memory.hram.as_mut_ptr(), 0xFF80, 0x7F),
+    ];
+    if !memory.cart_ram.is_empty() {
+        let len = memory.cart_ram.len().min(0x2000);
+        descriptors.push(describe(RETRO_MEMDESC_SAVE_RAM, memory.cart_ram.as_mut_ptr(), 0xA000, len));
+    }
+    descriptors
+}
+
+// Multi-cartridge loading (e.g. Sufami Turbo style) does not apply
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_unload_game() {
+    state().core = None;
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+// The frontend reads and writes these directly, e.g. to keep SRAM on disk
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    let mut state = state();
+    let Some(core) = &mut state.core else {
+        return ptr::null_mut();
+    };
+    let memory = &mut core.gameboy.memory;
+    let data: &mut [u8] = match id {
+        RETRO_MEMORY_SAVE_RAM => &mut memory.cart_ram,
+        RETRO_MEMORY_SYSTEM_RAM => &mut memory.wram,
+        RETRO_MEMORY_VIDEO_RAM => &mut memory.ppu.vram,
+        _ => return ptr::null_mut(),
+    };
+    if data.is_empty() { ptr::null_mut() } else { data.as_mut_ptr().cast() }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    let state = state();
+    let Some(core) = &state.core else {
+        return 0;
+    };
+    let memory = &core.gameboy.memory;
+    match id {
+        RETRO_MEMORY_SAVE_RAM => memory.cart_ram.len(),
+        // DMG software only ever sees the first two banks
+        RETRO_MEMORY_SYSTEM_RAM if memory.ppu.cgb_mode => memory.wram.len(),
+        RETRO_MEMORY_SYSTEM_RAM => 0x2000,
+        RETRO_MEMORY_VIDEO_RAM if memory.ppu.cgb_mode => memory.ppu.vram.len(),
+        RETRO_MEMORY_VIDEO_RAM => 0x2000,
+        _ => 0, // No RTC without an MBC3
+    }
+}
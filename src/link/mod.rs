@@ -0,0 +1,122 @@
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::serial::Serial;
+
+// Two consoles joined by an in-memory link cable.
+//
+// Both machines are advanced in lockstep: the one that is behind always runs
+// the next instruction, so neither ever gets more than one instruction ahead
+// of the other. Nothing here depends on wall-clock time, threads or hashing
+// order, so the same ROMs always produce the same frames.
+pub struct LinkedPair {
+    pub left: GameBoy,
+    pub right: GameBoy,
+    left_cycles: u64,
+    right_cycles: u64,
+}
+
+impl LinkedPair {
+    pub fn new(left_rom: &[u8], right_rom: &[u8]) -> Self {
+        Self::from_consoles(GameBoy::new(left_rom), GameBoy::new(right_rom))
+    }
+
+    pub fn from_consoles(mut left: GameBoy, mut right: GameBoy) -> Self {
+        left.memory.serial.linked = true;
+        right.memory.serial.linked = true;
+
+        LinkedPair {
+            left,
+            right,
+            left_cycles: 0,
+            right_cycles: 0,
+        }
+    }
+
+    // Cycles executed by each side since the pair was created
+    pub fn cycles(&self) -> (u64, u64) {
+        (self.left_cycles, self.right_cycles)
+    }
+
+    // Execute one instruction on whichever console is behind (left wins ties)
+    pub fn step(&mut self) {
+        if self.left_cycles <= self.right_cycles {
+            self.left_cycles += self.left.step() as u64;
+        } else {
+            self.right_cycles += self.right.step() as u64;
+        }
+
+        self.resolve_transfers();
+    }
+
+    // Run both consoles until each has completed at least one more frame's worth of cycles
+    pub fn run_frame(&mut self) {
+        let left_target = self.left_cycles + CYCLES_PER_FRAME as u64;
+        let right_target = self.right_cycles + CYCLES_PER_FRAME as u64;
+        while self.left_cycles < left_target || self.right_cycles < right_target {
+            self.step();
+        }
+    }
+
+    pub fn frame_buffers(&self) -> (&[u8], &[u8]) {
+        (self.left.frame_buffer(), self.right.frame_buffer())
+    }
+
+    fn resolve_transfers(&mut self) {
+        let left = &mut self.left.memory;
+        let right = &mut self.right.memory;
+
+        Serial::exchange(&mut left.serial, &mut right.serial);
+        Serial::exchange(&mut right.serial, &mut left.serial);
+
+        // Raise the serial interrupt on whichever side just finished
+        left.step_serial(0);
+        right.step_serial(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 32 KiB ROM that runs `program` from the entry point
+    fn rom(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        rom
+    }
+
+    // Writes `data` to SB and `control` to SC, then spins with JR -2
+    fn send(data: u8, control: u8) -> Vec<u8> {
+        rom(&[0x3E, data, 0xE0, 0x01, 0x3E, control, 0xE0, 0x02, 0x18, 0xFE])
+    }
+
+    #[test]
+    fn exchanges_one_byte() {
+        let mut pair = LinkedPair::new(&send(0x42, 0x81), &send(0x99, 0x80));
+        for _ in 0..10_000 {
+            if pair.left.memory.if_ & 0x08 != 0 {
+                break;
+            }
+            pair.step();
+        }
+
+        let (left, right) = (&pair.left.memory, &pair.right.memory);
+        assert_eq!(left.serial.sb, 0x99);
+        assert_eq!(right.serial.sb, 0x42);
+        assert_eq!(left.serial.read_sc() & 0x80, 0);
+        assert_eq!(right.serial.read_sc() & 0x80, 0);
+        assert_eq!(left.if_ & 0x08, 0x08);
+        assert_eq!(right.if_ & 0x08, 0x08);
+    }
+
+    #[test]
+    fn runs_are_deterministic() {
+        let run = || {
+            let mut pair = LinkedPair::new(&send(0x42, 0x81), &send(0x99, 0x80));
+            for _ in 0..20 {
+                pair.run_frame();
+            }
+            (pair.cycles(), pair.left.save_state(), pair.right.save_state())
+        };
+        assert!(run() == run());
+    }
+}
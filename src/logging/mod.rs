@@ -0,0 +1,55 @@
+// Logging behind the `log` feature
+//
+// The library logs through these macros instead of the `log` crate's so it
+// builds without it. With the feature off the arguments are still type
+// checked, then compiled out.
+
+// Smaller builds don't use every level
+#![allow(unused_macros, unused_imports)]
+
+macro_rules! log_error {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::error!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+macro_rules! log_info {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::info!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::debug!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+// `warn` is also a builtin attribute, so the names are given on export
+pub(crate) use {log_debug as debug, log_error as error, log_info as info, log_warn as warn};
@@ -1,24 +1,342 @@
 use std::fs;
-use log::{info, error};
+use log::{info, warn, error};
 use std::env;
-use minifb::{Window, WindowOptions, Key};
+use std::path::Path;
+use minifb::{Window, WindowOptions, Key, KeyRepeat, Scale};
 use std::error::Error;
+use gb_emulator::joypad::*;
+use gb_emulator::gdb::GdbStub;
+use gb_emulator::config::{Config, Model, RamInit};
 
 // Import from our crate modules
-use gb_emulator::{Cpu, Memory, SCREEN_WIDTH, SCREEN_HEIGHT, render_vram_debug_view};
+use gb_emulator::{Debugger, GameBoy, Movie, MoviePlayer, MovieRecorder, RewindBuffer, RewindConfig, Ppu};
+use gb_emulator::capture::Recording;
+use gb_emulator::cheats::Cheats;
+use gb_emulator::dump::FrameDumper;
+use gb_emulator::filter::{Filter, Scaler};
+use gb_emulator::palette::Palette;
+use gb_emulator::png;
+use gb_emulator::script::Script;
+use gb_emulator::ppu::rgb555_to_argb;
+use gb_emulator::vram_view::{self, DebugImage};
 
+// Initial window size in multiples of the screen; the window can be resized
 const WINDOW_SCALE: usize = 4;
 
+// Frames after power-on in which a button combo picks a CGB palette,
+// about as long as the CGB logo stays up
+const BOOT_COMBO_FRAMES: u32 = 120;
+
+// F1-F9 select save-state slots 1-9
+const SLOT_KEYS: [Key; 9] = [
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9,
+];
+
+// Keyboard layout for the joypad
+const BUTTON_KEYS: [(Key, u8); 8] = [
+    (Key::Right, BUTTON_RIGHT),
+    (Key::Left, BUTTON_LEFT),
+    (Key::Up, BUTTON_UP),
+    (Key::Down, BUTTON_DOWN),
+    (Key::Z, BUTTON_A),
+    (Key::X, BUTTON_B),
+    (Key::Tab, BUTTON_SELECT),
+    (Key::Enter, BUTTON_START),
+];
+
+const USAGE: &str = "Usage: gb_emulator <rom_file> [--record FILE | --play FILE] [--load-state FILE] [--debug | --gdb PORT] [--trace FILE]\n       [--model dmg0|dmg|mgb|sgb|cgb] [--boot-rom FILE] [--vram-init zero|random[:SEED]]\n       [--palette NAME|FILE] [--filter nearest|scale2x|scale3x|hq2x|xbrz2x] [--lcd-grid] [--ghosting]\n       [--dump-frames DIR [--dump-every N]] [--record-av FILE.y4m|FILE.gif] [--script FILE]";
+
+// Command-line options
+#[derive(Default)]
+struct Options {
+    rom_path: String,
+    record: Option<String>, // Record a movie from power-on to this file
+    play: Option<String>,   // Play back a movie (native, VBM or BK2 input log)
+    load_state: Option<String>, // Start from this save state instead of power-on
+    debug: bool,            // Start in the command-line debugger
+    trace: Option<String>,  // Write a Gameboy Doctor style instruction trace from power-on
+    gdb: Option<u16>,       // Serve the GDB remote protocol on this localhost port
+    boot_rom: Option<String>, // Run this boot ROM before the cartridge
+    model: Option<Model>,     // Overrides the model the cartridge asks for
+    vram_init: RamInit,
+    palette: Option<String>,  // DMG palette preset or palette file
+    filter: Option<Scaler>,
+    lcd_grid: bool,
+    ghosting: bool,
+    dump_frames: Option<String>, // Write frames to this directory as PNG
+    dump_every: Option<u64>,     // ... only every Nth frame
+    record_av: Option<String>,   // Record video (and audio) from power-on
+    script: Option<String>,      // Automation script, see `script`
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut rom_path = None;
+    let mut iter = args.iter().skip(1);
+
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().cloned().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--record" => options.record = Some(value()?),
+            "--play" => options.play = Some(value()?),
+            "--load-state" => options.load_state = Some(value()?),
+            "--debug" => options.debug = true,
+            "--trace" => options.trace = Some(value()?),
+            "--boot-rom" => options.boot_rom = Some(value()?),
+            "--model" => options.model = Some(Model::parse(&value()?)?),
+            "--palette" => options.palette = Some(value()?),
+            "--filter" => options.filter = Some(Scaler::parse(&value()?)?),
+            "--lcd-grid" => options.lcd_grid = true,
+            "--ghosting" => options.ghosting = true,
+            "--dump-frames" => options.dump_frames = Some(value()?),
+            "--record-av" => options.record_av = Some(value()?),
+            "--script" => options.script = Some(value()?),
+            "--dump-every" => options.dump_every = Some(value()?.parse().map_err(|_| "--dump-every needs a frame count")?),
+            "--vram-init" => options.vram_init = RamInit::parse(&value()?)?,
+            "--gdb" => options.gdb = Some(value()?.parse().map_err(|_| "--gdb needs a port number")?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    if options.debug && options.gdb.is_some() {
+        return Err("--debug and --gdb cannot be combined".to_string());
+    }
+    if options.record.is_some() && options.play.is_some() {
+        return Err("--record and --play cannot be combined".to_string());
+    }
+    if options.debug && (options.record.is_some() || options.play.is_some()) {
+        return Err("--debug cannot be combined with --record or --play".to_string());
+    }
+    if options.gdb.is_some() && (options.record.is_some() || options.play.is_some()) {
+        return Err("--gdb cannot be combined with --record or --play".to_string());
+    }
+    if options.play.is_some() && options.load_state.is_some() {
+        return Err("--play and --load-state cannot be combined, a movie brings its own start".to_string());
+    }
+    if options.script.is_some() && (options.debug || options.gdb.is_some() || options.record.is_some() || options.play.is_some()) {
+        return Err("--script cannot be combined with --debug, --gdb, --record or --play".to_string());
+    }
+    if options.dump_every.is_some() && options.dump_frames.is_none() {
+        return Err("--dump-every needs --dump-frames".to_string());
+    }
+    options.rom_path = rom_path.ok_or("missing ROM file")?;
+    Ok(options)
+}
+
+fn read_buttons(window: &Window) -> u8 {
+    BUTTON_KEYS
+        .iter()
+        .filter(|(key, _)| window.is_key_down(*key))
+        .fold(0, |buttons, (_, bit)| buttons | bit)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VramView {
+    Tiles,
+    Map9800,
+    Map9C00,
+    Oam,
+    Palettes,
+    TestPattern,
+}
+
+impl VramView {
+    fn name(self) -> &'static str {
+        match self {
+            VramView::Tiles => "tiles",
+            VramView::Map9800 => "map9800",
+            VramView::Map9C00 => "map9c00",
+            VramView::Oam => "oam",
+            VramView::Palettes => "palettes",
+            VramView::TestPattern => "testpattern",
+        }
+    }
+
+    fn render(self, ppu: &Ppu, shades: &[u32; 4]) -> DebugImage {
+        match self {
+            VramView::Tiles => vram_view::render_tiles(ppu, shades),
+            VramView::Map9800 => vram_view::render_bg_map(ppu, false, shades),
+            VramView::Map9C00 => vram_view::render_bg_map(ppu, true, shades),
+            VramView::Oam => vram_view::render_oam(ppu, shades),
+            VramView::Palettes => vram_view::render_palettes(ppu, shades),
+            VramView::TestPattern => vram_view::render_test_pattern(shades),
+        }
+    }
+}
+
+// Keys 1-6 in the viewer window select a view
+const VIEW_KEYS: [(Key, VramView); 6] = [
+    (Key::Key1, VramView::Tiles),
+    (Key::Key2, VramView::Map9800),
+    (Key::Key3, VramView::Map9C00),
+    (Key::Key4, VramView::Oam),
+    (Key::Key5, VramView::Palettes),
+    (Key::Key6, VramView::TestPattern),
+];
+
+// Separate window showing one VRAM view at a time; P exports it to PNG
+struct VramViewer {
+    window: Window,
+    view: VramView,
+}
+
+impl VramViewer {
+    fn open() -> Result<Self, minifb::Error> {
+        let options = WindowOptions {
+            resize: true,
+            scale: Scale::X2,
+            ..WindowOptions::default()
+        };
+        Ok(VramViewer {
+            window: Window::new("VRAM Viewer - 1 tiles, 2/3 maps, 4 OAM, 5 palettes, 6 test pattern, P save PNG", 256, 256, options)?,
+            view: VramView::Tiles,
+        })
+    }
+
+    // Returns false once the window has been closed
+    fn update(&mut self, ppu: &Ppu, shades: &[u32; 4], rom_path: &str) -> bool {
+        if !self.window.is_open() {
+            return false;
+        }
+
+        for (key, view) in VIEW_KEYS {
+            if self.window.is_key_pressed(key, KeyRepeat::No) {
+                self.view = view;
+                if view == VramView::Oam {
+                    print!("{}", vram_view::oam_table(ppu));
+                }
+            }
+        }
+
+        let image = self.view.render(ppu, shades);
+        if self.window.is_key_pressed(Key::P, KeyRepeat::No) {
+            let path = format!("{}.{}.png", rom_path, self.view.name());
+            match fs::write(&path, image.to_png()) {
+                Ok(()) => info!("Saved {}", path),
+                Err(e) => error!("Failed to save {}: {}", path, e),
+            }
+        }
+
+        if let Err(e) = self.window.update_with_buffer(&image.pixels, image.width, image.height) {
+            error!("Failed to update VRAM viewer: {}", e);
+        }
+        true
+    }
+}
+
+fn toggle_trace(gameboy: &mut GameBoy, path: &str) {
+    if let Some(lines) = gameboy.stop_trace() {
+        info!("Trace stopped after {} instructions", lines);
+        return;
+    }
+    match gameboy.start_trace(path) {
+        Ok(()) => info!("Tracing to {}", path),
+        Err(e) => error!("Failed to open {}: {}", path, e),
+    }
+}
+
+fn slot_path(rom_path: &str, slot: usize) -> String {
+    format!("{}.ss{}", rom_path, slot)
+}
+
+// F1-F9 saves to a slot, Shift+F1-F9 loads from it. Loads are ignored
+// while a movie records or plays, they would desync it.
+fn handle_state_hotkeys(window: &Window, gameboy: &mut GameBoy, rom_path: &str, movie_active: bool) {
+    let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+
+    for (i, &key) in SLOT_KEYS.iter().enumerate() {
+        if !window.is_key_pressed(key, KeyRepeat::No) {
+            continue;
+        }
+
+        let path = slot_path(rom_path, i + 1);
+        if shift && movie_active {
+            warn!("State loads are disabled while a movie is recording or playing");
+        } else if shift {
+            let result = fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| gameboy.load_state(&data).map_err(|e| e.to_string()));
+            match result {
+                Ok(()) => info!("Loaded state from slot {}", i + 1),
+                Err(e) => error!("Failed to load {}: {}", path, e),
+            }
+        } else {
+            match fs::write(&path, gameboy.save_state()) {
+                Ok(()) => info!("Saved state to slot {}", i + 1),
+                Err(e) => error!("Failed to save {}: {}", path, e),
+            }
+        }
+    }
+}
+
+// F12 saves a screenshot at native resolution, Shift+F12 the window as
+// shown: `shown` is the filtered buffer with its width and height
+fn take_screenshot(window: &Window, gameboy: &GameBoy, shown: (&[u32], usize, usize), rom_path: &str) {
+    if !window.is_key_pressed(Key::F12, KeyRepeat::No) {
+        return;
+    }
+    let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+
+    let path = (1..)
+        .map(|n| format!("{}.shot{}.png", rom_path, n))
+        .find(|path| !Path::new(path).exists())
+        .unwrap();
+    let png = if shift {
+        let (buffer, width, height) = shown;
+        png::encode_argb(width, height, buffer)
+    } else {
+        gameboy.screenshot_png(1)
+    };
+    match fs::write(&path, png) {
+        Ok(()) => println!("Saved screenshot {}", path),
+        Err(e) => error!("Failed to save {}: {}", path, e),
+    }
+}
+
+// R starts or stops a YUV4MPEG2 + WAV clip next to the ROM, Shift+R an
+// animated GIF
+fn toggle_recording(window: &Window, gameboy: &GameBoy, clip: &mut Option<Recording>, rom_path: &str) {
+    if !window.is_key_pressed(Key::R, KeyRepeat::No) {
+        return;
+    }
+    if let Some(recording) = clip.take() {
+        match recording.finish() {
+            Ok(()) => println!("Recording stopped"),
+            Err(e) => error!("Failed to finish recording: {}", e),
+        }
+        return;
+    }
+
+    let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+    let extension = if shift { "gif" } else { "y4m" };
+    let path = (1..)
+        .map(|n| format!("{}.clip{}.{}", rom_path, n, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap();
+    match Recording::start(&path, gameboy) {
+        Ok(recording) => {
+            println!("Recording to {}", path);
+            *clip = Some(recording);
+        }
+        Err(e) => error!("Failed to record to {}: {}", path, e),
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <rom_file>", args[0]);
-        std::process::exit(1);
-    }
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
 
-    let rom_path = &args[1];
+    let rom_path = &options.rom_path;
     info!("Loading ROM from {}", rom_path);
     let rom_data = fs::read(rom_path)?;
 
@@ -28,79 +346,288 @@ fn main() -> Result<(), Box<dyn Error>> {
     info!("ROM size: {:02X}", rom_data[0x148]);
     info!("RAM size: {:02X}", rom_data[0x149]);
 
-    let mut memory = Memory::new(&rom_data);
-    let mut cpu = Cpu::new();
+    // The model follows the cartridge header unless --model picks one or the
+    // boot ROM is a CGB one
+    let mut config = Config {
+        vram_init: options.vram_init,
+        ..Config::for_rom(&rom_data)
+    };
+    if let Some(model) = options.model {
+        config.model = model;
+    }
+    if let Some(path) = &options.boot_rom {
+        let boot_rom = fs::read(path)?;
+        if boot_rom.len() != 0x100 && boot_rom.len() != Model::Cgb.boot_rom_size() {
+            return Err(format!("{}: boot ROM must be 256 (DMG/MGB/SGB) or 2304 (CGB) bytes", path).into());
+        }
+        if options.model.is_none() {
+            config.model = Model::for_boot_rom(&boot_rom).unwrap_or(config.model);
+        }
+        config.boot_rom = Some(boot_rom);
+    }
+
+    // A movie powers on with the model, VRAM and cheats it was recorded with
+    let movie = match &options.play {
+        Some(path) => Some(Movie::parse(&fs::read(path)?)?),
+        None => None,
+    };
+    if let Some(setup) = movie.as_ref().and_then(|movie| movie.setup.as_ref()) {
+        setup.apply(&mut config)?;
+    }
+
+    let mut gameboy = GameBoy::with_config(&rom_data, &config);
+
+    // Cheats from `<rom>.cht`; K switches them all off and on again
+    if movie.is_none() {
+        gameboy.memory.cheats = Cheats::load_for_rom(rom_path)?;
+        if !gameboy.memory.cheats.is_empty() {
+            info!("Loaded {} cheats", gameboy.memory.cheats.list().len());
+        }
+    }
+
+    // A movie recorded after this embeds the state it starts from
+    if let Some(path) = &options.load_state {
+        gameboy.load_state(&fs::read(path)?).map_err(|e| format!("{}: {}", path, e))?;
+    }
 
-    // Configure PPU for optimal initial state
-    memory.ppu.lcdc = 0x91; // LCD on, BG enabled
-    memory.ppu.scy = 0;     // Initial scroll Y
-    memory.ppu.scx = 0;     // Initial scroll X
-    memory.ppu.bgp = 0xE4;  // Standard Game Boy palette
+    let mut script = match &options.script {
+        Some(path) => {
+            let mut script = Script::load(path)?;
+            script.start(&mut gameboy).map_err(|e| format!("{}: {}", path, e))?;
+            Some(script)
+        }
+        None => None,
+    };
 
+    let mut recorder = options.record.as_ref().map(|_| match options.load_state {
+        Some(_) => MovieRecorder::from_current_state(&gameboy),
+        None => MovieRecorder::from_power_on(&gameboy),
+    });
+    let mut player = match movie {
+        Some(movie) => Some(MoviePlayer::new(movie, &mut gameboy)?),
+        None => None,
+    };
+
+    // A Super Game Boy shows the 256x224 picture with its border
+    let (screen_width, screen_height, _) = gameboy.screen();
     let mut window = Window::new(
         "Game Boy Emulator",
-        SCREEN_WIDTH * WINDOW_SCALE,
-        SCREEN_HEIGHT * WINDOW_SCALE,
-        WindowOptions::default(),
+        screen_width * WINDOW_SCALE,
+        screen_height * WINDOW_SCALE,
+        WindowOptions {
+            resize: true,
+            ..WindowOptions::default()
+        },
     )?;
 
-    // Buffer to store the scaled ARGB pixels
-    let mut buffer = vec![0u32; SCREEN_WIDTH * WINDOW_SCALE * SCREEN_HEIGHT * WINDOW_SCALE];
+    // Buffer to store the filtered ARGB pixels, sized to the window.
+    // F cycles scalers, G toggles the LCD grid, H toggles ghosting.
+    let mut buffer = Vec::new();
+    let mut filter = Filter::new(options.filter.unwrap_or(Scaler::Nearest));
+    filter.lcd_grid = options.lcd_grid;
+    filter.ghosting = options.ghosting;
+
+    // DMG colors; C cycles through the presets. Without --palette the
+    // console's own colors are kept (gray, or the CGB's colorization).
+    let mut palette = options.palette.as_deref().map(Palette::load).transpose()?;
+
+    // On a CGB, holding a direction (and A or B) right after power-on picks
+    // one of the boot ROM's colorizations for DMG games
+    let power_on = options.load_state.is_none() && player.is_none() && config.boot_rom.is_none();
+    let mut combo_frames = if power_on && palette.is_none() && config.model == Model::Cgb && !gameboy.memory.ppu.cgb_mode {
+        BOOT_COMBO_FRAMES
+    } else {
+        0
+    };
+
+    // T toggles tracing at runtime, into the --trace file or next to the ROM
+    let trace_path = options.trace.clone().unwrap_or_else(|| format!("{}.trace.log", rom_path));
+    if options.trace.is_some() {
+        gameboy.start_trace(&trace_path)?;
+    }
+
+    let mut gdb = match options.gdb {
+        Some(port) => {
+            let mut stub = GdbStub::bind(port)?;
+            println!("Waiting for GDB on localhost:{}", stub.local_port()?);
+            stub.wait_for_client()?;
+            Some(stub)
+        }
+        None => None,
+    };
 
-    // Game Boy DMG colors - White, Light Gray, Dark Gray, Black (classic palette)
-    let palette = [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000];
+    let mut dumper = match &options.dump_frames {
+        Some(dir) => Some(FrameDumper::new(dir, options.dump_every.unwrap_or(1))?),
+        None => None,
+    };
 
-    // Configure display mode - set to true for debug overlay, false for normal rendering
-    let mut debug_mode = false;
+    let mut clip = match &options.record_av {
+        Some(path) => Some(Recording::start(path, &gameboy)?),
+        None => None,
+    };
+
+    let mut debugger = options.debug.then(Debugger::new);
+    if debugger.is_some() {
+        println!("Debugger attached, type 'help' for commands");
+    }
+
+    // Hold Backspace to rewind
+    let mut rewind = RewindBuffer::new(RewindConfig::default());
+
+    // D opens and closes the VRAM viewer
+    let mut vram_viewer: Option<VramViewer> = None;
 
     // Main game loop
     while window.is_open() && !window.is_key_down(Key::Escape) {
+        if combo_frames > 0 {
+            combo_frames -= 1;
+            if let Some(preset) = Palette::for_buttons(read_buttons(&window)) {
+                println!("Palette: {}", preset.name);
+                palette = Some(preset);
+                combo_frames = 0;
+            }
+        }
+
+        // Only the picture changes; states and movies stay palette-independent
+        gameboy.display_palette = palette.as_ref().map(Palette::rgb555);
+
         // Run CPU for one frame (70224 cycles)
-        let mut frame_cycles = 0;
-        while frame_cycles < 70224 {
-            let cycles = cpu.step(&mut memory);
-            frame_cycles += cycles as u32;
+        if let Some(movie) = &mut player {
+            match movie.run_frame(&mut gameboy) {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("Movie finished after {} frames", movie.frame());
+                    player = None;
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    player = None;
+                }
+            }
+        } else if let Some(movie) = &mut recorder {
+            movie.run_frame(&mut gameboy, read_buttons(&window));
+        } else if let Some(debugger) = &mut debugger {
+            gameboy.set_buttons(read_buttons(&window));
+            if !debugger.run_frame(&mut gameboy) {
+                break;
+            }
+        } else if let Some(stub) = &mut gdb {
+            gameboy.set_buttons(read_buttons(&window));
+            if !stub.run_frame(&mut gameboy) {
+                break;
+            }
+        } else if let Some(running) = &mut script {
+            match running.run_frame(&mut gameboy, read_buttons(&window)) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    error!("Script stopped: {}", e);
+                    script = None;
+                }
+            }
+        } else if window.is_key_down(Key::Backspace) {
+            // Rewinding would desync a movie, so it is only available in free play
+            rewind.step_back(&mut gameboy);
+        } else {
+            gameboy.set_buttons(read_buttons(&window));
+            gameboy.run_frame();
+            rewind.on_frame(&gameboy);
+        }
+        if let Some(dumper) = &mut dumper
+            && let Err(e) = dumper.on_frame(&gameboy)
+        {
+            error!("Failed to dump frame: {}", e);
         }
-        
+        if let Some(recording) = &mut clip
+            && let Err(e) = recording.add_frame(&gameboy)
+        {
+            error!("Failed to record frame: {}", e);
+            clip = None;
+        }
+
+        let memory = &gameboy.memory;
+
         // Log PPU state for debugging
         info!("PPU State - LCDC: {:02X}, BG Palette: {:02X}, SCX: {}, SCY: {}", 
               memory.ppu.lcdc, memory.ppu.bgp, memory.ppu.scx, memory.ppu.scy);
-        
-        // Check if frame buffer has any non-zero pixels (actual content)
-        let has_content = memory.ppu.frame_buffer.iter().any(|&pixel| pixel != 0);
-        
-        // Enable debug mode visualization if there's no content or if debug mode is active
-        if !has_content || debug_mode {
-            info!("Rendering debug view");
-            render_vram_debug_view(&mut memory.ppu);
-        }
-
-        // Convert Game Boy colors to ARGB and scale
-        for y in 0..SCREEN_HEIGHT {
-            for x in 0..SCREEN_WIDTH {
-                let color_idx = memory.ppu.frame_buffer[y * SCREEN_WIDTH + x] as usize;
-                let argb = palette[color_idx & 0x3]; // Ensure we stay in bounds
-
-                // Scale the pixel
-                for sy in 0..WINDOW_SCALE {
-                    for sx in 0..WINDOW_SCALE {
-                        let buffer_idx = (y * WINDOW_SCALE + sy) * (SCREEN_WIDTH * WINDOW_SCALE) + (x * WINDOW_SCALE + sx);
-                        buffer[buffer_idx] = argb;
-                    }
-                }
-            }
+
+        if let Some(viewer) = &mut vram_viewer
+            && !viewer.update(&memory.ppu, &palette.as_ref().map_or(Palette::default().bg_argb(), Palette::bg_argb), rom_path)
+        {
+            vram_viewer = None;
         }
 
+        // Convert 15-bit colors to ARGB and filter into a window-sized buffer
+        let (_, _, screen) = gameboy.screen();
+        let frame: Vec<u32> = screen.iter().map(|&c| rgb555_to_argb(c)).collect();
+        let (window_width, window_height) = window.get_size();
+        let (window_width, window_height) = (window_width.max(1), window_height.max(1));
+        buffer.resize(window_width * window_height, 0);
+        filter.render(&frame, screen_width, screen_height, &mut buffer, window_width, window_height);
+
         // Update the window with the scaled buffer
-        if let Err(e) = window.update_with_buffer(&buffer, SCREEN_WIDTH * WINDOW_SCALE, SCREEN_HEIGHT * WINDOW_SCALE) {
+        if let Err(e) = window.update_with_buffer(&buffer, window_width, window_height) {
             error!("Failed to update window: {}", e);
         }
 
-        // Toggle debug mode with D key
-        if window.is_key_pressed(Key::D, minifb::KeyRepeat::No) {
-            debug_mode = !debug_mode;
-            info!("Debug mode: {}", debug_mode);
+        // Toggle the VRAM viewer with D key
+        if window.is_key_pressed(Key::D, KeyRepeat::No) {
+            vram_viewer = match vram_viewer {
+                Some(_) => None,
+                None => VramViewer::open()
+                    .inspect_err(|e| error!("Failed to open VRAM viewer: {}", e))
+                    .ok(),
+            };
         }
+
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            toggle_trace(&mut gameboy, &trace_path);
+        }
+
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            filter.scaler = filter.scaler.next();
+            println!("Filter: {}", filter.scaler.name());
+        }
+        if window.is_key_pressed(Key::G, KeyRepeat::No) {
+            filter.lcd_grid = !filter.lcd_grid;
+            println!("LCD grid: {}", if filter.lcd_grid { "on" } else { "off" });
+        }
+        if window.is_key_pressed(Key::H, KeyRepeat::No) {
+            filter.ghosting = !filter.ghosting;
+            println!("Ghosting: {}", if filter.ghosting { "on" } else { "off" });
+        }
+
+        if window.is_key_pressed(Key::C, KeyRepeat::No) {
+            let next = palette.as_ref().and_then(Palette::preset_index).map_or(0, |i| i + 1);
+            let preset = Palette::preset_at(next);
+            println!("Palette: {}", preset.name);
+            palette = Some(preset);
+        }
+
+        // Cheats are part of a movie's setup, switching them would desync it
+        if window.is_key_pressed(Key::K, KeyRepeat::No) {
+            if recorder.is_some() || player.is_some() {
+                warn!("Cheats can't be switched while a movie is recording or playing");
+            } else {
+                let cheats = &mut gameboy.memory.cheats;
+                cheats.set_active(!cheats.is_active());
+                println!("Cheats: {}", if cheats.is_active() { "on" } else { "off" });
+            }
+        }
+
+        handle_state_hotkeys(&window, &mut gameboy, rom_path, recorder.is_some() || player.is_some());
+        take_screenshot(&window, &gameboy, (&buffer, window_width, window_height), rom_path);
+        toggle_recording(&window, &gameboy, &mut clip, rom_path);
+    }
+
+    gameboy.stop_trace();
+    if let Some(recording) = clip {
+        recording.finish()?;
+    }
+
+    if let (Some(movie), Some(path)) = (recorder, &options.record) {
+        fs::write(path, movie.finish().to_bytes())?;
+        info!("Movie saved to {}", path);
     }
 
     Ok(())
@@ -1,31 +1,183 @@
-use log::{info, error};
-use crate::ppu::Ppu;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
 
-pub struct Memory {
-    pub rom: Vec<u8>,
-    pub wram: [u8; 0x2000], // 0xC000–0xDFFF
+use crate::logging::error;
+use crate::bus::Bus;
+use crate::config::{Config, Model};
+use crate::ppu::{self, Ppu};
+use crate::serial::Serial;
+use crate::hdma::{self, Hdma};
+#[cfg(feature = "alloc")]
+use crate::sgb::Sgb;
+use crate::joypad::Joypad;
+#[cfg(feature = "alloc")]
+use crate::watch::Watchpoints;
+#[cfg(feature = "alloc")]
+use crate::cheats::Cheats;
+#[cfg(feature = "alloc")]
+use crate::savestate::{SaveState, StateError, StateReader, StateWriter};
+use crate::storage::Bytes;
+
+// Cartridge ROM as the memory map sees it. Anything that derefs to bytes is
+// one, so a ROM can be borrowed straight from flash instead of copied into
+// RAM; implement it directly for ROMs that need a driver to read.
+pub trait Rom {
+    // Byte at `offset` into the image, None past its end
+    fn byte(&self, offset: usize) -> Option<u8>;
+}
+
+impl<T: AsRef<[u8]> + ?Sized> Rom for T {
+    fn byte(&self, offset: usize) -> Option<u8> {
+        self.as_ref().get(offset).copied()
+    }
+}
+
+// Without `alloc` cartridge RAM is the 8 KiB window itself, whatever the
+// header asks for
+#[cfg(feature = "alloc")]
+type CartRam = Vec<u8>;
+#[cfg(not(feature = "alloc"))]
+type CartRam = [u8; 0x2000];
+
+#[derive(Clone)]
+pub struct Memory<R = Bytes> {
+    pub rom: R,
+    pub boot_rom: Option<Bytes>,
+    pub boot_rom_mapped: bool, // Boot ROM overlays 0x0000-0x00FF (and 0x0200-0x08FF on CGB) until 0xFF50 is written
+    pub cart_ram: CartRam, // 0xA000-0xBFFF, sized by header byte 0x149; battery-backed on some cartridges
+    pub wram: [u8; 0x8000], // 0xC000–0xDFFF, eight 4 KiB banks; 0xD000 shows bank SVBK (CGB)
+    pub svbk: u8,
+    pub double_speed: bool,       // KEY1 bit 7 (CGB)
+    pub speed_switch_armed: bool, // KEY1 bit 0, STOP switches speed when set
     pub io: [u8; 0x80],     // 0xFF00–0xFF7F
     pub hram: [u8; 0x7F],   // 0xFF80-0xFFFE
     pub ie: u8,             // 0xFFFF - Interrupt Enable
     pub if_: u8,            // 0xFF0F - Interrupt Flag
     pub ppu: Ppu,
+    pub serial: Serial,
+    pub hdma: Hdma,
+    pub dma_stall: u32, // CPU cycles the CPU stays halted for a VRAM DMA
+    pub joypad: Joypad,
+    #[cfg(feature = "alloc")]
+    pub sgb: Option<Sgb>, // Present when running as a Super Game Boy
+    #[cfg(feature = "alloc")]
+    pub watchpoints: Watchpoints, // Debugger hooks, not part of the emulated state
+    #[cfg(feature = "alloc")]
+    pub cheats: Cheats,           // Neither are cheats
 }
 
+#[cfg(feature = "alloc")]
 impl Memory {
-    pub fn new(rom_data: &Vec<u8>) -> Self {
+    pub fn new(rom_data: &[u8]) -> Self {
+        Self::with_config(rom_data, &Config::default())
+    }
+
+    // Copies the ROM; see `from_rom` to keep it where it is
+    pub fn with_config(rom_data: &[u8], config: &Config) -> Self {
+        Self::from_rom(rom_data.to_vec(), config)
+    }
+
+    // Takes over the heap buffers of `old` that embedders hold pointers to
+    // (ROM, cartridge RAM, VRAM, OAM), filled with this memory's contents,
+    // so the pointers stay valid once this memory replaces `old`. Both must
+    // run the same cartridge.
+    pub fn reuse_buffers(&mut self, old: &mut Memory) {
+        core::mem::swap(&mut self.rom, &mut old.rom);
+        for (new, old) in [
+            (&mut self.cart_ram, &mut old.cart_ram),
+            (&mut self.ppu.vram, &mut old.ppu.vram),
+            (&mut self.ppu.oam, &mut old.ppu.oam),
+        ] {
+            old.copy_from_slice(new);
+            core::mem::swap(new, old);
+        }
+    }
+}
+
+impl<R: Rom> Memory<R> {
+    // The boot ROM is only borrowed without `alloc`, and cloning it is a copy
+    #[cfg_attr(not(feature = "alloc"), allow(clippy::clone_on_copy))]
+    pub fn from_rom(rom: R, config: &Config) -> Self {
+        #[cfg(feature = "alloc")]
+        let cart_ram = alloc::vec![0; cart_ram_size(rom.byte(0x149).unwrap_or(0))];
+        #[cfg(not(feature = "alloc"))]
+        let cart_ram = [0; 0x2000];
+        let cgb_cartridge = rom.byte(0x143).is_some_and(|flag| flag & 0x80 != 0);
+
         let mut memory = Memory {
-            rom: rom_data.clone(),
-            wram: [0; 0x2000],
+            #[cfg(feature = "alloc")]
+            sgb: (config.model == Model::Sgb).then(|| Sgb::new(&rom)),
+            rom,
+            boot_rom: config.boot_rom.clone(),
+            boot_rom_mapped: config.boot_rom.is_some(),
+            cart_ram,
+            wram: [0; 0x8000],
+            svbk: 0,
+            double_speed: false,
+            speed_switch_armed: false,
             io: [0; 0x80],
             hram: [0; 0x7F],
             ie: 0,
             if_: 0,
             ppu: Ppu::new(),
+            serial: Serial::new(),
+            hdma: Hdma::new(),
+            dma_stall: 0,
+            joypad: Joypad::new(),
+            #[cfg(feature = "alloc")]
+            watchpoints: Watchpoints::new(),
+            #[cfg(feature = "alloc")]
+            cheats: Cheats::new(),
         };
 
+        config.vram_init.fill(&mut memory.ppu.vram);
+
+        // The boot ROM sets up the hardware itself. A CGB starts in CGB mode
+        // and its boot ROM drops to DMG compatibility through KEY0.
+        if memory.boot_rom_mapped {
+            memory.ppu.power_on();
+            memory.ppu.cgb_mode = config.model == Model::Cgb;
+            return memory;
+        }
+
+        // Header byte 0x143 bit 7 marks cartridges with CGB support
+        if config.model == Model::Cgb {
+            if cgb_cartridge {
+                memory.ppu.cgb_mode = true;
+                memory.ppu.bg_palette_ram = [0xFF; 64];
+            } else {
+                // DMG compatibility, colored like the boot ROM would
+                let mut header = [0; 0x50];
+                for (i, byte) in header.iter_mut().enumerate() {
+                    *byte = memory.rom.byte(0x100 + i).unwrap_or(0xFF);
+                }
+                let [bg, obj0, obj1] = ppu::dmg_compat_palettes(&header);
+                memory.ppu.set_dmg_palettes(&bg, &obj0, &obj1);
+            }
+        }
+
+        // Registers the boot ROM leaves behind differ by model: the DMG0 boot
+        // ROM hands over in VBlank, and a CGB's SC has the fast clock bit.
+        // DIV is left out where it depends on how long the SGB and CGB boot
+        // ROMs ran.
+        let (div, ly, stat, sc) = match config.model {
+            Model::Dmg0 => (Some(0x18), 0x91, 0x81, 0x7E),
+            Model::Dmg | Model::Mgb => (Some(0xAB), 0x00, 0x85, 0x7E),
+            Model::Sgb => (None, 0x00, 0x85, 0x7E),
+            Model::Cgb => (None, 0x00, 0x85, 0x7F),
+        };
+        if let Some(div) = div {
+            memory.io[0x04] = div;
+        }
+        memory.ppu.line = ly;
+        if ly >= 144 {
+            memory.ppu.mode = 1;
+        }
+
         // Initialize important registers to post-bootrom values
+        memory.write(0xFF02, sc);    // SC - serial control, no transfer
         memory.write(0xFF40, 0x91);  // LCDC - LCD on, BG enabled
-        memory.write(0xFF41, 0x85);  // STAT
+        memory.write(0xFF41, stat);  // STAT
         memory.write(0xFF42, 0x00);  // SCY - Scroll Y
         memory.write(0xFF43, 0x00);  // SCX - Scroll X
         memory.write(0xFF45, 0x00);  // LYC
@@ -37,77 +189,41 @@ impl Memory {
         memory.write(0xFF0F, 0xE1);  // IF - Interrupt flag (V-blank enabled)
         memory.write(0xFFFF, 0x01);  // IE - VBlank interrupt enabled
         
-        // Create some test pattern tiles for VRAM at the beginning of the tile data area
-        
-        // Tile 0: Solid filled tile
-        for i in 0..16 {
-            memory.ppu.vram[i] = 0xFF;
-        }
-        
-        // Tile 1: Checkerboard pattern
-        for i in 0..8 {
-            let pattern = if i % 2 == 0 { 0xAA } else { 0x55 };
-            memory.ppu.vram[16 + i*2] = pattern;
-            memory.ppu.vram[16 + i*2 + 1] = pattern;
-        }
-        
-        // Tile 2: Border pattern
-        for i in 0..8 {
-            if i == 0 || i == 7 {
-                memory.ppu.vram[32 + i*2] = 0xFF;     // Top and bottom rows filled
-                memory.ppu.vram[32 + i*2 + 1] = 0xFF;
-            } else {
-                memory.ppu.vram[32 + i*2] = 0x81;     // Sides only
-                memory.ppu.vram[32 + i*2 + 1] = 0x81;
-            }
-        }
-        
-        // Tile 3: Diagonal pattern
-        for i in 0..8 {
-            memory.ppu.vram[48 + i*2] = 1 << i;      // Diagonal from top-left to bottom-right
-            memory.ppu.vram[48 + i*2 + 1] = 1 << i;
-        }
-        
-        // Set up the background tile map to show these test patterns
-        let start_map_addr = 0x1800;  // Start of first background map (0x9800 in GB memory)
-        
-        // Create a recognizable pattern in the tile map
-        for y in 0..32 {
-            for x in 0..32 {
-                let tile_idx = ((x + y) % 4) as u8; // Cycle through our 4 test tiles
-                memory.ppu.vram[start_map_addr + y*32 + x] = tile_idx;
-            }
-        }
-        
-        // Try to copy Nintendo logo data from ROM to VRAM (from 0x0104-0x0133)
-        if rom_data.len() >= 0x134 {
-            let logo_start = 0x104;
-            let vram_offset = 0x100; // Place logo tiles at a visible position in VRAM
-            
-            // Copy the Nintendo logo bitmap pattern
-            for i in 0..48 {
-                if logo_start + i < rom_data.len() {
-                    memory.ppu.vram[vram_offset + i] = rom_data[logo_start + i];
-                }
-            }
-            
-            // Place the logo tiles in a visible position in the background map
-            for i in 0..12 {
-                memory.ppu.vram[start_map_addr + 32*5 + 10 + i] = 0x10 + i as u8; // Use tiles 0x10-0x1B for logo
-            }
-        }
-
         memory
     }
 
     pub fn read(&self, addr: u16) -> u8 {
+        #[cfg_attr(not(feature = "alloc"), allow(unused_mut))]
+        let mut value = self.peek(addr);
+        #[cfg(feature = "alloc")]
+        {
+            if addr < 0x8000 {
+                value = self.cheats.patch_rom(addr, value); // Game Genie
+            }
+            self.watchpoints.on_read(addr, value);
+        }
+        value
+    }
+
+    // Read without side effects, for debuggers and disassemblers
+    pub fn peek(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x7FFF => self.rom[addr as usize],
-            0x8000..=0x9FFF => self.ppu.vram[(addr - 0x8000) as usize],
-            0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize],
+            0x0000..=0x00FF if self.boot_rom_mapped => self.boot_rom.as_ref().and_then(|b| b.get(addr as usize)).copied().unwrap_or(0xFF),
+            // Only a CGB boot ROM is long enough to reach past the cartridge header
+            0x0200..=0x08FF if self.boot_rom_mapped && self.boot_rom.as_ref().is_some_and(|b| b.len() > addr as usize) => {
+                self.boot_rom.as_ref().map_or(0xFF, |b| b[addr as usize])
+            }
+            0x0000..=0x7FFF => self.rom.byte(addr as usize).unwrap_or(0xFF),
+            0x8000..=0x9FFF => self.ppu.vram[self.vram_offset() + (addr - 0x8000) as usize],
+            0xA000..=0xBFFF => self.cart_ram.get((addr - 0xA000) as usize).copied().unwrap_or(0xFF),
+            0xC000..=0xCFFF => self.wram[(addr - 0xC000) as usize],
+            0xD000..=0xDFFF => self.wram[self.wram_offset() + (addr - 0xD000) as usize],
             0xFE00..=0xFE9F => self.ppu.oam[(addr - 0xFE00) as usize],
             0xFF00..=0xFF7F => {
                 match addr {
+                    0xFF00 => self.read_joypad(),    // Joypad
+                    0xFF01 => self.serial.sb,        // Serial transfer data
+                    0xFF02 => self.serial.read_sc(), // Serial transfer control
                     0xFF0F => self.if_,    // Interrupt Flag
                     0xFF40 => self.ppu.lcdc, // LCD Control
                     0xFF41 => self.ppu.stat, // LCD Status
@@ -119,6 +235,16 @@ impl Memory {
                     0xFF49 => self.ppu.obp1, // Object Palette 1
                     0xFF4A => self.ppu.wy,   // Window Y position
                     0xFF4B => self.ppu.wx,   // Window X position
+                    0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 if !self.ppu.cgb_mode => 0xFF,
+                    0xFF51..=0xFF54 => 0xFF, // HDMA1-4 are write-only
+                    0xFF55 => self.hdma.read_hdma5(), // HDMA5 - remaining length
+                    0xFF4D => 0x7E | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8, // KEY1
+                    0xFF4F => 0xFE | self.ppu.vram_bank, // VBK - VRAM bank
+                    0xFF68 => 0x40 | self.ppu.bcps,  // BCPS - BG palette index
+                    0xFF69 => self.ppu.read_bcpd(),  // BCPD - BG palette data
+                    0xFF6A => 0x40 | self.ppu.ocps,  // OCPS - OBJ palette index
+                    0xFF6B => self.ppu.read_ocpd(),  // OCPD - OBJ palette data
+                    0xFF70 => 0xF8 | self.svbk,      // SVBK - WRAM bank
                     _ => self.io[(addr - 0xFF00) as usize],
                 }
             }
@@ -131,13 +257,46 @@ impl Memory {
         }
     }
 
+    // The SGB answers joypad reads itself when it polls several players
+    fn read_joypad(&self) -> u8 {
+        #[cfg(feature = "alloc")]
+        if let Some(sgb) = &self.sgb {
+            return sgb.read_joypad(&self.joypad);
+        }
+        self.joypad.read()
+    }
+
     pub fn write(&mut self, addr: u16, value: u8) {
+        #[cfg(feature = "alloc")]
+        self.watchpoints.on_write(addr, value, || self.peek(addr));
         match addr {
-            0x8000..=0x9FFF => self.ppu.vram[(addr - 0x8000) as usize] = value,
-            0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize] = value,
+            0x8000..=0x9FFF => {
+                let offset = self.vram_offset();
+                self.ppu.vram[offset + (addr - 0x8000) as usize] = value;
+            }
+            0xA000..=0xBFFF => {
+                if let Some(byte) = self.cart_ram.get_mut((addr - 0xA000) as usize) {
+                    *byte = value;
+                }
+            }
+            0xC000..=0xCFFF => self.wram[(addr - 0xC000) as usize] = value,
+            0xD000..=0xDFFF => {
+                let offset = self.wram_offset();
+                self.wram[offset + (addr - 0xD000) as usize] = value;
+            }
             0xFE00..=0xFE9F => self.ppu.oam[(addr - 0xFE00) as usize] = value,
             0xFF00..=0xFF7F => {
                 match addr {
+                    0xFF00 => {
+                        // Joypad, which also carries SGB command packets
+                        self.joypad.write(value);
+                        #[cfg(feature = "alloc")]
+                        if let Some(sgb) = &mut self.sgb {
+                            sgb.write_joypad(value);
+                        }
+                    }
+                    0xFF01 => self.serial.sb = value,       // Serial transfer data
+                    0xFF02 => self.serial.write_sc(value),  // Serial transfer control
                     0xFF0F => self.if_ = value, // Interrupt Flag
                     0xFF40 => self.ppu.lcdc = value, // LCD Control
                     0xFF41 => self.ppu.stat = value, // LCD Status
@@ -148,6 +307,42 @@ impl Memory {
                     0xFF49 => self.ppu.obp1 = value, // Object Palette 1
                     0xFF4A => self.ppu.wy = value,   // Window Y position
                     0xFF4B => self.ppu.wx = value,   // Window X position
+                    0xFF4C => {
+                        // KEY0: the CGB boot ROM selects DMG compatibility here
+                        if self.boot_rom_mapped && value & 0x04 != 0 {
+                            self.ppu.cgb_mode = false;
+                        }
+                    }
+                    0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 if !self.ppu.cgb_mode => {}
+                    0xFF51 => self.hdma.write_src_high(value), // HDMA1 - source high
+                    0xFF52 => self.hdma.write_src_low(value),  // HDMA2 - source low
+                    0xFF53 => self.hdma.write_dst_high(value), // HDMA3 - destination high
+                    0xFF54 => self.hdma.write_dst_low(value),  // HDMA4 - destination low
+                    0xFF55 => {
+                        // HDMA5: a general purpose transfer runs to completion now
+                        if let Some(blocks) = self.hdma.write_hdma5(value) {
+                            for _ in 0..blocks {
+                                self.copy_hdma_block();
+                            }
+                        } else if self.hdma.hblank_active && self.ppu.lcdc & 0x80 != 0 && self.ppu.mode == 0 {
+                            // Started during HBlank: this one gets its block too
+                            self.copy_hdma_block();
+                        }
+                    }
+                    0xFF4D => self.speed_switch_armed = value & 0x01 != 0, // KEY1
+                    0xFF4F => self.ppu.vram_bank = value & 0x01, // VBK - VRAM bank
+                    0xFF68 => self.ppu.bcps = value & 0xBF,      // BCPS - BG palette index
+                    0xFF69 => self.ppu.write_bcpd(value),        // BCPD - BG palette data
+                    0xFF6A => self.ppu.ocps = value & 0xBF,      // OCPS - OBJ palette index
+                    0xFF6B => self.ppu.write_ocpd(value),        // OCPD - OBJ palette data
+                    0xFF70 => self.svbk = value & 0x07,          // SVBK - WRAM bank
+                    0xFF50 => {
+                        // Any write unmaps the boot ROM until the next reset
+                        if value != 0 {
+                            self.boot_rom_mapped = false;
+                        }
+                        self.io[0x50] = value;
+                    }
                     _ => self.io[(addr - 0xFF00) as usize] = value,
                 }
             }
@@ -157,13 +352,112 @@ impl Memory {
         }
     }
 
+    // ROM bank mapped at `addr`. Without a memory bank controller the
+    // cartridge is a flat 32K image: bank 0 below 0x4000, bank 1 above.
+    pub fn rom_bank_at(&self, addr: u16) -> u16 {
+        if addr < 0x4000 { 0 } else { 1 }
+    }
+
+    // Offset into `ppu.vram` of the bank selected by VBK
+    fn vram_offset(&self) -> usize {
+        self.ppu.vram_bank as usize * 0x2000
+    }
+
+    // Offset into `wram` of the bank at 0xD000; SVBK 0 selects bank 1
+    fn wram_offset(&self) -> usize {
+        self.svbk.max(1) as usize * 0x1000
+    }
+
+    // STOP with KEY1 armed toggles CGB double speed. Returns whether it did.
+    pub fn try_speed_switch(&mut self) -> bool {
+        if !self.ppu.cgb_mode || !self.speed_switch_armed {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        true
+    }
+
+    // Converts CPU cycles to cycles of the normal-speed clock the PPU runs on.
+    // Instructions always take a multiple of 4 cycles, so halving is exact.
+    pub fn normal_speed_cycles(&self, cycles: u8) -> u8 {
+        if self.double_speed { cycles / 2 } else { cycles }
+    }
+
+    // Copy one 16-byte VRAM DMA block and halt the CPU for it
+    fn copy_hdma_block(&mut self) {
+        let (src, dst) = self.hdma.next_block();
+        let offset = self.vram_offset() + dst as usize;
+        for i in 0..16 {
+            let addr = src.wrapping_add(i as u16);
+            self.ppu.vram[offset + i] = match addr {
+                // VRAM can't be read while it's being written to
+                0x8000..=0x9FFF => 0xFF,
+                // The top 8K reads from cartridge RAM, not echo RAM and IO
+                0xE000..=0xFFFF => self.peek(addr - 0x4000),
+                _ => self.peek(addr),
+            };
+        }
+        let cycles = if self.double_speed { hdma::BLOCK_CYCLES * 2 } else { hdma::BLOCK_CYCLES };
+        self.dma_stall += cycles;
+    }
+
     pub fn step_ppu(&mut self, cycles: u8) {
-        self.ppu.step(cycles as u32);
+        self.ppu.step(self.normal_speed_cycles(cycles) as u32);
+
+        // HBlank DMA moves one block per HBlank while the LCD is on
+        if self.ppu.hblank_started {
+            self.ppu.hblank_started = false;
+            if self.hdma.hblank_active && self.ppu.lcdc & 0x80 != 0 {
+                self.copy_hdma_block();
+            }
+        }
         
         // Check if VBlank interrupt was triggered
         if self.ppu.vblank_interrupt {
             self.if_ |= 0x01; // Set VBlank interrupt flag
             self.ppu.vblank_interrupt = false; // Reset the flag
+
+            #[cfg(feature = "alloc")]
+            {
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.on_vblank(&self.ppu.frame_buffer);
+                }
+                self.apply_game_shark();
+            }
+        }
+    }
+
+    // GameShark codes rewrite their RAM bytes once per frame, at VBlank
+    #[cfg(feature = "alloc")]
+    fn apply_game_shark(&mut self) {
+        // Indexed, as writing borrows all of `self`
+        for i in 0..self.cheats.ram_writes().len() {
+            let (bank, addr, value) = self.cheats.ram_writes()[i];
+            match (bank, addr) {
+                (Some(bank), 0xD000..=0xDFFF) => {
+                    self.wram[bank.max(1) as usize * 0x1000 + (addr - 0xD000) as usize] = value;
+                }
+                _ => self.write(addr, value),
+            }
+        }
+    }
+
+    pub fn step_serial(&mut self, cycles: u8) {
+        self.serial.step(cycles as u32);
+
+        if self.serial.interrupt {
+            self.if_ |= 0x08; // Set Serial interrupt flag
+            self.serial.interrupt = false;
+        }
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.joypad.set_buttons(buttons);
+
+        if self.joypad.interrupt {
+            self.if_ |= 0x10; // Set Joypad interrupt flag
+            self.joypad.interrupt = false;
         }
     }
     
@@ -206,4 +500,115 @@ impl Memory {
         
         false
     }
-} 
\ No newline at end of file
+}
+
+// The CPU's view of the memory map. Reads go through cheats and
+// watchpoints like any other read.
+impl<R: Rom> Bus for Memory<R> {
+    fn read(&mut self, addr: u16) -> u8 {
+        Memory::read(self, addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        Memory::write(self, addr, value);
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.step_ppu(cycles);
+        self.step_serial(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.if_ & self.ie & 0x1F
+    }
+
+    // A VRAM DMA halts the CPU for as long as it copies; the stall is
+    // handed out in chunks small enough for a step, kept a multiple of 4
+    fn take_stall(&mut self) -> u8 {
+        let cycles = self.dma_stall.min(128) as u8;
+        self.dma_stall -= cycles as u32;
+        cycles
+    }
+
+    fn stop(&mut self) {
+        self.try_speed_switch();
+    }
+}
+
+// Cartridge RAM size for header byte 0x149. Without a memory bank
+// controller only the first 8 KiB can be reached.
+#[cfg(feature = "alloc")]
+fn cart_ram_size(code: u8) -> usize {
+    match code {
+        0x01 => 0x800,
+        0x02 => 0x2000,
+        0x03 => 0x8000,
+        0x04 => 0x20000,
+        0x05 => 0x10000,
+        _ => 0,
+    }
+}
+
+// Covers the memory map itself; the PPU, serial port and joypad are saved as their own chunks
+#[cfg(feature = "alloc")]
+impl<R: Rom> SaveState for Memory<R> {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.wram);
+        w.bytes(&self.io);
+        w.bytes(&self.hram);
+        w.u8(self.ie);
+        w.u8(self.if_);
+        w.bool(self.boot_rom_mapped);
+        w.u8(self.svbk);
+        w.bool(self.double_speed);
+        w.bool(self.speed_switch_armed);
+        w.u32(self.dma_stall);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.wram, "WRAM size")?;
+        r.bytes_into(&mut self.io, "IO size")?;
+        r.bytes_into(&mut self.hram, "HRAM size")?;
+        self.ie = r.u8()?;
+        self.if_ = r.u8()?;
+        self.boot_rom_mapped = r.bool()? && self.boot_rom.is_some();
+        self.svbk = r.u8()? & 0x07;
+        self.double_speed = r.bool()?;
+        self.speed_switch_armed = r.bool()?;
+        self.dma_stall = r.u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post_boot(model: Model) -> Memory {
+        Memory::with_config(&[0; 0x8000], &Config { model, ..Config::default() })
+    }
+
+    #[test]
+    fn post_boot_registers_follow_the_model() {
+        // (DIV, LY, STAT, SC) as each model's boot ROM leaves them
+        let dmg0 = post_boot(Model::Dmg0);
+        assert_eq!([0xFF04, 0xFF44, 0xFF41, 0xFF02].map(|addr| dmg0.peek(addr)), [0x18, 0x91, 0x81, 0x7E]);
+        for model in [Model::Dmg, Model::Mgb] {
+            let memory = post_boot(model);
+            assert_eq!([0xFF04, 0xFF44, 0xFF41, 0xFF02].map(|addr| memory.peek(addr)), [0xAB, 0x00, 0x85, 0x7E]);
+        }
+        assert_eq!(post_boot(Model::Sgb).peek(0xFF02), 0x7E);
+        assert_eq!(post_boot(Model::Cgb).peek(0xFF02), 0x7F);
+        assert_eq!(post_boot(Model::Cgb).peek(0xFF40), 0x91);
+    }
+
+    #[test]
+    fn dmg0_hands_over_in_vblank() {
+        let mut memory = post_boot(Model::Dmg0);
+        assert_eq!(memory.ppu.mode, 1);
+        while memory.ppu.line != 0 {
+            memory.step_ppu(4);
+        }
+        assert_eq!(memory.ppu.mode, 2);
+    }
+}
@@ -0,0 +1,550 @@
+// Input movies: per-frame joypad state that replays a session exactly
+//
+// Native layout (all integers little-endian):
+//   "GBMV"            magic
+//   u16               format version
+//   u32               CRC-32 of the ROM
+//   u32               checkpoint interval in frames (0 = no checkpoints)
+//   u8                console model (see MODELS)
+//   u8, u32           VRAM init: 0 zero, 1 random with the seed that follows
+//   u8, u32           1 and the boot ROM's CRC-32 if one ran, else 0 and 0
+//   u32 + bytes       active cheats in `.cht` form
+//   u32 + bytes       start state (empty = power-on)
+//   u32 + bytes       one joypad byte per frame (see `joypad::BUTTON_*`)
+//   u32               checkpoint count, then (frame: u32, state CRC: u32) pairs
+//
+// A checkpoint is the CRC-32 of the full save state after the given frame, so
+// any divergence in CPU, memory or PPU state is caught within one interval.
+// The power-on settings and cheats aren't in the save state, so the header
+// carries them. The boot ROM itself is not stored; playback needs the same
+// image.
+//
+// `Movie::parse` also accepts VBM files and BizHawk BK2 input logs
+// ("Input Log.txt" extracted from the .bk2 archive). Those carry no ROM
+// checksum or checkpoints, so playback trusts them blindly.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::cheats::Cheats;
+use crate::checksum::crc32;
+use crate::config::{Config, Model, RamInit};
+use crate::gameboy::GameBoy;
+use crate::joypad::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+pub const MOVIE_MAGIC: &[u8; 4] = b"GBMV";
+pub const MOVIE_VERSION: u16 = 2;
+pub const DEFAULT_CHECKPOINT_INTERVAL: u32 = 60;
+
+const VBM_MAGIC: &[u8; 4] = b"VBM\x1A";
+
+// Models by their number in the header
+const MODELS: [Model; 5] = [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Cgb];
+
+#[derive(Debug)]
+pub enum MovieError {
+    BadFormat(&'static str),
+    UnsupportedVersion(u16),
+    RomMismatch { expected: u32, found: u32 },
+    SetupMismatch(&'static str),
+    State(StateError),
+    Desync { frame: u32 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::BadFormat(what) => write!(f, "invalid movie: {}", what),
+            MovieError::UnsupportedVersion(v) => {
+                write!(f, "unsupported movie version {} (expected {})", v, MOVIE_VERSION)
+            }
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "movie was recorded on a different ROM (CRC {:08X}, loaded ROM is {:08X})",
+                found, expected
+            ),
+            MovieError::SetupMismatch(what) => write!(f, "movie was recorded with a different {}", what),
+            MovieError::State(e) => write!(f, "movie start state: {}", e),
+            MovieError::Desync { frame } => write!(f, "movie desynced at frame {}", frame),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(e: StateError) -> Self {
+        MovieError::State(e)
+    }
+}
+
+// Everything besides the ROM that decides how a console runs from power-on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MovieSetup {
+    pub model: Model,
+    pub vram_init: RamInit,
+    pub boot_rom_crc: Option<u32>,
+    pub cheats: String, // Active cheats in `.cht` form, empty when switched off
+}
+
+impl MovieSetup {
+    // The setup `gameboy` runs with
+    pub fn of(gameboy: &GameBoy) -> Self {
+        let config = gameboy.config();
+        let cheats = &gameboy.memory.cheats;
+        MovieSetup {
+            model: config.model,
+            vram_init: config.vram_init,
+            boot_rom_crc: config.boot_rom.as_deref().map(crc32),
+            cheats: if cheats.is_active() { cheats.to_text() } else { String::new() },
+        }
+    }
+
+    // Makes `config` power on like the recording did. The boot ROM is not
+    // part of the movie, so the one in `config` has to match.
+    pub fn apply(&self, config: &mut Config) -> Result<(), MovieError> {
+        if config.boot_rom.as_deref().map(crc32) != self.boot_rom_crc {
+            return Err(MovieError::SetupMismatch("boot ROM"));
+        }
+        config.model = self.model;
+        config.vram_init = self.vram_init;
+        Ok(())
+    }
+}
+
+pub struct Movie {
+    pub rom_crc: Option<u32>,        // None for imported logs
+    pub setup: Option<MovieSetup>,   // None for imported logs
+    pub start_state: Option<Vec<u8>>, // None = power-on
+    pub inputs: Vec<u8>,
+    pub checkpoint_interval: u32,
+    pub checkpoints: Vec<(u32, u32)>,
+}
+
+impl Movie {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        for &b in MOVIE_MAGIC {
+            w.u8(b);
+        }
+        w.u16(MOVIE_VERSION);
+        w.u32(self.rom_crc.unwrap_or(0));
+        w.u32(self.checkpoint_interval);
+        let setup = self.setup.as_ref();
+        let model = setup.map_or(Model::default(), |s| s.model);
+        w.u8(MODELS.iter().position(|&m| m == model).unwrap() as u8);
+        match setup.map_or(RamInit::Zero, |s| s.vram_init) {
+            RamInit::Zero => {
+                w.u8(0);
+                w.u32(0);
+            }
+            RamInit::Random { seed } => {
+                w.u8(1);
+                w.u32(seed);
+            }
+        }
+        let boot_rom_crc = setup.and_then(|s| s.boot_rom_crc);
+        w.bool(boot_rom_crc.is_some());
+        w.u32(boot_rom_crc.unwrap_or(0));
+        w.bytes(setup.map_or("", |s| &s.cheats).as_bytes());
+        w.bytes(self.start_state.as_deref().unwrap_or(&[]));
+        w.bytes(&self.inputs);
+        w.u32(self.checkpoints.len() as u32);
+        for &(frame, hash) in &self.checkpoints {
+            w.u32(frame);
+            w.u32(hash);
+        }
+        w.into_bytes()
+    }
+
+    // Detects the format from the file contents
+    pub fn parse(data: &[u8]) -> Result<Movie, MovieError> {
+        if data.starts_with(MOVIE_MAGIC) {
+            Self::parse_native(data)
+        } else if data.starts_with(VBM_MAGIC) {
+            Self::import_vbm(data)
+        } else {
+            let text = std::str::from_utf8(data).map_err(|_| MovieError::BadFormat("unknown format"))?;
+            Self::import_bk2_log(text)
+        }
+    }
+
+    fn parse_native(data: &[u8]) -> Result<Movie, MovieError> {
+        let truncated = |_| MovieError::BadFormat("truncated");
+        let mut r = StateReader::new(&data[4..]);
+        let version = r.u16().map_err(truncated)?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_crc = r.u32().map_err(truncated)?;
+        let checkpoint_interval = r.u32().map_err(truncated)?;
+        let model = *MODELS
+            .get(r.u8().map_err(truncated)? as usize)
+            .ok_or(MovieError::BadFormat("unknown model"))?;
+        let vram_init = match (r.u8().map_err(truncated)?, r.u32().map_err(truncated)?) {
+            (0, _) => RamInit::Zero,
+            (1, seed) => RamInit::Random { seed },
+            _ => return Err(MovieError::BadFormat("unknown VRAM init")),
+        };
+        let has_boot_rom = r.bool().map_err(truncated)?;
+        let boot_rom_crc = r.u32().map_err(truncated)?;
+        let cheats = std::str::from_utf8(r.bytes().map_err(truncated)?)
+            .map_err(|_| MovieError::BadFormat("cheats are not text"))?
+            .to_string();
+        let start_state = r.bytes().map_err(truncated)?;
+        let inputs = r.bytes().map_err(truncated)?.to_vec();
+        let count = r.u32().map_err(truncated)?;
+        let mut checkpoints = Vec::new();
+        for _ in 0..count {
+            checkpoints.push((r.u32().map_err(truncated)?, r.u32().map_err(truncated)?));
+        }
+
+        Ok(Movie {
+            rom_crc: Some(rom_crc),
+            setup: Some(MovieSetup {
+                model,
+                vram_init,
+                boot_rom_crc: has_boot_rom.then_some(boot_rom_crc),
+                cheats,
+            }),
+            start_state: (!start_state.is_empty()).then(|| start_state.to_vec()),
+            inputs,
+            checkpoint_interval,
+            checkpoints,
+        })
+    }
+
+    // VisualBoyAdvance movie. Its per-frame button word uses the same bit order as ours.
+    pub fn import_vbm(data: &[u8]) -> Result<Movie, MovieError> {
+        if data.len() < 0x100 || !data.starts_with(VBM_MAGIC) {
+            return Err(MovieError::BadFormat("VBM header"));
+        }
+        let u32_at = |off: usize| u32::from_le_bytes(data[off..off + 4].try_into().unwrap());
+
+        let frames = u32_at(0x0C) as usize;
+        if data[0x14] & 0x03 != 0 {
+            return Err(MovieError::BadFormat("VBM movies starting from a snapshot or SRAM are not supported"));
+        }
+        let controllers = (data[0x15] & 0x0F).count_ones() as usize;
+        if controllers == 0 {
+            return Err(MovieError::BadFormat("VBM movie has no controllers"));
+        }
+        let input_offset = u32_at(0x3C) as usize;
+
+        // The frame count comes from the file; make sure the data is there
+        // before trusting it
+        let available = data.len().saturating_sub(input_offset);
+        if frames.checked_mul(controllers * 2).is_none_or(|len| len > available) {
+            return Err(MovieError::BadFormat("VBM input data is truncated"));
+        }
+
+        let mut inputs = Vec::with_capacity(frames);
+        for frame in 0..frames {
+            // Only the first controller drives a single console
+            let off = input_offset + frame * controllers * 2;
+            inputs.push(data[off]);
+        }
+
+        Ok(Movie {
+            rom_crc: None,
+            setup: None,
+            start_state: None,
+            inputs,
+            checkpoint_interval: 0,
+            checkpoints: Vec::new(),
+        })
+    }
+
+    // BizHawk input log. Column order comes from the LogKey line when present.
+    pub fn import_bk2_log(text: &str) -> Result<Movie, MovieError> {
+        let mut columns = vec![BUTTON_UP, BUTTON_DOWN, BUTTON_LEFT, BUTTON_RIGHT, BUTTON_START, BUTTON_SELECT, BUTTON_B, BUTTON_A, 0];
+        let mut inputs = Vec::new();
+
+        for line in text.lines().map(str::trim) {
+            if let Some(key) = line.strip_prefix("LogKey:") {
+                columns = key
+                    .split(['|', '#'])
+                    .filter(|name| !name.is_empty())
+                    .map(|name| match name.trim_start_matches("P1 ") {
+                        "Up" => BUTTON_UP,
+                        "Down" => BUTTON_DOWN,
+                        "Left" => BUTTON_LEFT,
+                        "Right" => BUTTON_RIGHT,
+                        "Start" => BUTTON_START,
+                        "Select" => BUTTON_SELECT,
+                        "B" => BUTTON_B,
+                        "A" => BUTTON_A,
+                        _ => 0, // Power, Reset and other non-joypad columns
+                    })
+                    .collect();
+                continue;
+            }
+
+            if !line.starts_with('|') {
+                continue;
+            }
+            let flags: String = line.chars().filter(|&c| c != '|').collect();
+            let mut buttons = 0;
+            for (c, &bit) in flags.chars().zip(&columns) {
+                if c != '.' && c != ' ' {
+                    buttons |= bit;
+                }
+            }
+            inputs.push(buttons);
+        }
+
+        if inputs.is_empty() {
+            return Err(MovieError::BadFormat("no input frames found"));
+        }
+
+        Ok(Movie {
+            rom_crc: None,
+            setup: None,
+            start_state: None,
+            inputs,
+            checkpoint_interval: 0,
+            checkpoints: Vec::new(),
+        })
+    }
+}
+
+// Hash used for desync detection
+fn state_hash(gameboy: &GameBoy) -> u32 {
+    crc32(&gameboy.save_state())
+}
+
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    // Start recording from a console that has just been created
+    pub fn from_power_on(gameboy: &GameBoy) -> Self {
+        Self::new(gameboy, None)
+    }
+
+    // Start recording mid-session; the current state is embedded in the movie
+    pub fn from_current_state(gameboy: &GameBoy) -> Self {
+        Self::new(gameboy, Some(gameboy.save_state()))
+    }
+
+    fn new(gameboy: &GameBoy, start_state: Option<Vec<u8>>) -> Self {
+        MovieRecorder {
+            movie: Movie {
+                rom_crc: Some(gameboy.rom_crc()),
+                setup: Some(MovieSetup::of(gameboy)),
+                start_state,
+                inputs: Vec::new(),
+                checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+                checkpoints: Vec::new(),
+            },
+        }
+    }
+
+    // Applies `buttons`, runs one frame and records it
+    pub fn run_frame(&mut self, gameboy: &mut GameBoy, buttons: u8) {
+        gameboy.set_buttons(buttons);
+        gameboy.run_frame();
+        self.movie.inputs.push(buttons);
+
+        let frame = self.movie.inputs.len() as u32;
+        if frame.is_multiple_of(self.movie.checkpoint_interval) {
+            self.movie.checkpoints.push((frame, state_hash(gameboy)));
+        }
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: u32,
+    next_checkpoint: usize,
+}
+
+impl MoviePlayer {
+    // Prepares `gameboy` for playback and loads the movie's cheats into it.
+    // Movies recorded from power-on expect a console that has just been
+    // created with the config `MovieSetup::apply` gives.
+    pub fn new(movie: Movie, gameboy: &mut GameBoy) -> Result<Self, MovieError> {
+        if let Some(crc) = movie.rom_crc
+            && crc != gameboy.rom_crc()
+        {
+            return Err(MovieError::RomMismatch {
+                expected: gameboy.rom_crc(),
+                found: crc,
+            });
+        }
+        if let Some(setup) = &movie.setup {
+            let current = MovieSetup::of(gameboy);
+            if current.model != setup.model {
+                return Err(MovieError::SetupMismatch("model"));
+            }
+            if current.vram_init != setup.vram_init {
+                return Err(MovieError::SetupMismatch("VRAM init"));
+            }
+            if current.boot_rom_crc != setup.boot_rom_crc {
+                return Err(MovieError::SetupMismatch("boot ROM"));
+            }
+            gameboy.memory.cheats = Cheats::parse(&setup.cheats).map_err(|_| MovieError::BadFormat("cheats"))?;
+        }
+        if let Some(state) = &movie.start_state {
+            gameboy.load_state(state)?;
+        }
+
+        Ok(MoviePlayer {
+            movie,
+            frame: 0,
+            next_checkpoint: 0,
+        })
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame as usize >= self.movie.inputs.len()
+    }
+
+    // Runs the next recorded frame. Returns Ok(false) once the movie has ended.
+    pub fn run_frame(&mut self, gameboy: &mut GameBoy) -> Result<bool, MovieError> {
+        let Some(&buttons) = self.movie.inputs.get(self.frame as usize) else {
+            return Ok(false);
+        };
+        gameboy.set_buttons(buttons);
+        gameboy.run_frame();
+        self.frame += 1;
+
+        if let Some(&(frame, hash)) = self.movie.checkpoints.get(self.next_checkpoint)
+            && frame == self.frame
+        {
+            self.next_checkpoint += 1;
+            if state_hash(gameboy) != hash {
+                return Err(MovieError::Desync { frame });
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Palette;
+
+    // A VBM file with one controller, the author field filled with junk
+    // and the input data right after the 0x100 byte header
+    fn vbm(frames: u32, inputs: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xAA; 0x100];
+        data[..4].copy_from_slice(VBM_MAGIC);
+        data[0x0C..0x10].copy_from_slice(&frames.to_le_bytes());
+        data[0x14] = 0;
+        data[0x15] = 0x01;
+        data[0x38..0x3C].copy_from_slice(&0u32.to_le_bytes());
+        data[0x3C..0x40].copy_from_slice(&0x100u32.to_le_bytes());
+        for &buttons in inputs {
+            data.extend_from_slice(&[buttons, 0]);
+        }
+        data
+    }
+
+    #[test]
+    fn imports_vbm_inputs() {
+        let movie = Movie::import_vbm(&vbm(3, &[BUTTON_A, 0, BUTTON_START | BUTTON_RIGHT])).unwrap();
+        assert_eq!(movie.inputs, [BUTTON_A, 0, BUTTON_START | BUTTON_RIGHT]);
+    }
+
+    #[test]
+    fn rejects_vbm_frame_counts_past_the_data() {
+        for frames in [4, u32::MAX] {
+            assert!(matches!(
+                Movie::import_vbm(&vbm(frames, &[BUTTON_A, 0, BUTTON_B])),
+                Err(MovieError::BadFormat(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn plays_back_under_another_palette() {
+        let rom = vec![0; 0x8000];
+        let mut gameboy = GameBoy::new(&rom);
+        gameboy.display_palette = Some(Palette::preset("gray").unwrap().rgb555());
+        let mut recorder = MovieRecorder::from_power_on(&gameboy);
+        for frame in 0..180u32 {
+            recorder.run_frame(&mut gameboy, (frame % 5) as u8);
+        }
+        let movie = Movie::parse(&recorder.finish().to_bytes()).unwrap();
+        assert_eq!(movie.checkpoints.len(), 3);
+
+        // Switching palettes mid-movie, like C does, changes nothing either
+        let mut gameboy = GameBoy::new(&rom);
+        gameboy.display_palette = Some(Palette::preset("dmg-green").unwrap().rgb555());
+        let mut player = MoviePlayer::new(movie, &mut gameboy).unwrap();
+        while player.run_frame(&mut gameboy).unwrap() {
+            if player.frame() == 90 {
+                gameboy.display_palette = Some(Palette::preset("cgb-left").unwrap().rgb555());
+            }
+        }
+        assert!(player.is_finished());
+    }
+
+    fn record(gameboy: &mut GameBoy, frames: u32) -> Movie {
+        let mut recorder = MovieRecorder::from_power_on(gameboy);
+        for frame in 0..frames {
+            recorder.run_frame(gameboy, (frame % 3) as u8);
+        }
+        Movie::parse(&recorder.finish().to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn stores_the_power_on_setup() {
+        let rom = vec![0; 0x8000];
+        let config = Config {
+            model: Model::Mgb,
+            vram_init: RamInit::Random { seed: 7 },
+            boot_rom: None,
+        };
+        let mut gameboy = GameBoy::with_config(&rom, &config);
+        gameboy.memory.cheats = Cheats::parse("01FF00C0 Fill\n-00A-17B-C49\n").unwrap();
+        let movie = record(&mut gameboy, 60);
+        let setup = movie.setup.as_ref().unwrap();
+        assert_eq!((setup.model, setup.vram_init, setup.boot_rom_crc), (Model::Mgb, RamInit::Random { seed: 7 }, None));
+
+        // Played back on a console set up from the movie, with the cheats it brings
+        let mut config = Config::default();
+        setup.apply(&mut config).unwrap();
+        let mut gameboy = GameBoy::with_config(&rom, &config);
+        let mut player = MoviePlayer::new(movie, &mut gameboy).unwrap();
+        assert_eq!(gameboy.memory.cheats.to_text(), "01FF00C0 Fill\n-00A-17B-C49\n");
+        while player.run_frame(&mut gameboy).unwrap() {}
+    }
+
+    #[test]
+    fn refuses_another_setup() {
+        let rom = vec![0; 0x8000];
+        let movie = record(&mut GameBoy::new(&rom), 1);
+        let config = Config {
+            model: Model::Cgb,
+            ..Config::default()
+        };
+        assert!(matches!(
+            MoviePlayer::new(movie, &mut GameBoy::with_config(&rom, &config)),
+            Err(MovieError::SetupMismatch("model"))
+        ));
+
+        // The boot ROM has to be supplied, it isn't in the movie
+        let config = Config {
+            boot_rom: Some(vec![0; 0x100]),
+            ..Config::default()
+        };
+        let movie = record(&mut GameBoy::with_config(&rom, &config), 1);
+        assert!(matches!(
+            movie.setup.unwrap().apply(&mut Config::default()),
+            Err(MovieError::SetupMismatch("boot ROM"))
+        ));
+    }
+}
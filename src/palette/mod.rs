@@ -0,0 +1,194 @@
+// DMG color palettes
+//
+// A palette gives the four shades of each DMG layer a color: background
+// and window, and the two object palettes. It only changes how frames are
+// shown (see `GameBoy::display_palette`): the emulated palette RAM is left
+// alone, so save states and movies don't depend on it. It applies to every
+// DMG-mode game but never to CGB games.
+//
+// Custom palettes are text files:
+//
+//     # Lines starting with '#' are comments
+//     bg   = FFFFFF AAAAAA 555555 000000
+//     obj0 = FFFFFF FF8484 943A3A 000000
+//     obj1 = FFFFFF 7BFF31 008400 000000
+//
+// Colors are RRGGBB (optionally #RRGGBB), lightest first. Missing object palettes copy `bg`.
+
+use crate::joypad::{BUTTON_A, BUTTON_B, BUTTON_DOWN, BUTTON_LEFT, BUTTON_RIGHT, BUTTON_UP};
+
+// Four shades, lightest first
+pub type Shades = [u32; 4];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    pub bg: Shades, // 0xRRGGBB
+    pub obj0: Shades,
+    pub obj1: Shades,
+}
+
+const GRAY: Shades = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000];
+const POCKET: Shades = [0xE0DBCD, 0xA89F94, 0x706B66, 0x2B2B26];
+const DMG_GREEN: Shades = [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F];
+const BGB_GREEN: Shades = [0xE0F8D0, 0x88C070, 0x346856, 0x081820];
+
+// Building blocks of the CGB boot ROM colorizations
+const WHITE_BROWN: Shades = [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000];
+const WHITE_RED: Shades = [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000];
+const WHITE_GREEN: Shades = [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000];
+const WHITE_BLUE: Shades = [0xFFFFFF, 0x65A49B, 0x0000FE, 0x000000];
+
+// (name, bg, obj0, obj1). The CGB entries are the palettes picked by
+// holding a direction (and A or B) while the CGB logo is shown.
+const PRESETS: [(&str, Shades, Shades, Shades); 16] = [
+    ("gray", GRAY, GRAY, GRAY),
+    ("pocket", POCKET, POCKET, POCKET),
+    ("dmg-green", DMG_GREEN, DMG_GREEN, DMG_GREEN),
+    ("bgb-green", BGB_GREEN, BGB_GREEN, BGB_GREEN),
+    ("cgb-up", WHITE_BROWN, WHITE_BROWN, WHITE_BROWN),
+    ("cgb-up-a", WHITE_RED, WHITE_RED, WHITE_RED),
+    ("cgb-up-b", [0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108], [0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108], [0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108]),
+    ("cgb-left", WHITE_BLUE, WHITE_RED, WHITE_GREEN),
+    ("cgb-left-a", [0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000], WHITE_RED, WHITE_BROWN),
+    ("cgb-left-b", [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000], [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000], [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000]),
+    ("cgb-down", [0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000], [0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000], [0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000]),
+    ("cgb-down-a", [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000], [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000], [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000]),
+    ("cgb-down-b", [0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000], WHITE_BLUE, WHITE_GREEN),
+    ("cgb-right", [0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000], [0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000], [0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000]),
+    ("cgb-right-a", [0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000], WHITE_RED, WHITE_RED),
+    ("cgb-right-b", [0x000000, 0x008484, 0xFFDE00, 0xFFFFFF], [0x000000, 0x008484, 0xFFDE00, 0xFFFFFF], [0x000000, 0x008484, 0xFFDE00, 0xFFFFFF]),
+];
+
+// 0xRRGGBB to the PPU's 15-bit RGB
+fn to_rgb555(color: u32) -> u16 {
+    let r = (color >> 19) & 0x1F;
+    let g = (color >> 11) & 0x1F;
+    let b = (color >> 3) & 0x1F;
+    (r | (g << 5) | (b << 10)) as u16
+}
+
+impl Palette {
+    pub fn preset_names() -> impl Iterator<Item = &'static str> {
+        PRESETS.iter().map(|p| p.0)
+    }
+
+    pub fn preset(name: &str) -> Option<Palette> {
+        PRESETS.iter().position(|p| p.0 == name).map(Self::preset_at)
+    }
+
+    // Presets by index, wrapping around, for cycling through them
+    pub fn preset_at(index: usize) -> Palette {
+        let (name, bg, obj0, obj1) = PRESETS[index % PRESETS.len()];
+        Palette {
+            name: name.to_string(),
+            bg,
+            obj0,
+            obj1,
+        }
+    }
+
+    // The CGB preset for buttons held at power-on: a direction, optionally
+    // with A or B. None without a direction.
+    pub fn for_buttons(buttons: u8) -> Option<Palette> {
+        let (_, direction) = [(BUTTON_UP, "up"), (BUTTON_LEFT, "left"), (BUTTON_DOWN, "down"), (BUTTON_RIGHT, "right")]
+            .into_iter()
+            .find(|(bit, _)| buttons & bit != 0)?;
+        let name = if buttons & BUTTON_A != 0 {
+            format!("cgb-{}-a", direction)
+        } else if buttons & BUTTON_B != 0 {
+            format!("cgb-{}-b", direction)
+        } else {
+            format!("cgb-{}", direction)
+        };
+        Self::preset(&name)
+    }
+
+    // Index of the preset with this palette's name
+    pub fn preset_index(&self) -> Option<usize> {
+        PRESETS.iter().position(|p| p.0 == self.name)
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Palette, String> {
+        let (mut bg, mut obj0, mut obj1) = (None, None, None);
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected 'layer = colors'", n + 1))?;
+            let colors = Self::parse_colors(value).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            match key.trim() {
+                "bg" => bg = Some(colors),
+                "obj0" => obj0 = Some(colors),
+                "obj1" => obj1 = Some(colors),
+                other => return Err(format!("line {}: unknown layer '{}'", n + 1, other)),
+            }
+        }
+        let bg = bg.ok_or("missing 'bg' palette")?;
+        Ok(Palette {
+            name: name.to_string(),
+            bg,
+            obj0: obj0.unwrap_or(bg),
+            obj1: obj1.unwrap_or(bg),
+        })
+    }
+
+    fn parse_colors(text: &str) -> Result<Shades, String> {
+        let colors = text
+            .split_whitespace()
+            .map(|c| {
+                let hex = c.trim_start_matches('#');
+                match u32::from_str_radix(hex, 16) {
+                    Ok(color) if hex.len() == 6 => Ok(color),
+                    _ => Err(format!("bad color '{}'", c)),
+                }
+            })
+            .collect::<Result<Vec<u32>, String>>()?;
+        colors.try_into().map_err(|_| "expected 4 colors".to_string())
+    }
+
+    // A preset name or the path of a palette file
+    pub fn load(name_or_path: &str) -> Result<Palette, String> {
+        if let Some(palette) = Self::preset(name_or_path) {
+            return Ok(palette);
+        }
+        let text = std::fs::read_to_string(name_or_path).map_err(|e| {
+            let names: Vec<&str> = Self::preset_names().collect();
+            format!("{}: {} (presets: {})", name_or_path, e, names.join(", "))
+        })?;
+        Self::parse(name_or_path, &text)
+    }
+
+    // BG, OBJ0 and OBJ1 shades as 15-bit RGB, for `GameBoy::display_palette`
+    pub fn rgb555(&self) -> [[u16; 4]; 3] {
+        [self.bg, self.obj0, self.obj1].map(|shades| shades.map(to_rgb555))
+    }
+
+    // Background shades as 0xAARRGGBB, for debug views
+    pub fn bg_argb(&self) -> [u32; 4] {
+        self.bg.map(|c| 0xFF00_0000 | c)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::preset_at(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_boot_combos_to_presets() {
+        let name = |buttons| Palette::for_buttons(buttons).map(|p| p.name);
+        assert_eq!(name(BUTTON_LEFT | BUTTON_A).as_deref(), Some("cgb-left-a"));
+        assert_eq!(name(BUTTON_DOWN | BUTTON_B).as_deref(), Some("cgb-down-b"));
+        assert_eq!(name(BUTTON_RIGHT).as_deref(), Some("cgb-right"));
+        assert_eq!(name(BUTTON_A), None);
+    }
+}
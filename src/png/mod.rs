@@ -0,0 +1,148 @@
+// Minimal PNG encoder
+//
+// Writes 8-bit RGB images. The zlib stream uses stored (uncompressed)
+// deflate blocks: files are larger than a real compressor would produce,
+// but the encoder stays tiny and needs no dependencies.
+
+use crate::checksum::{adler32, crc32_update};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// Encodes `pixels` (0xAARRGGBB, alpha ignored) as a PNG file
+pub fn encode_argb(width: usize, height: usize, pixels: &[u32]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height, "pixel count does not match image size");
+
+    // Each scanline starts with filter type 0 (none)
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in pixels.chunks(width.max(1)).take(height) {
+        raw.push(0);
+        for &argb in row {
+            raw.extend_from_slice(&[(argb >> 16) as u8, (argb >> 8) as u8, argb as u8]);
+        }
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit depth, truecolor, deflate, no filter, no interlace
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32_update(crc32_update(0, kind), data);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01]; // Deflate, 32K window, no preset dictionary
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]); // Single empty final block
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (type, data) of each chunk, checking lengths and CRCs on the way
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+            let data = &rest[8..8 + len];
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32_update(crc32_update(0, &kind), data));
+            chunks.push((kind, data));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    // Undoes `zlib_stored`, checking block lengths and the Adler-32
+    fn inflate_stored(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(zlib[..2], [0x78, 0x01]);
+        assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+        let mut out = Vec::new();
+        let mut at = 2;
+        loop {
+            let last = zlib[at] & 0x01 != 0;
+            assert_eq!(zlib[at] & 0x06, 0, "not a stored block");
+            let len = u16::from_le_bytes([zlib[at + 1], zlib[at + 2]]);
+            assert_eq!(!len, u16::from_le_bytes([zlib[at + 3], zlib[at + 4]]));
+            out.extend_from_slice(&zlib[at + 5..at + 5 + len as usize]);
+            at += 5 + len as usize;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(zlib[at..], adler32(&out).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn encodes_a_tiny_image_exactly() {
+        let png = encode_argb(2, 2, &[0xFFFF_0000, 0xFF00_00FF, 0x0012_3456, 0xFFFF_FFFF]);
+        let raw = [0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0x12, 0x34, 0x56, 0xFF, 0xFF, 0xFF];
+        let mut expected = SIGNATURE.to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 13, b'I', b'H', b'D', b'R', 0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        expected.extend_from_slice(&[0xFD, 0xD4, 0x9A, 0x73]);
+        expected.extend_from_slice(&[0, 0, 0, 25, b'I', b'D', b'A', b'T', 0x78, 0x01, 0x01, 14, 0, !14, 0xFF]);
+        expected.extend_from_slice(&raw);
+        expected.extend_from_slice(&[0x1D, 0xBB, 0x05, 0x98]); // Adler-32 of the scanlines
+        expected.extend_from_slice(&[0x24, 0x6B, 0x39, 0x76]);
+        expected.extend_from_slice(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+        assert_eq!(png, expected);
+    }
+
+    #[test]
+    fn splits_large_images_into_stored_blocks() {
+        // 160x144 RGB is about 69K of scanlines: two blocks
+        let (width, height) = (160, 144);
+        let pixels: Vec<u32> = (0..width * height).map(|i| (i as u32).wrapping_mul(0x9E37_79B9)).collect();
+        let png = encode_argb(width, height, &pixels);
+
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1[..8], [0, 0, 0, 160, 0, 0, 0, 144]);
+
+        let raw = inflate_stored(chunks[1].1);
+        assert_eq!(raw.len(), height * (width * 3 + 1));
+        for (y, line) in raw.chunks(width * 3 + 1).enumerate() {
+            assert_eq!(line[0], 0);
+            for (x, rgb) in line[1..].chunks(3).enumerate() {
+                let argb = pixels[y * width + x];
+                assert_eq!(rgb, [(argb >> 16) as u8, (argb >> 8) as u8, argb as u8]);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_images_still_have_a_final_block() {
+        let png = encode_argb(0, 0, &[]);
+        assert_eq!(inflate_stored(chunks(&png)[1].1), []);
+    }
+}
@@ -0,0 +1,194 @@
+// Colors the CGB boot ROM gives DMG cartridges
+//
+// Nintendo-licensed games are looked up by the sum of their 16 title bytes.
+// Some sums are shared by several games; past FIRST_DUPLICATE the fourth
+// letter of the title tells them apart. The entry found picks one of the
+// combinations of OBJ0, OBJ1 and BG palettes; everything else gets entry 0.
+// The tables are the boot ROM's own.
+
+// 15-bit RGB palettes the combinations are built from
+const PALETTES: [u16; 30 * 4] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+// (OBJ0, OBJ1, BG) as the index of their first color in PALETTES. They are
+// whole palettes except in combinations 22, 34 and 35, where the boot ROM
+// reads four colors starting one short of a palette.
+const COMBINATIONS: [(usize, usize, usize); 51] = [
+    (4 * 4, 4 * 4, 29 * 4),
+    (18 * 4, 18 * 4, 18 * 4),
+    (20 * 4, 20 * 4, 20 * 4),
+    (24 * 4, 24 * 4, 24 * 4),
+    (9 * 4, 9 * 4, 9 * 4),
+    (0, 0, 0),
+    (27 * 4, 27 * 4, 27 * 4),
+    (5 * 4, 5 * 4, 5 * 4),
+    (12 * 4, 12 * 4, 12 * 4),
+    (26 * 4, 26 * 4, 26 * 4),
+    (16 * 4, 8 * 4, 8 * 4),
+    (4 * 4, 28 * 4, 28 * 4),
+    (4 * 4, 2 * 4, 2 * 4),
+    (3 * 4, 4 * 4, 4 * 4),
+    (4 * 4, 29 * 4, 29 * 4),
+    (28 * 4, 4 * 4, 28 * 4),
+    (2 * 4, 17 * 4, 2 * 4),
+    (16 * 4, 16 * 4, 8 * 4),
+    (4 * 4, 4 * 4, 7 * 4),
+    (4 * 4, 4 * 4, 18 * 4),
+    (4 * 4, 4 * 4, 20 * 4),
+    (19 * 4, 19 * 4, 9 * 4),
+    (4 * 4 - 1, 4 * 4 - 1, 11 * 4),
+    (17 * 4, 17 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 3 * 4),
+    (28 * 4, 28 * 4, 0),
+    (3 * 4, 3 * 4, 0),
+    (0, 0, 4),
+    (18 * 4, 22 * 4, 18 * 4),
+    (20 * 4, 22 * 4, 20 * 4),
+    (24 * 4, 22 * 4, 24 * 4),
+    (16 * 4, 22 * 4, 8 * 4),
+    (17 * 4, 4 * 4, 13 * 4),
+    (28 * 4 - 1, 0, 14 * 4),
+    (28 * 4 - 1, 4 * 4, 15 * 4),
+    (19 * 4, 22 * 4, 9 * 4),
+    (16 * 4, 28 * 4, 10 * 4),
+    (4 * 4, 23 * 4, 28 * 4),
+    (17 * 4, 22 * 4, 2 * 4),
+    (4 * 4, 0, 2 * 4),
+    (4 * 4, 28 * 4, 3 * 4),
+    (28 * 4, 3 * 4, 0),
+    (3 * 4, 28 * 4, 4 * 4),
+    (21 * 4, 28 * 4, 4 * 4),
+    (3 * 4, 28 * 4, 0),
+    (25 * 4, 3 * 4, 28 * 4),
+    (0, 28 * 4, 8 * 4),
+    (4 * 4, 3 * 4, 28 * 4),
+    (28 * 4, 3 * 4, 6 * 4),
+    (4 * 4, 28 * 4, 29 * 4),
+];
+
+// Title checksums, and the combination each one selects
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B,
+    // From here on the fourth letter has to match as well
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3, 0x46,
+    0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+const TITLE_COMBINATIONS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17, 46,
+    6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+const FIRST_DUPLICATE: usize = 65;
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// Combination for the cartridge header (0x0100-0x014F)
+fn combination(header: &[u8; 0x50]) -> usize {
+    // Only games licensed by Nintendo are in the table
+    let nintendo = match header[0x4B] {
+        0x33 => &header[0x44..0x46] == b"01",
+        licensee => licensee == 0x01,
+    };
+    if !nintendo {
+        return 0;
+    }
+
+    let title = &header[0x34..0x44];
+    let checksum = title.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    let found = TITLE_CHECKSUMS.iter().enumerate().position(|(i, &sum)| {
+        sum == checksum && (i < FIRST_DUPLICATE || FOURTH_LETTERS[i - FIRST_DUPLICATE] == title[3])
+    });
+    found.map_or(0, |i| TITLE_COMBINATIONS[i] as usize)
+}
+
+// The (BG, OBJ0, OBJ1) colors the boot ROM picks for a DMG cartridge
+pub fn dmg_compat_palettes(header: &[u8; 0x50]) -> [[u16; 4]; 3] {
+    let (obj0, obj1, bg) = COMBINATIONS[combination(header)];
+    let palette = |start: usize| -> [u16; 4] { PALETTES[start..start + 4].try_into().unwrap() };
+    [palette(bg), palette(obj0), palette(obj1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(title: &[u8], licensee: u8) -> [u8; 0x50] {
+        let mut header = [0; 0x50];
+        header[0x34..0x34 + title.len()].copy_from_slice(title);
+        header[0x4B] = licensee;
+        header
+    }
+
+    #[test]
+    fn finds_nintendo_titles() {
+        // Tetris is the Down+A palette throughout
+        let tetris = [0x7FFF, 0x03FF, 0x001F, 0x0000];
+        assert_eq!(dmg_compat_palettes(&header(b"TETRIS", 0x01)), [tetris; 3]);
+
+        let [bg, obj0, obj1] = dmg_compat_palettes(&header(b"POKEMON RED", 0x01));
+        assert_eq!(bg, [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        assert_eq!((obj0, obj1), ([0x7FFF, 0x1BEF, 0x0200, 0x0000], bg));
+
+        // Licensed through the new licensee code
+        let mut zelda = header(b"ZELDA", 0x33);
+        zelda[0x44..0x46].copy_from_slice(b"01");
+        assert_eq!(combination(&zelda), 44);
+    }
+
+    #[test]
+    fn tells_shared_checksums_apart_by_the_fourth_letter() {
+        let [bg, ..] = dmg_compat_palettes(&header(b"POKEMON BLUE", 0x01));
+        assert_eq!(bg, [0x7FFF, 0x7E8C, 0x7C00, 0x0000]);
+        assert_eq!(combination(&header(b"SUPER MARIOLAND", 0x01)), 22);
+
+        // Same checksum as POKEMON BLUE, other fourth letter
+        assert_eq!(combination(&header(b"POKXMON BLBE", 0x01)), 0);
+    }
+
+    #[test]
+    fn defaults_for_other_licensees() {
+        assert_eq!(combination(&header(b"TETRIS", 0x33)), 0);
+        assert_eq!(combination(&header(b"TETRIS", 0x08)), 0);
+        let [bg, obj0, obj1] = dmg_compat_palettes(&header(b"TETRIS", 0x08));
+        assert_eq!(bg, [0x7FFF, 0x1BEF, 0x6180, 0x0000]);
+        assert_eq!((obj0, obj1), ([0x7FFF, 0x421F, 0x1CF2, 0x0000], obj0));
+    }
+}
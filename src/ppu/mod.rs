@@ -1,25 +1,62 @@
-use log::info;
+use crate::logging::info;
+#[cfg(feature = "alloc")]
+use crate::savestate::{SaveState, StateError, StateReader, StateWriter};
+use crate::storage::{buffer, Buffer};
+
+mod compat;
 
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
+const SCREEN_PIXELS: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+// 15-bit RGB colors as stored in CGB palette RAM: bits 0-4 red, 5-9 green, 10-14 blue
+pub const DMG_GRAYS: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+pub use compat::dmg_compat_palettes;
 
+// Expands a 15-bit RGB color to 0xAARRGGBB
+pub fn rgb555_to_argb(color: u16) -> u32 {
+    let expand = |c: u16| {
+        let c = (c & 0x1F) as u32;
+        (c << 3) | (c >> 2)
+    };
+    0xFF00_0000 | (expand(color) << 16) | (expand(color >> 5) << 8) | expand(color >> 10)
+}
+
+#[derive(Clone)]
 pub struct Ppu {
     pub mode: u8,
     pub mode_clock: u32,
     pub line: u8,
-    pub vram: Vec<u8>,
-    pub oam: Vec<u8>,
-    pub frame_buffer: Vec<u8>,
+    pub vram: Buffer<u8, 0x4000>, // Bank 0 at 0x0000, CGB bank 1 at 0x2000
+    pub oam: Buffer<u8, 0xA0>,
+    pub frame_buffer: Buffer<u8, SCREEN_PIXELS>, // DMG shade per pixel, or color index within its palette in CGB mode
+    pub rgb_buffer: Buffer<u16, SCREEN_PIXELS>,  // Final 15-bit RGB color per pixel
+    pub dmg_layers: Buffer<u8, SCREEN_PIXELS>,   // DMG palette per pixel: 0 BG and window, 1 OBJ0, 2 OBJ1
     pub lcdc: u8,
     pub scx: u8,
     pub scy: u8,
     pub bgp: u8,  // Background palette
     pub stat: u8, // LCD status
     pub vblank_interrupt: bool,
+    pub hblank_started: bool, // Set on entering HBlank, drives CGB HBlank DMA
     pub wx: u8,   // Window X position
     pub wy: u8,   // Window Y position
     pub obp0: u8,  // Object Palette 0
     pub obp1: u8,  // Object Palette 1
+    pub cgb_mode: bool,     // Map attributes, VRAM bank 1 and color palettes in use
+    pub vram_bank: u8,      // VBK - bank the CPU sees at 0x8000
+    pub bg_palette_ram: [u8; 64],  // 8 palettes of 4 little-endian colors
+    pub obj_palette_ram: [u8; 64],
+    pub bcps: u8, // BG palette index, bit 7 increments it on each BCPD write
+    pub ocps: u8, // OBJ palette index, bit 7 increments it on each OCPD write
+    line_bg: [u8; SCREEN_WIDTH], // Current line's BG color indices, bit 7 = BG-to-OAM priority
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Ppu {
@@ -28,20 +65,31 @@ impl Ppu {
             mode: 2, // Start in OAM scan mode
             mode_clock: 0,
             line: 0,
-            vram: vec![0; 0x2000],
-            oam: vec![0; 0xA0],
-            frame_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            vram: buffer::<_, 0x4000>(0),
+            oam: buffer::<_, 0xA0>(0),
+            frame_buffer: buffer::<_, SCREEN_PIXELS>(0),
+            rgb_buffer: buffer::<_, SCREEN_PIXELS>(DMG_GRAYS[0]),
+            dmg_layers: buffer::<_, SCREEN_PIXELS>(0),
             lcdc: 0x91, // LCD on, BG enabled
             scx: 0,
             scy: 0,
             bgp: 0xFC,  // Default background palette (11 11 00 00)
             stat: 0x85, // Default STAT register
             vblank_interrupt: false,
+            hblank_started: false,
             wx: 0,      // Window X position
             wy: 0,      // Window Y position
             obp0: 0xFF, // Default sprite palette 0
             obp1: 0xFF, // Default sprite palette 1
+            cgb_mode: false,
+            vram_bank: 0,
+            bg_palette_ram: [0xFF; 64],
+            obj_palette_ram: [0xFF; 64],
+            bcps: 0,
+            ocps: 0,
+            line_bg: [0; SCREEN_WIDTH],
         };
+        ppu.set_dmg_palettes(&DMG_GRAYS, &DMG_GRAYS, &DMG_GRAYS);
         
         // Initialize frame buffer to be white
         ppu.frame_buffer.fill(0);
@@ -49,20 +97,70 @@ impl Ppu {
         ppu
     }
 
-    fn get_tile_data(&self, tile_idx: u8, use_signed: bool) -> &[u8] {
-        let base_addr = if use_signed {
-            // Use signed addressing (0x8800-0x97FF)
-            // Tile index is treated as signed, with 0 = 0x9000
-            let signed_idx = tile_idx as i8;
-            0x1000 + ((signed_idx as i16 + 128) * 16) as usize
+    // Register state at power-on, before a boot ROM has run: LCD off
+    pub fn power_on(&mut self) {
+        self.lcdc = 0;
+        self.stat = 0;
+        self.bgp = 0;
+        self.obp0 = 0;
+        self.obp1 = 0;
+        self.mode = 0;
+        self.mode_clock = 0;
+        self.line = 0;
+    }
+
+    // Colors DMG shades are shown in: BG palette 0 and OBJ palettes 0 and 1,
+    // as in the CGB's DMG compatibility mode
+    pub fn set_dmg_palettes(&mut self, bg: &[u16; 4], obj0: &[u16; 4], obj1: &[u16; 4]) {
+        for i in 0..4 {
+            self.bg_palette_ram[i * 2..i * 2 + 2].copy_from_slice(&bg[i].to_le_bytes());
+            self.obj_palette_ram[i * 2..i * 2 + 2].copy_from_slice(&obj0[i].to_le_bytes());
+            self.obj_palette_ram[8 + i * 2..8 + i * 2 + 2].copy_from_slice(&obj1[i].to_le_bytes());
+        }
+    }
+
+    pub fn read_bcpd(&self) -> u8 {
+        self.bg_palette_ram[(self.bcps & 0x3F) as usize]
+    }
+
+    pub fn write_bcpd(&mut self, value: u8) {
+        Self::write_palette_ram(&mut self.bg_palette_ram, &mut self.bcps, value);
+    }
+
+    pub fn read_ocpd(&self) -> u8 {
+        self.obj_palette_ram[(self.ocps & 0x3F) as usize]
+    }
+
+    pub fn write_ocpd(&mut self, value: u8) {
+        Self::write_palette_ram(&mut self.obj_palette_ram, &mut self.ocps, value);
+    }
+
+    fn write_palette_ram(ram: &mut [u8; 64], spec: &mut u8, value: u8) {
+        ram[(*spec & 0x3F) as usize] = value;
+        if *spec & 0x80 != 0 {
+            *spec = 0x80 | (spec.wrapping_add(1) & 0x3F);
+        }
+    }
+
+    fn palette_color(ram: &[u8; 64], palette: u8, color_idx: u8) -> u16 {
+        let i = (palette as usize * 4 + color_idx as usize) * 2;
+        u16::from_le_bytes([ram[i], ram[i + 1]]) & 0x7FFF
+    }
+
+    // Store a BG or window pixel. DMG shades go through BGP and BG palette 0,
+    // CGB color indices through the palette named by the map attribute.
+    fn put_bg_pixel(&mut self, x: usize, color_idx: u8, attr: u8) {
+        let idx = self.line as usize * SCREEN_WIDTH + x;
+        self.line_bg[x] = color_idx | (attr & 0x80);
+        if self.cgb_mode {
+            self.frame_buffer[idx] = color_idx;
+            self.rgb_buffer[idx] = Self::palette_color(&self.bg_palette_ram, attr & 0x07, color_idx);
         } else {
-            // Use unsigned addressing (0x8000-0x8FFF)
-            (tile_idx as usize) * 16
-        };
-        
-        // Ensure we don't go out of bounds
-        let end_addr = (base_addr + 16).min(0x2000);
-        &self.vram[base_addr..end_addr]
+            let shade = (self.bgp >> (color_idx * 2)) & 0x03;
+            self.frame_buffer[idx] = shade;
+            self.rgb_buffer[idx] = Self::palette_color(&self.bg_palette_ram, 0, shade);
+            self.dmg_layers[idx] = 0;
+        }
     }
 
     pub fn render_scanline(&mut self) {
@@ -71,6 +169,8 @@ impl Ppu {
             let start = self.line as usize * SCREEN_WIDTH;
             let end = start + SCREEN_WIDTH;
             self.frame_buffer[start..end].fill(0);
+            self.rgb_buffer[start..end].fill(0x7FFF);
+            self.dmg_layers[start..end].fill(0);
             return;
         }
 
@@ -78,6 +178,9 @@ impl Ppu {
         let start = self.line as usize * SCREEN_WIDTH;
         let end = start + SCREEN_WIDTH;
         self.frame_buffer[start..end].fill(0);
+        self.rgb_buffer[start..end].fill(Self::palette_color(&self.bg_palette_ram, 0, 0));
+        self.dmg_layers[start..end].fill(0);
+        self.line_bg.fill(0);
         
         // Log rendering activity for debugging
         if self.line == 0 || self.line == 80 {
@@ -85,8 +188,9 @@ impl Ppu {
                   self.line, self.lcdc, self.scx, self.scy);
         }
 
-        // Render background if enabled (LCDC bit 0)
-        if self.lcdc & 0x01 != 0 {
+        // Render background if enabled (LCDC bit 0). In CGB mode the bit
+        // only takes away the background's priority over sprites.
+        if self.lcdc & 0x01 != 0 || self.cgb_mode {
             // Get background tile map address (bit 3 of LCDC)
             let bg_map_addr = if self.lcdc & 0x08 == 0 { 0x1800 } else { 0x1C00 };
             
@@ -114,9 +218,14 @@ impl Ppu {
                     continue;
                 }
                 
-                // Get the tile index from the map
+                // Get the tile index from the map, and in CGB mode its
+                // attributes from the same offset in bank 1
                 let tile_idx = self.vram[map_addr];
-                
+                let attr = if self.cgb_mode { self.vram[0x2000 + map_addr] } else { 0 };
+                let tile_line = if attr & 0x40 != 0 { 7 - tile_line } else { tile_line };
+                let pixel_x = if attr & 0x20 != 0 { 7 - pixel_x } else { pixel_x };
+                let bank = if attr & 0x08 != 0 { 0x2000 } else { 0 };
+
                 // Calculate tile data address
                 let tile_addr = if use_signed {
                     // Use signed addressing (0x8800-0x97FF)
@@ -133,26 +242,20 @@ impl Ppu {
                 }
                 
                 // Get the tile data for this line
-                let byte1 = self.vram[tile_addr + tile_line * 2];
-                let byte2 = self.vram[tile_addr + tile_line * 2 + 1];
+                let byte1 = self.vram[bank + tile_addr + tile_line * 2];
+                let byte2 = self.vram[bank + tile_addr + tile_line * 2 + 1];
                 
                 // Get the color index for this pixel (2 bits per pixel)
                 let bit1 = (byte1 >> pixel_x) & 1;
                 let bit2 = (byte2 >> pixel_x) & 1;
                 let color_idx = (bit2 << 1) | bit1;
                 
-                // Map through the background palette
-                let color = (self.bgp >> (color_idx * 2)) & 0x03;
-                
-                // Set the pixel in the frame buffer
-                let fb_idx = self.line as usize * SCREEN_WIDTH + x;
-                if fb_idx < self.frame_buffer.len() {
-                    self.frame_buffer[fb_idx] = color;
-                    
-                    // Debug logging for specific pixels
-                    if self.line == 80 && x == 80 && color != 0 {
-                        info!("Wrote non-zero pixel at ({},{}) - color={}", x, self.line, color);
-                    }
+                // Map through the palette and set the pixel
+                self.put_bg_pixel(x, color_idx, attr);
+
+                // Debug logging for specific pixels
+                if self.line == 80 && x == 80 && color_idx != 0 {
+                    info!("Wrote non-zero pixel at ({},{}) - color={}", x, self.line, color_idx);
                 }
             }
         }
@@ -205,7 +308,11 @@ impl Ppu {
                 continue; // Skip if out of bounds
             }
             let tile_idx = self.vram[map_addr];
-            
+            let attr = if self.cgb_mode { self.vram[0x2000 + map_addr] } else { 0 };
+            let row = if attr & 0x40 != 0 { 7 - tile_line } else { tile_line };
+            let pixel_x = if attr & 0x20 != 0 { 7 - pixel_x } else { pixel_x };
+            let bank = if attr & 0x08 != 0 { 0x2000 } else { 0 };
+
             // Get the tile data
             let tile_addr = if use_signed {
                 // Use signed addressing (0x8800-0x97FF)
@@ -217,13 +324,13 @@ impl Ppu {
             };
             
             // Ensure tile address is valid
-            if tile_addr + tile_line * 2 + 1 >= 0x2000 {
+            if tile_addr + row * 2 + 1 >= 0x2000 {
                 continue;
             }
             
             // Get the pixel color from the tile data (2 bits per pixel)
-            let byte1 = self.vram[tile_addr + tile_line * 2];
-            let byte2 = self.vram[tile_addr + tile_line * 2 + 1];
+            let byte1 = self.vram[bank + tile_addr + row * 2];
+            let byte2 = self.vram[bank + tile_addr + row * 2 + 1];
             
             let bit1 = (byte1 >> pixel_x) & 1;
             let bit2 = (byte2 >> pixel_x) & 1;
@@ -234,11 +341,8 @@ impl Ppu {
                 continue;
             }
             
-            // Map the color through the background palette
-            let color = (self.bgp >> (color_idx * 2)) & 0x03;
-            
-            // Set the pixel in the frame buffer
-            self.frame_buffer[self.line as usize * SCREEN_WIDTH + screen_x] = color;
+            // Map the color through the palette and set the pixel
+            self.put_bg_pixel(screen_x, color_idx, attr);
         }
     }
     
@@ -252,15 +356,18 @@ impl Ppu {
         let sprite_height = if self.lcdc & 0x04 == 0 { 8 } else { 16 };
         
         // Structure to hold sprite information
+        #[derive(Clone, Copy, Default)]
         struct Sprite {
             y: i32,
             x: i32,
             tile_idx: u8,
             attributes: u8,
+            oam_index: usize,
         }
         
         // Maximum of 10 sprites per scanline in GB
-        let mut visible_sprites = Vec::with_capacity(10);
+        let mut sprites = [Sprite::default(); 10];
+        let mut count = 0;
         
         // Check all 40 sprites in OAM (each sprite uses 4 bytes)
         for i in 0..40 {
@@ -274,32 +381,38 @@ impl Ppu {
             
             // Check if sprite is visible on this scanline
             let line_i32 = self.line as i32;
-            let height_i32 = sprite_height as i32;
+            let height_i32 = sprite_height;
             if line_i32 >= y_pos && line_i32 < y_pos + height_i32 {
-                visible_sprites.push(Sprite {
+                sprites[count] = Sprite {
                     y: y_pos,
                     x: x_pos,
                     tile_idx,
                     attributes,
-                });
+                    oam_index: i,
+                };
+                count += 1;
                 
                 // GB hardware can only display 10 sprites per scanline
-                if visible_sprites.len() >= 10 {
+                if count >= 10 {
                     break;
                 }
             }
         }
+        let visible_sprites = &mut sprites[..count];
         
         // Sort sprites by X coordinate (GB prioritizes sprites with lower X coordinate)
-        // In case of a tie, the one earlier in OAM wins (which is already the order in our array)
-        visible_sprites.sort_by(|a, b| a.x.cmp(&b.x));
+        // In case of a tie, the one earlier in OAM wins.
+        // In CGB mode OAM order alone decides.
+        if !self.cgb_mode {
+            visible_sprites.sort_unstable_by_key(|s| (s.x, s.oam_index));
+        }
         
         // Draw sprites from lowest to highest priority (last to first)
         for sprite in visible_sprites.iter().rev() {
             // Calculate which line of the sprite we're on
             let mut sprite_line = if sprite.attributes & 0x40 != 0 {
                 // Y-flip
-                sprite_height as i32 - 1 - (self.line as i32 - sprite.y)
+                sprite_height - 1 - (self.line as i32 - sprite.y)
             } else {
                 self.line as i32 - sprite.y
             };
@@ -325,9 +438,10 @@ impl Ppu {
                 continue;
             }
             
-            // Get the tile data for this line
-            let byte1 = self.vram[tile_addr];
-            let byte2 = self.vram[tile_addr + 1];
+            // Get the tile data for this line, from bank 1 if a CGB sprite asks for it
+            let bank = if self.cgb_mode && sprite.attributes & 0x08 != 0 { 0x2000 } else { 0 };
+            let byte1 = self.vram[bank + tile_addr];
+            let byte2 = self.vram[bank + tile_addr + 1];
             
             // Draw all 8 pixels of the sprite line
             for pixel in 0..8 {
@@ -355,17 +469,33 @@ impl Ppu {
                 }
                 
                 // Check sprite priority (bit 7 of attributes)
-                // If priority=1, sprite is behind background colors 1-3
+                // If priority=1, sprite is behind background colors 1-3.
+                // In CGB mode the map attribute can also claim priority, and
+                // clearing LCDC bit 0 puts every sprite in front.
                 let frame_buffer_idx = self.line as usize * SCREEN_WIDTH + x as usize;
-                let bg_color = self.frame_buffer[frame_buffer_idx] & 0x03;
-                
-                if sprite.attributes & 0x80 != 0 && bg_color != 0 {
+                let bg = self.line_bg[x as usize];
+                let bg_color = bg & 0x03;
+                let bg_wins = if self.cgb_mode {
+                    self.lcdc & 0x01 != 0 && bg_color != 0 && (sprite.attributes | bg) & 0x80 != 0
+                } else {
+                    sprite.attributes & 0x80 != 0 && bg_color != 0
+                };
+                if bg_wins {
                     // Background has priority over sprite
                     continue;
                 }
+
+                if self.cgb_mode {
+                    // Color palette from bits 0-2 of attributes
+                    self.frame_buffer[frame_buffer_idx] = color_idx;
+                    self.rgb_buffer[frame_buffer_idx] =
+                        Self::palette_color(&self.obj_palette_ram, sprite.attributes & 0x07, color_idx);
+                    continue;
+                }
                 
                 // Choose palette (bit 4 of attributes)
-                let palette = if sprite.attributes & 0x10 != 0 {
+                let obj_palette = (sprite.attributes >> 4) & 1;
+                let palette = if obj_palette != 0 {
                     self.obp1
                 } else {
                     self.obp0
@@ -376,6 +506,8 @@ impl Ppu {
                 
                 // Set pixel in frame buffer
                 self.frame_buffer[frame_buffer_idx] = color;
+                self.rgb_buffer[frame_buffer_idx] = Self::palette_color(&self.obj_palette_ram, obj_palette, color);
+                self.dmg_layers[frame_buffer_idx] = 1 + obj_palette;
             }
         }
     }
@@ -394,6 +526,7 @@ impl Ppu {
                 if self.mode_clock >= 172 {
                     self.mode_clock = 0;
                     self.mode = 0;
+                    self.hblank_started = true;
                     
                     // Re-enable rendering - each scanline is rendered at the end of Mode 3
                     self.render_scanline();
@@ -438,115 +571,76 @@ impl Ppu {
         // Bit 2: LYC=LY Interrupt (not implemented)
         // Bit 1: Mode 2 OAM Interrupt (not implemented)
         // Bit 0: Mode 1 V-Blank Interrupt (not implemented)
-        (self.mode & 0x3) as u8
+        self.mode & 0x3
     }
 }
 
-// A more focused debugging function that shows relevant VRAM data
-pub fn render_vram_debug_view(ppu: &mut Ppu) {
-    // Display tiles from each region of VRAM
-    
-    // Top-left: First 16 tiles from pattern table 1 (0x8000-0x8FFF)
-    render_tile_region(ppu, 0, 0, 0, 16, 8);
-    
-    // Top-right: First 16 tiles from pattern table 2 (0x8800-0x97FF)
-    render_tile_region(ppu, SCREEN_WIDTH / 2, 0, 0x1000, 16, 8);
-    
-    // Bottom-left: Background map sampling (16x16 grid from 0x9800)
-    render_bg_map_region(ppu, 0, SCREEN_HEIGHT / 2, 0x1800, 16, 16, false);
-    
-    // Bottom-right: Window map sampling (16x16 grid from 0x9C00)
-    render_bg_map_region(ppu, SCREEN_WIDTH / 2, SCREEN_HEIGHT / 2, 0x1C00, 16, 16, true);
-    
-    // Add a border line to separate the regions
-    for i in 0..SCREEN_WIDTH {
-        ppu.frame_buffer[SCREEN_HEIGHT/2 * SCREEN_WIDTH + i] = 3; // Horizontal middle
-    }
-    for i in 0..SCREEN_HEIGHT {
-        ppu.frame_buffer[i * SCREEN_WIDTH + SCREEN_WIDTH/2] = 3; // Vertical middle
-    }
-}
-
-// Render a region of tiles directly from VRAM
-fn render_tile_region(ppu: &mut Ppu, start_x: usize, start_y: usize, base_addr: usize, width: usize, height: usize) {
-    for tile_y in 0..height {
-        for tile_x in 0..width {
-            let tile_idx = tile_y * width + tile_x;
-            let tile_addr = base_addr + tile_idx * 16;
-            
-            // Check if address is valid
-            if tile_addr + 16 > ppu.vram.len() {
-                continue;
-            }
-            
-            // Render this tile
-            for y in 0..8 {
-                let byte1 = ppu.vram[tile_addr + y * 2];
-                let byte2 = ppu.vram[tile_addr + y * 2 + 1];
-                
-                for x in 0..8 {
-                    let bit_pos = 7 - x;
-                    let bit1 = (byte1 >> bit_pos) & 1;
-                    let bit2 = (byte2 >> bit_pos) & 1;
-                    let color = (bit2 << 1) | bit1;
-                    
-                    let screen_x = start_x + tile_x * 8 + x;
-                    let screen_y = start_y + tile_y * 8 + y;
-                    
-                    if screen_x < SCREEN_WIDTH && screen_y < SCREEN_HEIGHT {
-                        ppu.frame_buffer[screen_y * SCREEN_WIDTH + screen_x] = color;
-                    }
-                }
-            }
+#[cfg(feature = "alloc")]
+impl SaveState for Ppu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.mode);
+        w.u32(self.mode_clock);
+        w.u8(self.line);
+        w.bytes(&self.vram);
+        w.bytes(&self.oam);
+        w.bytes(&self.frame_buffer);
+        for r in [self.lcdc, self.scx, self.scy, self.bgp, self.stat, self.wx, self.wy, self.obp0, self.obp1] {
+            w.u8(r);
         }
+        w.bool(self.vblank_interrupt);
+        for &color in &self.rgb_buffer {
+            w.u16(color);
+        }
+        w.bool(self.cgb_mode);
+        w.u8(self.vram_bank);
+        w.bytes(&self.bg_palette_ram);
+        w.bytes(&self.obj_palette_ram);
+        w.u8(self.bcps);
+        w.u8(self.ocps);
+        w.bool(self.hblank_started);
+        w.bytes(&self.dmg_layers);
     }
-}
 
-// Render a region of the background/window map to see what tiles are mapped
-fn render_bg_map_region(ppu: &mut Ppu, start_x: usize, start_y: usize, map_addr: usize, 
-                        width: usize, height: usize, use_signed: bool) {
-    for map_y in 0..height {
-        for map_x in 0..width {
-            if map_addr + map_y * 32 + map_x >= ppu.vram.len() {
-                continue;
-            }
-            
-            // Get tile index from the tile map
-            let tile_idx = ppu.vram[map_addr + map_y * 32 + map_x];
-            
-            // Get tile address based on the addressing mode
-            let tile_addr = if use_signed {
-                // Use signed addressing (0x8800-0x97FF)
-                let signed_idx = tile_idx as i8;
-                0x1000 + ((signed_idx as i16 + 128) * 16) as usize
-            } else {
-                // Use unsigned addressing (0x8000-0x8FFF)
-                (tile_idx as usize) * 16
-            };
-            
-            if tile_addr + 16 > ppu.vram.len() {
-                continue;
-            }
-            
-            // Render this tile
-            for y in 0..8 {
-                let byte1 = ppu.vram[tile_addr + y * 2];
-                let byte2 = ppu.vram[tile_addr + y * 2 + 1];
-                
-                for x in 0..8 {
-                    let bit_pos = 7 - x;
-                    let bit1 = (byte1 >> bit_pos) & 1;
-                    let bit2 = (byte2 >> bit_pos) & 1;
-                    let color = (bit2 << 1) | bit1;
-                    
-                    let screen_x = start_x + map_x * 8 + x;
-                    let screen_y = start_y + map_y * 8 + y;
-                    
-                    if screen_x < SCREEN_WIDTH && screen_y < SCREEN_HEIGHT {
-                        ppu.frame_buffer[screen_y * SCREEN_WIDTH + screen_x] = color;
-                    }
-                }
-            }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.mode = r.u8()?;
+        if self.mode > 3 {
+            return Err(StateError::Invalid("PPU mode"));
+        }
+        self.mode_clock = r.u32()?;
+        self.line = r.u8()?;
+        // Lines 144-153 are VBlank and nothing else; `step` and
+        // `render_scanline` rely on it
+        let vblank = (144..=153).contains(&self.line);
+        if self.line > 153 || vblank != (self.mode == 1) {
+            return Err(StateError::Invalid("PPU line"));
+        }
+        r.bytes_into(&mut self.vram, "VRAM size")?;
+        r.bytes_into(&mut self.oam, "OAM size")?;
+        r.bytes_into(&mut self.frame_buffer, "frame buffer size")?;
+        self.lcdc = r.u8()?;
+        self.scx = r.u8()?;
+        self.scy = r.u8()?;
+        self.bgp = r.u8()?;
+        self.stat = r.u8()?;
+        self.wx = r.u8()?;
+        self.wy = r.u8()?;
+        self.obp0 = r.u8()?;
+        self.obp1 = r.u8()?;
+        self.vblank_interrupt = r.bool()?;
+        for color in self.rgb_buffer.iter_mut() {
+            *color = r.u16()?;
         }
+        self.cgb_mode = r.bool()?;
+        self.vram_bank = r.u8()? & 0x01;
+        r.bytes_into(&mut self.bg_palette_ram, "BG palette RAM size")?;
+        r.bytes_into(&mut self.obj_palette_ram, "OBJ palette RAM size")?;
+        self.bcps = r.u8()?;
+        self.ocps = r.u8()?;
+        self.hblank_started = r.bool()?;
+        r.bytes_into(&mut self.dmg_layers, "DMG layer buffer size")?;
+        if self.dmg_layers.iter().any(|&layer| layer > 2) {
+            return Err(StateError::Invalid("DMG layer"));
+        }
+        Ok(())
     }
-} 
\ No newline at end of file
+}
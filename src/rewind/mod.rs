@@ -0,0 +1,275 @@
+// Rewind ring buffer built on save states
+//
+// Only the newest captured state is kept in full. Every older state is stored
+// as the XOR of itself and its successor, run-length compressed, so walking
+// backward is `head ^= delta` per step. Between two captures most of RAM and
+// VRAM stays put, but the frame buffers and the thumbnail change with the
+// picture: a delta is around a kilobyte on a still screen and grows by a few
+// bytes for every pixel that changes, against about 145 KiB for a full state.
+
+use std::collections::VecDeque;
+
+use crate::logging::error;
+
+use crate::gameboy::GameBoy;
+
+pub struct RewindConfig {
+    pub interval_frames: u32, // Capture a state every N frames
+    pub max_entries: usize,   // Upper bound on the number of rewind steps kept
+    pub max_bytes: usize,     // Upper bound on memory used by the deltas
+}
+
+impl Default for RewindConfig {
+    // Roughly 60 seconds of history at 60 frames per second
+    fn default() -> Self {
+        RewindConfig {
+            interval_frames: 2,
+            max_entries: 1800,
+            max_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+// Difference between one captured state and the next
+struct Delta {
+    prev_len: usize,
+    data: Vec<u8>,
+}
+
+pub struct RewindBuffer {
+    config: RewindConfig,
+    head: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+    delta_bytes: usize,
+    frames_since_capture: u32,
+}
+
+impl RewindBuffer {
+    pub fn new(config: RewindConfig) -> Self {
+        RewindBuffer {
+            config,
+            head: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            frames_since_capture: 0,
+        }
+    }
+
+    // Number of states that can still be stepped back to
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.head.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    // Bytes held by the buffer, including the full head state
+    pub fn memory_usage(&self) -> usize {
+        self.delta_bytes + self.head.as_ref().map_or(0, |h| h.len())
+    }
+
+    pub fn clear(&mut self) {
+        self.head = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+        self.frames_since_capture = 0;
+    }
+
+    // Call once after every emulated frame
+    pub fn on_frame(&mut self, gameboy: &GameBoy) {
+        self.frames_since_capture += 1;
+        if self.head.is_none() || self.frames_since_capture >= self.config.interval_frames.max(1) {
+            self.capture(gameboy);
+        }
+    }
+
+    pub fn capture(&mut self, gameboy: &GameBoy) {
+        let state = gameboy.save_state();
+        if let Some(prev) = self.head.take() {
+            let delta = Delta {
+                prev_len: prev.len(),
+                data: compress(&xor(&prev, &state)),
+            };
+            self.delta_bytes += delta.data.len();
+            self.deltas.push_back(delta);
+        }
+        self.head = Some(state);
+        self.frames_since_capture = 0;
+        self.trim();
+    }
+
+    // Restores the newest state older than the current frame.
+    // Returns false once the history is exhausted.
+    pub fn step_back(&mut self, gameboy: &mut GameBoy) -> bool {
+        // Frames have run since the last capture: the head is the state to
+        // return to. Otherwise the head is the current frame, so step the
+        // head back by one delta first.
+        if self.frames_since_capture == 0 {
+            let Some(delta) = self.deltas.pop_back() else {
+                return false;
+            };
+            self.delta_bytes -= delta.data.len();
+            let head = self.head.as_mut().expect("deltas imply a head state");
+            let diff = decompress(&delta.data, head.len().max(delta.prev_len));
+            head.resize(diff.len(), 0);
+            for (h, d) in head.iter_mut().zip(&diff) {
+                *h ^= d;
+            }
+            head.truncate(delta.prev_len);
+        }
+
+        let Some(head) = &self.head else {
+            return false;
+        };
+        if let Err(e) = gameboy.load_state(head) {
+            error!("Rewind failed: {}", e);
+            self.clear();
+            return false;
+        }
+        self.frames_since_capture = 0;
+        true
+    }
+
+    // Drop the oldest history until both limits are respected
+    fn trim(&mut self) {
+        while self.deltas.len() + 1 > self.config.max_entries.max(1)
+            || self.memory_usage() > self.config.max_bytes
+        {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.data.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+// XOR of two buffers, with the shorter one treated as zero-padded
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let len = a.len().max(b.len());
+    (0..len)
+        .map(|i| a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0))
+        .collect()
+}
+
+// Run-length encoding tuned for XOR deltas, which are mostly zero.
+// Control byte 0x00-0x7F: (n + 1) zero bytes.
+// Control byte 0x80-0xFF: ((n & 0x7F) + 1) literal bytes follow.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..].iter().take(128).take_while(|&&b| b == 0).count();
+        if run > 0 {
+            out.push((run - 1) as u8);
+            i += run;
+            continue;
+        }
+
+        // Literals continue until a pair of zeros makes a zero run worthwhile
+        let start = i;
+        while i < data.len() && i - start < 128 {
+            if data[i] == 0 && data.get(i + 1) == Some(&0) {
+                break;
+            }
+            i += 1;
+        }
+        out.push(0x80 | (i - start - 1) as u8);
+        out.extend_from_slice(&data[start..i]);
+    }
+    out
+}
+
+fn decompress(data: &[u8], len_hint: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len_hint);
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        let count = (control & 0x7F) as usize + 1;
+        if control & 0x80 == 0 {
+            out.resize(out.len() + count, 0);
+        } else {
+            let end = (i + count).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compression_round_trips() {
+        let mut data = vec![0u8; 1000];
+        data[0] = 1;
+        data[200..460].iter_mut().enumerate().for_each(|(i, b)| *b = i as u8 | 1);
+        data[600] = 0xFF;
+        data[601] = 0; // A lone zero inside a literal run
+        data[602] = 0x10;
+        let packed = compress(&data);
+        assert!(packed.len() < 300);
+        assert_eq!(decompress(&packed, data.len()), data);
+        assert!(compress(&[]).is_empty());
+    }
+
+    // Runs `frames` frames with changing input and returns the states the
+    // buffer captured, oldest first
+    fn run(gameboy: &mut GameBoy, rewind: &mut RewindBuffer, frames: u32) -> Vec<Vec<u8>> {
+        let mut captured = Vec::new();
+        for frame in 0..frames {
+            gameboy.set_buttons((frame % 4) as u8);
+            gameboy.run_frame();
+            gameboy.memory.wram[frame as usize] = frame as u8; // Something to rewind
+            rewind.on_frame(gameboy);
+            if rewind.frames_since_capture == 0 {
+                captured.push(gameboy.save_state());
+            }
+        }
+        captured
+    }
+
+    #[test]
+    fn steps_back_through_the_captured_states() {
+        let mut gameboy = GameBoy::new(&[0; 0x8000]);
+        let mut rewind = RewindBuffer::new(RewindConfig {
+            interval_frames: 3,
+            ..RewindConfig::default()
+        });
+        let captured = run(&mut gameboy, &mut rewind, 20);
+        assert_eq!(captured.len(), 7);
+
+        // A frame has run since the last capture, so that capture comes first
+        for state in captured.iter().rev() {
+            assert!(rewind.step_back(&mut gameboy));
+            assert!(gameboy.save_state() == *state);
+        }
+        assert!(!rewind.step_back(&mut gameboy));
+    }
+
+    #[test]
+    fn evicts_the_oldest_states_first() {
+        let mut gameboy = GameBoy::new(&[0; 0x8000]);
+        let state_len = gameboy.save_state().len();
+        let mut rewind = RewindBuffer::new(RewindConfig {
+            interval_frames: 1,
+            max_entries: 1000,
+            max_bytes: state_len + 2000,
+        });
+        let captured = run(&mut gameboy, &mut rewind, 40);
+        assert!(rewind.memory_usage() <= state_len + 2000);
+        let kept = rewind.len();
+        assert!(kept > 1 && kept < captured.len());
+
+        // Right after a capture the head is the current frame: the first
+        // step goes one further back
+        for state in captured.iter().rev().skip(1).take(kept - 1) {
+            assert!(rewind.step_back(&mut gameboy));
+            assert!(gameboy.save_state() == *state);
+        }
+        assert!(!rewind.step_back(&mut gameboy));
+    }
+}
@@ -0,0 +1,237 @@
+// Versioned save-state format
+//
+// Layout (all integers little-endian):
+//   "GBSS"            magic
+//   u16               format version
+//   u32               CRC-32 of the ROM the state was taken from
+//   u32 + bytes       thumbnail (THUMB_WIDTH x THUMB_HEIGHT shade indices)
+//   chunks...         [tag: 4 bytes][len: u32][payload]
+//
+// Every component with mutable state writes one chunk. Unknown chunks are
+// skipped on load so new hardware (MBC, timer, APU) can add its own chunk
+// without breaking older readers of the same version.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const STATE_MAGIC: &[u8; 4] = b"GBSS";
+pub const STATE_VERSION: u16 = 7;
+
+pub const THUMB_WIDTH: usize = SCREEN_WIDTH / 2;
+pub const THUMB_HEIGHT: usize = SCREEN_HEIGHT / 2;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    RomMismatch { expected: u32, found: u32 },
+    Truncated,
+    MissingChunk([u8; 4]),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(v) => {
+                write!(f, "unsupported save state version {} (expected {})", v, STATE_VERSION)
+            }
+            StateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state belongs to a different ROM (CRC {:08X}, loaded ROM is {:08X})",
+                found, expected
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::MissingChunk(tag) => {
+                write!(f, "save state has no '{}' chunk", String::from_utf8_lossy(tag))
+            }
+            StateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+// Implemented by every component that owns emulated state
+pub trait SaveState {
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+// Byte buffers whose size is fixed by the cartridge, like its RAM
+impl SaveState for Vec<u8> {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(self);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(self, "buffer size")
+    }
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { buf: Vec::new() }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.buf.push(v as u8);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    // Length-prefixed byte block
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.buf.extend_from_slice(v);
+    }
+
+    // Writes a tagged chunk whose payload is produced by `component`
+    pub fn chunk(&mut self, tag: &[u8; 4], component: &dyn SaveState) {
+        let mut inner = StateWriter::new();
+        component.save_state(&mut inner);
+        self.buf.extend_from_slice(tag);
+        self.bytes(&inner.buf);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos.checked_add(len).ok_or(StateError::Truncated)?;
+        let slice = self.data.get(self.pos..end).ok_or(StateError::Truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    // Reads a length-prefixed block into a fixed-size buffer, rejecting size changes
+    pub fn bytes_into(&mut self, out: &mut [u8], what: &'static str) -> Result<(), StateError> {
+        let data = self.bytes()?;
+        if data.len() != out.len() {
+            return Err(StateError::Invalid(what));
+        }
+        out.copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn chunk(&mut self) -> Result<([u8; 4], StateReader<'a>), StateError> {
+        let tag: [u8; 4] = self.take(4)?.try_into().unwrap();
+        let payload = self.bytes()?;
+        Ok((tag, StateReader::new(payload)))
+    }
+}
+
+/// Header fields of a save state, readable without loading it
+pub struct StateHeader<'a> {
+    pub version: u16,
+    pub rom_crc: u32,
+    pub thumbnail: &'a [u8],
+    pub chunks: StateReader<'a>,
+}
+
+pub fn write_header(w: &mut StateWriter, rom_crc: u32, frame_buffer: &[u8]) {
+    for &b in STATE_MAGIC {
+        w.u8(b);
+    }
+    w.u16(STATE_VERSION);
+    w.u32(rom_crc);
+    w.bytes(&make_thumbnail(frame_buffer));
+}
+
+pub fn read_header(data: &[u8]) -> Result<StateHeader<'_>, StateError> {
+    let mut r = StateReader::new(data);
+    if r.take(4).map_err(|_| StateError::BadMagic)? != STATE_MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    let version = r.u16()?;
+    if version != STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    let rom_crc = r.u32()?;
+    let thumbnail = r.bytes()?;
+    if thumbnail.len() != THUMB_WIDTH * THUMB_HEIGHT {
+        return Err(StateError::Invalid("thumbnail"));
+    }
+    Ok(StateHeader {
+        version,
+        rom_crc,
+        thumbnail,
+        chunks: r,
+    })
+}
+
+// Half-resolution copy of the screen (every other pixel on every other line)
+fn make_thumbnail(frame_buffer: &[u8]) -> Vec<u8> {
+    let mut thumb = Vec::with_capacity(THUMB_WIDTH * THUMB_HEIGHT);
+    for y in 0..THUMB_HEIGHT {
+        for x in 0..THUMB_WIDTH {
+            thumb.push(frame_buffer[(y * 2) * SCREEN_WIDTH + x * 2]);
+        }
+    }
+    thumb
+}
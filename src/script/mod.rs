@@ -0,0 +1,648 @@
+// Automation scripts
+//
+// A small Lua-like language for driving the emulator from outside, e.g.
+// for automated playtesting. Top-level code runs once when the script is
+// started and usually registers callbacks:
+//
+//     -- Press A whenever the title screen is up, stop after a minute
+//     on frame do
+//       if read(0xC0A0) == 1 then joypad("a") else joypad("") end
+//       if frame() == 3600 then screenshot("end.png") exit() end
+//     end
+//     on scanline 144 do print("vblank at frame", frame()) end
+//     on pc 0x0150 do print("main loop, A =", hex(A)) end
+//
+// Values are integers, strings and nil; 0 and nil are false. Variables are
+// global unless declared `local` inside a function. The uppercase names
+// A B C D E F H L AF BC DE HL SP PC read and assign CPU registers, and
+// ZF NF HF CF LY are read-only. Besides Lua's comparisons, `and`, `or`,
+// `not` and `..`, the C operators | & ~ << >> work on integers. Note that
+// `^` is bitwise XOR as in C, not Lua's power operator.
+//
+// Functions:
+//   print(...)                 write values to stdout
+//   read(addr) read16(addr)    read memory as the CPU would
+//   write(addr, byte) write16(addr, word)
+//   joypad("a+b+start")        hold buttons until the next call; "" releases
+//   joypad()                   held buttons as a mask of joypad::BUTTON_*
+//   save_state(path) load_state(path)
+//   screenshot(path [, scale]) write the screen as a PNG
+//   frame()                    frames run since the script started
+//   hex(value [, digits])      format as hex
+//   exit()                     stop the emulator after this frame
+
+mod parse;
+
+use std::collections::HashMap;
+use std::fs;
+use std::rc::Rc;
+
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::joypad::*;
+
+use parse::{BinOp, Block, Event, Expr, Stmt, StmtKind, UnOp};
+
+// Statements a single callback may run before it is assumed to be stuck
+const STEP_LIMIT: u64 = 10_000_000;
+
+// Nested script function calls. Each one takes several native stack frames,
+// so this keeps even a debug build inside a 2 MiB thread stack.
+const CALL_DEPTH_LIMIT: usize = 100;
+
+const BUTTON_NAMES: [(&str, u8); 8] = [
+    ("a", BUTTON_A),
+    ("b", BUTTON_B),
+    ("select", BUTTON_SELECT),
+    ("start", BUTTON_START),
+    ("right", BUTTON_RIGHT),
+    ("left", BUTTON_LEFT),
+    ("up", BUTTON_UP),
+    ("down", BUTTON_DOWN),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Nil,
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Int(0))
+    }
+
+    fn int(&self) -> Result<i64, String> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            Value::Nil => Err("expected a number, got nil".to_string()),
+            Value::Str(s) => Err(format!("expected a number, got \"{}\"", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+struct Function {
+    params: Vec<String>,
+    body: Rc<Block>,
+}
+
+// How a statement finished
+enum Flow {
+    Normal,
+    Break,
+    Return(Value),
+}
+
+pub struct Script {
+    program: Block,
+    globals: HashMap<String, Value>,
+    functions: HashMap<String, Rc<Function>>,
+    locals: Vec<HashMap<String, Value>>, // One per active function call
+    on_frame: Vec<Rc<Block>>,
+    on_scanline: Vec<(u8, Rc<Block>)>,
+    on_pc: Vec<(u16, Rc<Block>)>,
+    buttons: u8, // Held by joypad()
+    frame: u64,
+    steps: u64,
+    line: usize, // Statement being run, for errors
+    exit: bool,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Script, String> {
+        Ok(Script {
+            program: parse::parse(source)?,
+            globals: HashMap::new(),
+            functions: HashMap::new(),
+            locals: Vec::new(),
+            on_frame: Vec::new(),
+            on_scanline: Vec::new(),
+            on_pc: Vec::new(),
+            buttons: 0,
+            frame: 0,
+            steps: 0,
+            line: 0,
+            exit: false,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Script, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Script::parse(&source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Runs the top-level code, which registers the callbacks
+    pub fn start(&mut self, gameboy: &mut GameBoy) -> Result<(), String> {
+        let program = std::mem::take(&mut self.program);
+        self.run_callback(gameboy, &program)
+    }
+
+    // Runs one frame with the script's callbacks. `buttons` are pressed by
+    // the player and combined with those the script holds. Returns false
+    // once the script has called exit().
+    pub fn run_frame(&mut self, gameboy: &mut GameBoy, buttons: u8) -> Result<bool, String> {
+        gameboy.set_buttons(buttons | self.buttons);
+
+        if self.on_scanline.is_empty() && self.on_pc.is_empty() {
+            gameboy.run_frame();
+        } else {
+            let mut frame_cycles = 0;
+            while frame_cycles < CYCLES_PER_FRAME {
+                let line = gameboy.memory.ppu.line;
+                frame_cycles += gameboy.step() as u32;
+
+                let new_line = gameboy.memory.ppu.line;
+                if new_line != line {
+                    for (_, body) in self.on_scanline.clone().iter().filter(|(n, _)| *n == new_line) {
+                        self.run_callback(gameboy, body)?;
+                    }
+                }
+                let pc = gameboy.cpu.pc;
+                if self.on_pc.iter().any(|(addr, _)| *addr == pc) {
+                    for (_, body) in self.on_pc.clone().iter().filter(|(addr, _)| *addr == pc) {
+                        self.run_callback(gameboy, body)?;
+                    }
+                }
+            }
+        }
+
+        self.frame += 1;
+        for body in self.on_frame.clone() {
+            self.run_callback(gameboy, &body)?;
+        }
+        Ok(!self.exit)
+    }
+
+    fn run_callback(&mut self, gameboy: &mut GameBoy, body: &Block) -> Result<(), String> {
+        self.steps = 0;
+        self.exec_block(gameboy, body)
+            .map(|_| ())
+            .map_err(|e| format!("line {}: {}", self.line, e))
+    }
+
+    fn exec_block(&mut self, gameboy: &mut GameBoy, block: &Block) -> Result<Flow, String> {
+        for stmt in block {
+            match self.exec(gameboy, stmt)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, gameboy: &mut GameBoy, stmt: &Stmt) -> Result<Flow, String> {
+        self.line = stmt.line;
+        self.count_step()?;
+
+        match &stmt.kind {
+            StmtKind::Assign { name, value, local } => {
+                let value = self.eval(gameboy, value)?;
+                self.assign(gameboy, name, value, *local)?;
+            }
+            StmtKind::Call(call) => {
+                self.eval(gameboy, call)?;
+            }
+            StmtKind::If { branches, otherwise } => {
+                for (cond, body) in branches {
+                    if self.eval(gameboy, cond)?.truthy() {
+                        return self.exec_block(gameboy, body);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.exec_block(gameboy, body);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                while self.eval(gameboy, cond)?.truthy() {
+                    self.count_step()?;
+                    match self.exec_block(gameboy, body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal => {}
+                    }
+                    self.line = stmt.line;
+                }
+            }
+            StmtKind::For { var, start, end, step, body } => {
+                let mut i = self.eval(gameboy, start)?.int()?;
+                let end = self.eval(gameboy, end)?.int()?;
+                let step = match step {
+                    Some(step) => self.eval(gameboy, step)?.int()?,
+                    None => 1,
+                };
+                if step == 0 {
+                    return Err("for loop step is 0".to_string());
+                }
+                while (step > 0 && i <= end) || (step < 0 && i >= end) {
+                    self.assign(gameboy, var, Value::Int(i), true)?;
+                    match self.exec_block(gameboy, body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal => {}
+                    }
+                    i += step;
+                }
+            }
+            StmtKind::Function { name, params, body } => {
+                let function = Function {
+                    params: params.clone(),
+                    body: body.clone(),
+                };
+                self.functions.insert(name.clone(), Rc::new(function));
+            }
+            StmtKind::On { event, body } => match event {
+                Event::Frame => self.on_frame.push(body.clone()),
+                Event::Scanline(line) => {
+                    let line = self.eval(gameboy, line)?.int()?;
+                    if !(0..=153).contains(&line) {
+                        return Err(format!("scanline {} is not 0-153", line));
+                    }
+                    self.on_scanline.push((line as u8, body.clone()));
+                }
+                Event::Pc(addr) => {
+                    let addr = self.eval(gameboy, addr)?.int()?;
+                    self.on_pc.push((addr as u16, body.clone()));
+                }
+            },
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(gameboy, value)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StmtKind::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn count_step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if self.steps > STEP_LIMIT {
+            return Err("script ran too long without returning, stuck in a loop?".to_string());
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, gameboy: &mut GameBoy, name: &str, value: Value, local: bool) -> Result<(), String> {
+        if register(gameboy, name).is_some() {
+            return set_register(gameboy, name, value.int()?);
+        }
+        match self.locals.last_mut() {
+            Some(frame) if local || frame.contains_key(name) => {
+                frame.insert(name.to_string(), value);
+            }
+            _ => {
+                self.globals.insert(name.to_string(), value);
+            }
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, gameboy: &mut GameBoy, expr: &Expr) -> Result<Value, String> {
+        Ok(match expr {
+            Expr::Num(n) => Value::Int(*n),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Nil => Value::Nil,
+            Expr::Var(name) => {
+                if let Some(value) = register(gameboy, name) {
+                    return Ok(Value::Int(value));
+                }
+                self.locals
+                    .last()
+                    .and_then(|frame| frame.get(name))
+                    .or_else(|| self.globals.get(name))
+                    .cloned()
+                    .unwrap_or(Value::Nil)
+            }
+            Expr::Call(name, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.eval(gameboy, arg)?);
+                }
+                self.call(gameboy, name, values)?
+            }
+            Expr::Unary(op, inner) => {
+                let value = self.eval(gameboy, inner)?;
+                match op {
+                    UnOp::Not => Value::Int(!value.truthy() as i64),
+                    UnOp::Complement => Value::Int(!value.int()?),
+                    UnOp::Neg => Value::Int(value.int()?.wrapping_neg()),
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let l = self.eval(gameboy, lhs)?;
+                // Short-circuit like Lua, keeping the deciding value
+                match op {
+                    BinOp::Or if l.truthy() => return Ok(l),
+                    BinOp::And if !l.truthy() => return Ok(l),
+                    BinOp::Or | BinOp::And => return self.eval(gameboy, rhs),
+                    _ => {}
+                }
+                let r = self.eval(gameboy, rhs)?;
+                binary(*op, l, r)?
+            }
+        })
+    }
+
+    fn call(&mut self, gameboy: &mut GameBoy, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        if let Some(function) = self.functions.get(name).cloned() {
+            if self.locals.len() >= CALL_DEPTH_LIMIT {
+                return Err(format!("too much recursion in {}()", name));
+            }
+            let mut frame: HashMap<String, Value> = function.params.iter().cloned().zip(args).collect();
+            for param in &function.params {
+                frame.entry(param.clone()).or_insert(Value::Nil);
+            }
+            self.locals.push(frame);
+            let line = self.line;
+            let result = self.exec_block(gameboy, &function.body);
+            self.locals.pop();
+            // Errors keep the line inside the function
+            let value = match result? {
+                Flow::Return(value) => value,
+                _ => Value::Nil,
+            };
+            self.line = line;
+            return Ok(value);
+        }
+
+        let arg = |i: usize| args.get(i).cloned().unwrap_or(Value::Nil);
+        let path = |i: usize| match arg(i) {
+            Value::Str(path) => Ok(path),
+            _ => Err(format!("{}() needs a file name", name)),
+        };
+        let value = match name {
+            "print" => {
+                let text: Vec<String> = args.iter().map(Value::to_string).collect();
+                println!("{}", text.join("\t"));
+                Value::Nil
+            }
+            "read" => Value::Int(gameboy.memory.read(arg(0).int()? as u16) as i64),
+            "read16" => {
+                let addr = arg(0).int()? as u16;
+                let (lo, hi) = (gameboy.memory.read(addr), gameboy.memory.read(addr.wrapping_add(1)));
+                Value::Int(u16::from_le_bytes([lo, hi]) as i64)
+            }
+            "write" => {
+                gameboy.memory.write(arg(0).int()? as u16, arg(1).int()? as u8);
+                Value::Nil
+            }
+            "write16" => {
+                let (addr, [lo, hi]) = (arg(0).int()? as u16, (arg(1).int()? as u16).to_le_bytes());
+                gameboy.memory.write(addr, lo);
+                gameboy.memory.write(addr.wrapping_add(1), hi);
+                Value::Nil
+            }
+            "joypad" => match arg(0) {
+                Value::Nil => Value::Int(self.buttons as i64),
+                Value::Int(mask) => {
+                    self.buttons = mask as u8;
+                    Value::Nil
+                }
+                Value::Str(spec) => {
+                    self.buttons = parse_buttons(&spec)?;
+                    Value::Nil
+                }
+            },
+            "save_state" => {
+                let path = path(0)?;
+                fs::write(&path, gameboy.save_state()).map_err(|e| format!("{}: {}", path, e))?;
+                Value::Nil
+            }
+            "load_state" => {
+                let path = path(0)?;
+                let data = fs::read(&path).map_err(|e| format!("{}: {}", path, e))?;
+                gameboy.load_state(&data).map_err(|e| format!("{}: {}", path, e))?;
+                Value::Nil
+            }
+            "screenshot" => {
+                let path = path(0)?;
+                let scale = match arg(1) {
+                    Value::Nil => 1,
+                    scale => scale.int()?.clamp(1, 16) as usize,
+                };
+                fs::write(&path, gameboy.screenshot_png(scale)).map_err(|e| format!("{}: {}", path, e))?;
+                Value::Nil
+            }
+            "frame" => Value::Int(self.frame as i64),
+            "hex" => {
+                let digits = match arg(1) {
+                    Value::Nil => 2,
+                    digits => digits.int()?.clamp(1, 16) as usize,
+                };
+                Value::Str(format!("{:0width$X}", arg(0).int()?, width = digits))
+            }
+            "exit" => {
+                self.exit = true;
+                Value::Nil
+            }
+            _ => return Err(format!("unknown function {}()", name)),
+        };
+        Ok(value)
+    }
+}
+
+fn binary(op: BinOp, l: Value, r: Value) -> Result<Value, String> {
+    let value = match op {
+        BinOp::Eq => (l == r) as i64,
+        BinOp::Ne => (l != r) as i64,
+        BinOp::Concat => return Ok(Value::Str(format!("{}{}", l, r))),
+        _ => {
+            let (l, r) = (l.int()?, r.int()?);
+            match op {
+                BinOp::Lt => (l < r) as i64,
+                BinOp::Le => (l <= r) as i64,
+                BinOp::Gt => (l > r) as i64,
+                BinOp::Ge => (l >= r) as i64,
+                BinOp::BitOr => l | r,
+                BinOp::BitXor => l ^ r,
+                BinOp::BitAnd => l & r,
+                BinOp::Shl => l.wrapping_shl(r as u32),
+                BinOp::Shr => l.wrapping_shr(r as u32),
+                BinOp::Add => l.wrapping_add(r),
+                BinOp::Sub => l.wrapping_sub(r),
+                BinOp::Mul => l.wrapping_mul(r),
+                BinOp::Div => l.checked_div(r).ok_or("division by zero")?,
+                BinOp::Rem => l.checked_rem(r).ok_or("division by zero")?,
+                BinOp::Or | BinOp::And | BinOp::Eq | BinOp::Ne | BinOp::Concat => unreachable!("handled above"),
+            }
+        }
+    };
+    Ok(Value::Int(value))
+}
+
+// "a+b+start", case-insensitive; "" is no buttons
+fn parse_buttons(spec: &str) -> Result<u8, String> {
+    let mut mask = 0;
+    for name in spec.split('+').map(str::trim).filter(|n| !n.is_empty()) {
+        let name = name.to_ascii_lowercase();
+        let (_, bit) = BUTTON_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .ok_or_else(|| format!("unknown button '{}'", name))?;
+        mask |= bit;
+    }
+    Ok(mask)
+}
+
+fn register(gameboy: &GameBoy, name: &str) -> Option<i64> {
+    let cpu = &gameboy.cpu;
+    let pair = |hi: u8, lo: u8| u16::from_be_bytes([hi, lo]) as i64;
+    let value = match name {
+        "A" => cpu.a as i64,
+        "B" => cpu.b as i64,
+        "C" => cpu.c as i64,
+        "D" => cpu.d as i64,
+        "E" => cpu.e as i64,
+        "F" => cpu.f as i64,
+        "H" => cpu.h as i64,
+        "L" => cpu.l as i64,
+        "AF" => pair(cpu.a, cpu.f),
+        "BC" => pair(cpu.b, cpu.c),
+        "DE" => pair(cpu.d, cpu.e),
+        "HL" => pair(cpu.h, cpu.l),
+        "SP" => cpu.sp as i64,
+        "PC" => cpu.pc as i64,
+        "ZF" => (cpu.f >> 7 & 1) as i64,
+        "NF" => (cpu.f >> 6 & 1) as i64,
+        "HF" => (cpu.f >> 5 & 1) as i64,
+        "CF" => (cpu.f >> 4 & 1) as i64,
+        "LY" => gameboy.memory.ppu.line as i64,
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn set_register(gameboy: &mut GameBoy, name: &str, value: i64) -> Result<(), String> {
+    let cpu = &mut gameboy.cpu;
+    let [hi, lo] = (value as u16).to_be_bytes();
+    match name {
+        "A" => cpu.a = lo,
+        "B" => cpu.b = lo,
+        "C" => cpu.c = lo,
+        "D" => cpu.d = lo,
+        "E" => cpu.e = lo,
+        "F" => cpu.f = lo & 0xF0, // The low nibble of F always reads 0
+        "H" => cpu.h = lo,
+        "L" => cpu.l = lo,
+        "AF" => (cpu.a, cpu.f) = (hi, lo & 0xF0),
+        "BC" => (cpu.b, cpu.c) = (hi, lo),
+        "DE" => (cpu.d, cpu.e) = (hi, lo),
+        "HL" => (cpu.h, cpu.l) = (hi, lo),
+        "SP" => cpu.sp = value as u16,
+        "PC" => cpu.pc = value as u16,
+        _ => return Err(format!("{} is read-only", name)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0100: NOP; JR -3 (back to the NOP)
+    fn console() -> GameBoy {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0x00, 0x18, 0xFD]);
+        GameBoy::new(&rom)
+    }
+
+    fn run(source: &str) -> (Script, GameBoy, Result<(), String>) {
+        let mut gameboy = console();
+        let mut script = Script::parse(source).unwrap();
+        let result = script.start(&mut gameboy);
+        (script, gameboy, result)
+    }
+
+    fn global(script: &Script, name: &str) -> Value {
+        script.globals.get(name).cloned().unwrap_or(Value::Nil)
+    }
+
+    #[test]
+    fn keeps_locals_apart_from_globals() {
+        let source = "
+            x = 1
+            function f(n)
+              local x = n
+              x = x + 1   -- the local
+              y = x       -- a new global
+              return x
+            end
+            z = f(10)
+        ";
+        let (script, _, result) = run(source);
+        assert_eq!(result, Ok(()));
+        assert_eq!(global(&script, "x"), Value::Int(1));
+        assert_eq!(global(&script, "y"), Value::Int(11));
+        assert_eq!(global(&script, "z"), Value::Int(11));
+        assert_eq!(global(&script, "n"), Value::Nil);
+    }
+
+    #[test]
+    fn evaluates_like_lua_with_c_bit_operators() {
+        let (script, _, result) = run("a = 6 ^ 3\nb = nil or 0 or 7\nc = 1 and \"x\" .. 2\nd = 7 % 3 + 7 / 2");
+        assert_eq!(result, Ok(()));
+        assert_eq!(global(&script, "a"), Value::Int(5));
+        assert_eq!(global(&script, "b"), Value::Int(7)); // 0 is false
+        assert_eq!(global(&script, "c"), Value::Str("x2".into()));
+        assert_eq!(global(&script, "d"), Value::Int(4));
+    }
+
+    #[test]
+    fn limits_recursion() {
+        let (_, _, result) = run("function f(n)\n  return f(n + 1)\nend\nf(0)");
+        assert_eq!(result, Err("line 2: too much recursion in f()".to_string()));
+    }
+
+    #[test]
+    fn stops_runaway_loops() {
+        let (_, _, result) = run("n = 0\nwhile true do\n  n = n + 1\nend");
+        assert_eq!(result, Err("line 2: script ran too long without returning, stuck in a loop?".to_string()));
+    }
+
+    #[test]
+    fn assigns_registers() {
+        let (_, gameboy, result) = run("F = 0xFF\nBC = 0x1234\nAF = 0xABCD\nHL = 0x1C000");
+        assert_eq!(result, Ok(()));
+        let cpu = &gameboy.cpu;
+        assert_eq!((cpu.a, cpu.f, cpu.b, cpu.c, cpu.h, cpu.l), (0xAB, 0xC0, 0x12, 0x34, 0xC0, 0x00));
+
+        let (_, gameboy, result) = run("F = 0x3F");
+        assert_eq!((result, gameboy.cpu.f), (Ok(()), 0x30));
+        let (_, _, result) = run("x = 1\nZF = 1");
+        assert_eq!(result, Err("line 2: ZF is read-only".to_string()));
+    }
+
+    #[test]
+    fn fires_pc_and_scanline_callbacks() {
+        let source = "
+            pcs = 0 wrong_pc = 0 lines = 0
+            on pc 0x101 do
+              pcs = pcs + 1
+              if PC ~= 0x101 then wrong_pc = 1 end
+            end
+            on scanline 100 do lines = lines + 1 ly = LY end
+        ";
+        let (mut script, mut gameboy, result) = run(source);
+        assert_eq!(result, Ok(()));
+        assert_eq!(script.run_frame(&mut gameboy, 0), Ok(true));
+
+        // Once per round of the loop
+        let Value::Int(pcs) = global(&script, "pcs") else { panic!("pcs is not a number") };
+        assert!(pcs > 1000, "{}", pcs);
+        assert_eq!(global(&script, "wrong_pc"), Value::Int(0));
+        assert_eq!(global(&script, "lines"), Value::Int(1));
+        assert_eq!(global(&script, "ly"), Value::Int(100));
+    }
+}
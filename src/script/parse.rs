@@ -0,0 +1,500 @@
+// Lexer and parser for automation scripts
+//
+// Produces a tree of statements, each tagged with its source line for
+// error messages. The grammar is a small subset of Lua:
+//
+//   stmt   := NAME '=' expr | 'local' NAME '=' expr | call
+//           | 'if' expr 'then' block {'elseif' expr 'then' block} ['else' block] 'end'
+//           | 'while' expr 'do' block 'end'
+//           | 'for' NAME '=' expr ',' expr [',' expr] 'do' block 'end'
+//           | 'function' NAME '(' [NAME {',' NAME}] ')' block 'end'
+//           | 'on' ('frame' | 'scanline' expr | 'pc' expr) 'do' block 'end'
+//           | 'return' [expr] | 'break'
+//   expr   := binary operators over NUMBER, STRING, NAME, call, '(' expr ')'
+
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Or, And, Eq, Ne, Lt, Le, Gt, Ge, BitOr, BitXor, BitAnd, Shl, Shr, Concat, Add, Sub, Mul, Div, Rem,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnOp {
+    Not, Complement, Neg,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Num(i64),
+    Str(String),
+    Nil,
+    Var(String),
+    Call(String, Vec<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+pub type Block = Vec<Stmt>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Frame,
+    Scanline(Expr),
+    Pc(Expr),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StmtKind {
+    Assign { name: String, value: Expr, local: bool },
+    Call(Expr),
+    If { branches: Vec<(Expr, Block)>, otherwise: Option<Block> },
+    While { cond: Expr, body: Block },
+    For { var: String, start: Expr, end: Expr, step: Option<Expr>, body: Block },
+    Function { name: String, params: Vec<String>, body: Rc<Block> },
+    On { event: Event, body: Rc<Block> },
+    Return(Option<Expr>),
+    Break,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stmt {
+    pub line: usize,
+    pub kind: StmtKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(i64),
+    Str(String),
+    Name(String),
+    Op(&'static str),
+}
+
+// Longest first so `<=` is not read as `<`
+const OPERATORS: [&str; 27] = [
+    "..", "==", "~=", "!=", "<=", ">=", "<<", ">>", "||", "&&", "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!",
+    "~", "(", ")", ",", "=", ";",
+];
+
+const KEYWORDS: [&str; 19] = [
+    "if", "then", "elseif", "else", "end", "while", "do", "for", "function", "return", "break", "local", "on", "and",
+    "or", "not", "nil", "true", "false",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        let line_no = n + 1;
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            let c = rest.chars().next().unwrap();
+            if rest.starts_with("--") {
+                break;
+            } else if c.is_ascii_digit() || c == '$' {
+                let (digits, radix, skip) = if let Some(hex) = rest.strip_prefix("0x").or_else(|| rest.strip_prefix("0X")) {
+                    (hex, 16, 2)
+                } else if let Some(hex) = rest.strip_prefix('$') {
+                    (hex, 16, 1)
+                } else {
+                    (rest, 10, 0)
+                };
+                let len = digits.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(digits.len());
+                let value = i64::from_str_radix(&digits[..len], radix)
+                    .map_err(|_| format!("line {}: bad number '{}'", line_no, &rest[..skip + len]))?;
+                tokens.push((Token::Num(value), line_no));
+                rest = &digits[len..];
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                tokens.push((Token::Name(rest[..len].to_string()), line_no));
+                rest = &rest[len..];
+            } else if c == '"' || c == '\'' {
+                let (text, len) = string_literal(&rest[1..], c).ok_or_else(|| format!("line {}: unfinished string", line_no))?;
+                tokens.push((Token::Str(text), line_no));
+                rest = &rest[1 + len..];
+            } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+                tokens.push((Token::Op(op), line_no));
+                rest = &rest[op.len()..];
+            } else {
+                return Err(format!("line {}: unexpected '{}'", line_no, c));
+            }
+            rest = rest.trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+// Text of a string literal after its opening quote, and how many bytes
+// it took including the closing quote
+fn string_literal(rest: &str, quote: char) -> Option<(String, usize)> {
+    let mut text = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            _ if c == quote => return Some((text, i + 1)),
+            '\\' => match chars.next()?.1 {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                other => text.push(other),
+            },
+            _ => text.push(c),
+        }
+    }
+    None
+}
+
+// Binary operators from loosest to tightest binding
+const PRECEDENCE: [&[(&str, BinOp)]; 11] = [
+    &[("or", BinOp::Or), ("||", BinOp::Or)],
+    &[("and", BinOp::And), ("&&", BinOp::And)],
+    &[("==", BinOp::Eq), ("~=", BinOp::Ne), ("!=", BinOp::Ne), ("<", BinOp::Lt), ("<=", BinOp::Le), (">", BinOp::Gt), (">=", BinOp::Ge)],
+    &[("|", BinOp::BitOr)],
+    &[("^", BinOp::BitXor)],
+    &[("&", BinOp::BitAnd)],
+    &[("<<", BinOp::Shl), (">>", BinOp::Shr)],
+    &[("..", BinOp::Concat)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+    &[],
+];
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |&(_, line)| line)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.line(), message)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    // Operators and keywords look the same to the parser
+    fn peek_word(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            Some(Token::Name(name)) if KEYWORDS.contains(&name.as_str()) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn accept(&mut self, word: &str) -> bool {
+        if self.peek_word() == Some(word) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        if self.accept(word) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", word)))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Name(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    // Statements up to one of `terminators`, which is left unconsumed
+    fn block(&mut self, terminators: &[&str]) -> Result<Block, String> {
+        let mut block = Vec::new();
+        loop {
+            while self.accept(";") {}
+            match self.peek_word() {
+                Some(word) if terminators.contains(&word) => return Ok(block),
+                _ if self.peek().is_none() => {
+                    return match terminators.first() {
+                        Some(word) => Err(self.error(&format!("expected '{}' before the end of the script", word))),
+                        None => Ok(block),
+                    };
+                }
+                _ => block.push(self.statement()?),
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, String> {
+        let line = self.line();
+        let kind = match self.peek_word() {
+            Some("local") => {
+                self.pos += 1;
+                let name = self.name()?;
+                self.expect("=")?;
+                StmtKind::Assign { name, value: self.expr()?, local: true }
+            }
+            Some("if") => {
+                self.pos += 1;
+                let mut branches = Vec::new();
+                let mut otherwise = None;
+                loop {
+                    let cond = self.expr()?;
+                    self.expect("then")?;
+                    branches.push((cond, self.block(&["end", "elseif", "else"])?));
+                    if self.accept("elseif") {
+                        continue;
+                    }
+                    if self.accept("else") {
+                        otherwise = Some(self.block(&["end"])?);
+                    }
+                    self.expect("end")?;
+                    break;
+                }
+                StmtKind::If { branches, otherwise }
+            }
+            Some("while") => {
+                self.pos += 1;
+                let cond = self.expr()?;
+                StmtKind::While { cond, body: self.do_block()? }
+            }
+            Some("for") => {
+                self.pos += 1;
+                let var = self.name()?;
+                self.expect("=")?;
+                let start = self.expr()?;
+                self.expect(",")?;
+                let end = self.expr()?;
+                let step = if self.accept(",") { Some(self.expr()?) } else { None };
+                StmtKind::For { var, start, end, step, body: self.do_block()? }
+            }
+            Some("function") => {
+                self.pos += 1;
+                let name = self.name()?;
+                self.expect("(")?;
+                let mut params = Vec::new();
+                if !self.accept(")") {
+                    loop {
+                        params.push(self.name()?);
+                        if self.accept(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                let body = self.block(&["end"])?;
+                self.expect("end")?;
+                StmtKind::Function { name, params, body: Rc::new(body) }
+            }
+            Some("on") => {
+                self.pos += 1;
+                let event = match self.name()?.as_str() {
+                    "frame" => Event::Frame,
+                    "scanline" => Event::Scanline(self.expr()?),
+                    "pc" => Event::Pc(self.expr()?),
+                    other => return Err(self.error(&format!("unknown event '{}', expected frame, scanline or pc", other))),
+                };
+                StmtKind::On { event, body: Rc::new(self.do_block()?) }
+            }
+            Some("return") => {
+                self.pos += 1;
+                let value = match self.peek_word() {
+                    Some("end" | "else" | "elseif" | ";") => None,
+                    _ if self.peek().is_none() => None,
+                    _ => Some(self.expr()?),
+                };
+                StmtKind::Return(value)
+            }
+            Some("break") => {
+                self.pos += 1;
+                StmtKind::Break
+            }
+            _ => {
+                let name = self.name()?;
+                if self.accept("=") {
+                    StmtKind::Assign { name, value: self.expr()?, local: false }
+                } else if self.peek_word() == Some("(") {
+                    StmtKind::Call(self.call(name)?)
+                } else {
+                    return Err(self.error(&format!("expected '=' or '(' after '{}'", name)));
+                }
+            }
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    fn do_block(&mut self) -> Result<Block, String> {
+        self.expect("do")?;
+        let body = self.block(&["end"])?;
+        self.expect("end")?;
+        Ok(body)
+    }
+
+    fn call(&mut self, name: String) -> Result<Expr, String> {
+        self.expect("(")?;
+        let mut args = Vec::new();
+        if !self.accept(")") {
+            loop {
+                args.push(self.expr()?);
+                if self.accept(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        Ok(Expr::Call(name, args))
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if PRECEDENCE[level].is_empty() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(&(_, op)) = self.peek_word().and_then(|t| PRECEDENCE[level].iter().find(|(s, _)| *s == t)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek_word() {
+            Some("not" | "!") => UnOp::Not,
+            Some("~") => UnOp::Complement,
+            Some("-") => UnOp::Neg,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned().ok_or_else(|| self.error("expression ends unexpectedly"))?;
+        self.pos += 1;
+        match token {
+            Token::Num(n) => Ok(Expr::Num(n)),
+            Token::Str(s) => Ok(Expr::Str(s)),
+            Token::Name(name) => match name.as_str() {
+                "nil" => Ok(Expr::Nil),
+                "true" => Ok(Expr::Num(1)),
+                "false" => Ok(Expr::Num(0)),
+                _ if KEYWORDS.contains(&name.as_str()) => {
+                    self.pos -= 1;
+                    Err(self.error(&format!("unexpected '{}'", name)))
+                }
+                _ if self.peek_word() == Some("(") => self.call(name),
+                _ => Ok(Expr::Var(name)),
+            },
+            Token::Op("(") => {
+                let inner = self.expr()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Op(op) => {
+                self.pos -= 1;
+                Err(self.error(&format!("unexpected '{}'", op)))
+            }
+        }
+    }
+}
+
+pub fn parse(source: &str) -> Result<Block, String> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+    };
+    parser.block(&[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn num(n: i64) -> Box<Expr> {
+        Box::new(Expr::Num(n))
+    }
+
+    // The expression assigned by `x = ...`
+    fn expr(text: &str) -> Expr {
+        match parse(&format!("x = {}", text)).unwrap().remove(0).kind {
+            StmtKind::Assign { value, .. } => value,
+            kind => panic!("not an assignment: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn binds_operators_by_precedence() {
+        use BinOp::*;
+        assert_eq!(expr("1 + 2 * 3"), Expr::Binary(Add, num(1), Box::new(Expr::Binary(Mul, num(2), num(3)))));
+        assert_eq!(expr("(1 + 2) * 3"), Expr::Binary(Mul, Box::new(Expr::Binary(Add, num(1), num(2))), num(3)));
+        assert_eq!(expr("1 - 2 - 3"), Expr::Binary(Sub, Box::new(Expr::Binary(Sub, num(1), num(2))), num(3)));
+        // `^` is XOR, between | and &
+        assert_eq!(
+            expr("1 | 2 ^ 3 & 4"),
+            Expr::Binary(BitOr, num(1), Box::new(Expr::Binary(BitXor, num(2), Box::new(Expr::Binary(BitAnd, num(3), num(4))))))
+        );
+        assert_eq!(
+            expr("a == 1 or not b"),
+            Expr::Binary(
+                Or,
+                Box::new(Expr::Binary(Eq, Box::new(Expr::Var("a".into())), num(1))),
+                Box::new(Expr::Unary(UnOp::Not, Box::new(Expr::Var("b".into()))))
+            )
+        );
+        assert_eq!(expr("\"n=\" .. 1 + 2"), Expr::Binary(Concat, Box::new(Expr::Str("n=".into())), Box::new(Expr::Binary(Add, num(1), num(2)))));
+        assert_eq!(expr("-$10 ~= 0x10"), Expr::Binary(Ne, Box::new(Expr::Unary(UnOp::Neg, num(16))), num(16)));
+    }
+
+    #[test]
+    fn parses_elseif_chains() {
+        let source = "if a then x = 1\nelseif b then x = 2\nelseif c then x = 3\nelse x = 4 end";
+        let StmtKind::If { branches, otherwise } = &parse(source).unwrap()[0].kind else {
+            panic!("not an if");
+        };
+        let conditions: Vec<&Expr> = branches.iter().map(|(cond, _)| cond).collect();
+        assert_eq!(conditions, [&Expr::Var("a".into()), &Expr::Var("b".into()), &Expr::Var("c".into())]);
+        assert_eq!(branches[2].1[0].line, 3);
+        assert_eq!(otherwise.as_ref().map(|body| body[0].line), Some(4));
+    }
+
+    #[test]
+    fn parses_for_steps() {
+        let steps: Vec<Option<Expr>> = parse("for i = 1, 10 do end\nfor i = 10, 1, -2 do break end")
+            .unwrap()
+            .into_iter()
+            .map(|stmt| match stmt.kind {
+                StmtKind::For { step, .. } => step,
+                kind => panic!("not a for loop: {:?}", kind),
+            })
+            .collect();
+        assert_eq!(steps, [None, Some(Expr::Unary(UnOp::Neg, num(2)))]);
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let error = |source| parse(source).unwrap_err();
+        assert_eq!(error("x = 1\ny = (2\n"), "line 2: expected ')'");
+        assert_eq!(error("if x then\n  y = 1\n"), "line 2: expected 'end' before the end of the script");
+        assert_eq!(error("x = 1\n\n  @"), "line 3: unexpected '@'");
+        assert_eq!(error("x = 'abc"), "line 1: unfinished string");
+        assert_eq!(error("x = 0x1G"), "line 1: bad number '0x1G'");
+        assert_eq!(error("-- comment\nfoo bar"), "line 2: expected '=' or '(' after 'foo'");
+        assert_eq!(error("on vblank do end"), "line 1: unknown event 'vblank', expected frame, scanline or pc");
+        assert_eq!(error("x = 1 +\nend"), "line 2: unexpected 'end'");
+        assert_eq!(error("local end = 1"), "line 1: expected a name");
+    }
+}
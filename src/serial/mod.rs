@@ -0,0 +1,125 @@
+// Serial port (SB at 0xFF01, SC at 0xFF02)
+//
+// Transfers are modelled a byte at a time: an internally clocked transfer
+// completes 4096 cycles (8 bits at 8192 Hz) after it is started. When no
+// cable is attached the incoming byte is 0xFF, as on real hardware.
+
+#[cfg(feature = "alloc")]
+use crate::savestate::{SaveState, StateError, StateReader, StateWriter};
+
+/// Cycles needed to shift one full byte with the internal clock.
+pub const TRANSFER_CYCLES: u32 = 4096;
+
+#[derive(Clone)]
+pub struct Serial {
+    pub sb: u8,     // Serial transfer data
+    pub sc: u8,     // Serial transfer control
+    pub clock: u32, // Cycles spent on the current internally clocked transfer
+    pub linked: bool,
+    pub transfer_pending: bool, // Set when a linked transfer is waiting for the peer
+    pub interrupt: bool,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            sb: 0,
+            sc: 0x7E,
+            clock: 0,
+            linked: false,
+            transfer_pending: false,
+            interrupt: false,
+        }
+    }
+
+    pub fn read_sc(&self) -> u8 {
+        // Unused bits read back as 1
+        self.sc | 0x7E
+    }
+
+    pub fn write_sc(&mut self, value: u8) {
+        self.sc = value;
+        self.clock = 0;
+        self.transfer_pending = false;
+    }
+
+    // True while this side drives the clock of an active transfer
+    fn is_master(&self) -> bool {
+        self.sc & 0x81 == 0x81
+    }
+
+    // True while this side waits for a clock from the other end of the cable
+    fn is_armed_slave(&self) -> bool {
+        self.sc & 0x81 == 0x80
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        if !self.is_master() || self.transfer_pending {
+            return;
+        }
+
+        self.clock += cycles;
+        if self.clock < TRANSFER_CYCLES {
+            return;
+        }
+        self.clock = 0;
+
+        if self.linked {
+            // The owner of the cable finishes the exchange, see `Serial::exchange`
+            self.transfer_pending = true;
+        } else {
+            // Nothing connected: the line floats high
+            self.finish(0xFF);
+        }
+    }
+
+    fn finish(&mut self, incoming: u8) {
+        self.sb = incoming;
+        self.sc &= 0x7F;
+        self.transfer_pending = false;
+        self.interrupt = true;
+    }
+
+    /// Completes a transfer started by `master` against the other end of the cable.
+    /// Does nothing if `master` has no finished transfer waiting.
+    pub fn exchange(master: &mut Serial, slave: &mut Serial) {
+        if !master.transfer_pending {
+            return;
+        }
+
+        if slave.is_armed_slave() {
+            let outgoing = master.sb;
+            master.finish(slave.sb);
+            slave.finish(outgoing);
+        } else {
+            master.finish(0xFF);
+        }
+    }
+}
+
+// `linked` describes the cable, not the console, so it is not part of the state
+#[cfg(feature = "alloc")]
+impl SaveState for Serial {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.u32(self.clock);
+        w.bool(self.transfer_pending);
+        w.bool(self.interrupt);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.sb = r.u8()?;
+        self.sc = r.u8()?;
+        self.clock = r.u32()?;
+        self.transfer_pending = r.bool()?;
+        self.interrupt = r.bool()?;
+        Ok(())
+    }
+}
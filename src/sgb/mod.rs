@@ -0,0 +1,692 @@
+// Super Game Boy
+//
+// Commands arrive as 16-byte packets clocked in a bit at a time through
+// P14/P15 of the joypad register: a reset pulse (both low), then 128 bits
+// LSB first (P14 low = 0, P15 low = 1, each followed by both high), then a
+// 0 stop bit. Bulk data (palettes, border tiles and map, attribute files)
+// is sent by VRAM transfer: the game shows the data as tiles and the SGB
+// captures the next frame. The output is a 256x224 picture with the Game
+// Boy screen at (48, 40) inside the border.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::logging::debug;
+
+use crate::joypad::Joypad;
+use crate::memory::Rom;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::savestate::{SaveState, StateError, StateReader, StateWriter};
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// The Game Boy screen in 8x8 cells, each with its own palette
+const ATTR_COLS: usize = SCREEN_WIDTH / 8;
+const ATTR_ROWS: usize = SCREEN_HEIGHT / 8;
+
+const TRANSFER_SIZE: usize = 0x1000;
+const ATTR_FILE_SIZE: usize = 90;
+const ATTR_FILES: usize = 45;
+
+// 15-bit RGB palette shown until the game sets its own
+pub const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mask {
+    None,
+    Freeze, // Keep showing the last picture
+    Black,
+    Color0, // Fill with color 0 of palette 0
+}
+
+// VRAM transfer waiting for the next frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transfer {
+    Palettes,
+    Tiles { high: bool },
+    Border,
+    Attributes,
+}
+
+#[derive(Clone)]
+pub struct Sgb {
+    enabled: bool, // The cartridge header asks for SGB functions
+    receiving: bool,
+    bit: usize,
+    packet: [u8; 16],
+    command: Vec<u8>, // Packets of the command being received
+    select: u8,       // Last P14/P15 value written
+    pub players: u8,  // 1, 2 or 4, set by MLT_REQ
+    pub player: u8,   // Joypad reported while no button group is selected
+    pub palettes: [[u16; 4]; 4],
+    pub attributes: [u8; ATTR_COLS * ATTR_ROWS], // Palette per 8x8 cell
+    pub mask: Mask,
+    system_palettes: Vec<u8>, // PAL_TRN: 512 palettes of 4 colors
+    attribute_files: Vec<u8>, // ATTR_TRN: 45 attribute maps of 2 bits per cell
+    border_tiles: Vec<u8>,    // CHR_TRN: 256 SNES 4bpp tiles
+    border_map: Vec<u8>,      // PCT_TRN: 32x32 little-endian map entries
+    border_palettes: [[u16; 16]; 4], // PCT_TRN: SNES palettes 4-7
+    pending: Option<Transfer>,
+    pub frame_buffer: Vec<u16>, // 256x224, 15-bit RGB
+}
+
+impl Sgb {
+    // `rom` decides whether the game gets SGB functions: like the SGB BIOS,
+    // commands are only obeyed when the header flags SGB support
+    pub fn new(rom: &impl Rom) -> Self {
+        Sgb {
+            enabled: rom.byte(0x146) == Some(0x03) && rom.byte(0x14B) == Some(0x33),
+            receiving: false,
+            bit: 0,
+            packet: [0; 16],
+            command: Vec::new(),
+            select: 0x30,
+            players: 1,
+            player: 0,
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: [0; ATTR_COLS * ATTR_ROWS],
+            mask: Mask::None,
+            system_palettes: vec![0; TRANSFER_SIZE],
+            attribute_files: vec![0; ATTR_FILES * ATTR_FILE_SIZE],
+            border_tiles: vec![0; 2 * TRANSFER_SIZE],
+            border_map: vec![0; 0x800],
+            border_palettes: [[0; 16]; 4],
+            pending: None,
+            frame_buffer: vec![DEFAULT_PALETTE[0]; SGB_WIDTH * SGB_HEIGHT],
+        }
+    }
+
+    pub fn write_joypad(&mut self, value: u8) {
+        let select = value & 0x30;
+        match select {
+            0x00 => {
+                // Reset pulse starts a packet
+                self.receiving = true;
+                self.bit = 0;
+                self.packet = [0; 16];
+            }
+            0x10 | 0x20 if self.receiving && self.select == 0x30 => {
+                if self.bit == 128 {
+                    // Stop bit
+                    self.receiving = false;
+                    self.finish_packet();
+                } else {
+                    if select == 0x10 {
+                        self.packet[self.bit / 8] |= 1 << (self.bit % 8);
+                    }
+                    self.bit += 1;
+                }
+            }
+            _ => {}
+        }
+
+        // In multiplayer mode P15 going high moves on to the next joypad
+        if self.players > 1 && !self.receiving && self.select & 0x20 == 0 && select & 0x20 != 0 {
+            self.player = (self.player + 1) % self.players;
+        }
+        self.select = select;
+    }
+
+    // Only the first joypad has buttons; the others read as released.
+    // With no group selected the low nibble reports the current joypad.
+    pub fn read_joypad(&self, joypad: &Joypad) -> u8 {
+        if self.players > 1 && joypad.select == 0x30 {
+            0xC0 | 0x30 | (0x0F - self.player)
+        } else if self.player != 0 {
+            0xC0 | joypad.select | 0x0F
+        } else {
+            joypad.read()
+        }
+    }
+
+    fn finish_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+        let packets = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() >= packets * 16 {
+            let command = core::mem::take(&mut self.command);
+            if self.enabled {
+                self.execute(&command);
+            }
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]) & 0x7FFF;
+        match data[0] >> 3 {
+            // PAL01, PAL23, PAL03, PAL12: color 0 is shared by all palettes
+            cmd @ 0x00..=0x03 => {
+                let (a, b) = [(0, 1), (2, 3), (0, 3), (1, 2)][cmd as usize];
+                for palette in self.palettes.iter_mut() {
+                    palette[0] = color(1);
+                }
+                for i in 1..4 {
+                    self.palettes[a][i] = color(1 + i * 2);
+                    self.palettes[b][i] = color(7 + i * 2);
+                }
+            }
+            0x04 => self.attr_blk(data),
+            0x05 => self.attr_lin(data),
+            0x06 => self.attr_div(data),
+            0x07 => self.attr_chr(data),
+            0x0A => {
+                // PAL_SET: four palettes from the PAL_TRN data
+                for i in 0..4 {
+                    let n = (color(1 + i * 2) & 0x1FF) as usize;
+                    for c in 0..4 {
+                        let at = n * 8 + c * 2;
+                        self.palettes[i][c] =
+                            u16::from_le_bytes([self.system_palettes[at], self.system_palettes[at + 1]]) & 0x7FFF;
+                    }
+                }
+                self.attr_set(data[9]);
+            }
+            0x0B => self.pending = Some(Transfer::Palettes),
+            0x11 => {
+                // MLT_REQ
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            0x13 => self.pending = Some(Transfer::Tiles { high: data[1] & 0x01 != 0 }),
+            0x14 => self.pending = Some(Transfer::Border),
+            0x15 => self.pending = Some(Transfer::Attributes),
+            0x16 => self.attr_set(0x80 | (data[1] & 0x7F)),
+            0x17 => {
+                self.mask = match data[1] & 0x03 {
+                    0 => Mask::None,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                };
+            }
+            cmd => debug!("Ignoring SGB command {:02X}", cmd),
+        }
+    }
+
+    // Bit 7 applies attribute file bits 0-5, bit 6 cancels the mask
+    fn attr_set(&mut self, value: u8) {
+        if value & 0x80 != 0 {
+            let file = (value & 0x3F) as usize;
+            if file < ATTR_FILES {
+                let data = &self.attribute_files[file * ATTR_FILE_SIZE..(file + 1) * ATTR_FILE_SIZE];
+                for (i, attr) in self.attributes.iter_mut().enumerate() {
+                    *attr = (data[i / 4] >> (6 - (i % 4) * 2)) & 0x03;
+                }
+            }
+        }
+        if value & 0x40 != 0 {
+            self.mask = Mask::None;
+        }
+    }
+
+    // ATTR_BLK: rectangles with separate palettes inside, on the edge and outside
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = (data[1] & 0x1F) as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let control = set[0] & 0x07;
+            let pal_in = set[1] & 0x03;
+            let pal_line = (set[1] >> 2) & 0x03;
+            let pal_out = (set[1] >> 4) & 0x03;
+            let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+
+            // With only inside or only outside selected the edge follows it
+            let line = match control {
+                0x01 => Some(pal_in),
+                0x04 => Some(pal_out),
+                c if c & 0x02 != 0 => Some(pal_line),
+                _ => None,
+            };
+
+            for y in 0..ATTR_ROWS {
+                for x in 0..ATTR_COLS {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = if edge {
+                        line
+                    } else if within {
+                        (control & 0x01 != 0).then_some(pal_in)
+                    } else {
+                        (control & 0x04 != 0).then_some(pal_out)
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * ATTR_COLS + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    // ATTR_LIN: whole rows or columns
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &entry in data[2..].iter().take(count) {
+            let line = (entry & 0x1F) as usize;
+            let palette = (entry >> 5) & 0x03;
+            if entry & 0x80 != 0 {
+                if line < ATTR_ROWS {
+                    self.attributes[line * ATTR_COLS..(line + 1) * ATTR_COLS].fill(palette);
+                }
+            } else if line < ATTR_COLS {
+                for y in 0..ATTR_ROWS {
+                    self.attributes[y * ATTR_COLS + line] = palette;
+                }
+            }
+        }
+    }
+
+    // ATTR_DIV: split the screen in two at a row or column
+    fn attr_div(&mut self, data: &[u8]) {
+        let pal_after = data[1] & 0x03;
+        let pal_before = (data[1] >> 2) & 0x03;
+        let pal_line = (data[1] >> 4) & 0x03;
+        let horizontal = data[1] & 0x40 != 0;
+        let at = data[2] as usize;
+        for y in 0..ATTR_ROWS {
+            for x in 0..ATTR_COLS {
+                let pos = if horizontal { y } else { x };
+                self.attributes[y * ATTR_COLS + x] = match pos.cmp(&at) {
+                    core::cmp::Ordering::Less => pal_before,
+                    core::cmp::Ordering::Equal => pal_line,
+                    core::cmp::Ordering::Greater => pal_after,
+                };
+            }
+        }
+    }
+
+    // ATTR_CHR: individual cells, four 2-bit palettes per byte
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let vertical = data[5] & 0x01 != 0;
+        for i in 0..count.min(ATTR_COLS * ATTR_ROWS) {
+            let Some(&byte) = data.get(6 + i / 4) else {
+                break;
+            };
+            if x < ATTR_COLS && y < ATTR_ROWS {
+                self.attributes[y * ATTR_COLS + x] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+            }
+            if vertical {
+                y += 1;
+                if y >= ATTR_ROWS {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x >= ATTR_COLS {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    // Reads back the 4 KiB a game displays for a VRAM transfer: 256 tiles,
+    // 20 per row, from the shades of the finished frame
+    fn capture(frame_buffer: &[u8]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(TRANSFER_SIZE);
+        for tile in 0..TRANSFER_SIZE / 16 {
+            let (tx, ty) = (tile % ATTR_COLS, tile / ATTR_COLS);
+            for row in 0..8 {
+                let (mut low, mut high) = (0u8, 0u8);
+                for col in 0..8 {
+                    let shade = frame_buffer[(ty * 8 + row) * SCREEN_WIDTH + tx * 8 + col];
+                    low |= (shade & 0x01) << (7 - col);
+                    high |= ((shade >> 1) & 0x01) << (7 - col);
+                }
+                data.push(low);
+                data.push(high);
+            }
+        }
+        data
+    }
+
+    // Called at VBlank with the finished DMG frame (shades 0-3)
+    pub fn on_vblank(&mut self, frame_buffer: &[u8]) {
+        if let Some(transfer) = self.pending.take() {
+            let data = Self::capture(frame_buffer);
+            match transfer {
+                Transfer::Palettes => self.system_palettes.copy_from_slice(&data),
+                Transfer::Tiles { high } => {
+                    let start = if high { TRANSFER_SIZE } else { 0 };
+                    self.border_tiles[start..start + TRANSFER_SIZE].copy_from_slice(&data);
+                }
+                Transfer::Border => {
+                    self.border_map.copy_from_slice(&data[..0x800]);
+                    for (i, palette) in self.border_palettes.iter_mut().enumerate() {
+                        for (c, color) in palette.iter_mut().enumerate() {
+                            let at = 0x800 + (i * 16 + c) * 2;
+                            *color = u16::from_le_bytes([data[at], data[at + 1]]) & 0x7FFF;
+                        }
+                    }
+                }
+                Transfer::Attributes => self.attribute_files.copy_from_slice(&data[..ATTR_FILES * ATTR_FILE_SIZE]),
+            }
+        }
+        self.compose(frame_buffer);
+    }
+
+    fn compose(&mut self, frame_buffer: &[u8]) {
+        let backdrop = self.palettes[0][0];
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                let (gx, gy) = (x.wrapping_sub(SCREEN_X), y.wrapping_sub(SCREEN_Y));
+                let color = if gx < SCREEN_WIDTH && gy < SCREEN_HEIGHT {
+                    match self.mask {
+                        Mask::Freeze => continue,
+                        Mask::Black => 0,
+                        Mask::Color0 => backdrop,
+                        Mask::None => {
+                            let palette = self.attributes[(gy / 8) * ATTR_COLS + gx / 8] as usize;
+                            let shade = frame_buffer[gy * SCREEN_WIDTH + gx] as usize & 0x03;
+                            self.palettes[palette][shade]
+                        }
+                    }
+                } else {
+                    backdrop
+                };
+                self.frame_buffer[y * SGB_WIDTH + x] = color;
+            }
+        }
+        self.draw_border();
+    }
+
+    // The border sits on top; its color 0 is transparent
+    fn draw_border(&mut self) {
+        for ty in 0..SGB_HEIGHT / 8 {
+            for tx in 0..SGB_WIDTH / 8 {
+                let at = (ty * 32 + tx) * 2;
+                let entry = u16::from_le_bytes([self.border_map[at], self.border_map[at + 1]]);
+                let tile = &self.border_tiles[(entry & 0xFF) as usize * 32..][..32];
+                let palette = &self.border_palettes[((entry >> 10) & 0x03) as usize];
+                for row in 0..8 {
+                    let r = if entry & 0x8000 != 0 { 7 - row } else { row };
+                    let planes = [tile[r * 2], tile[r * 2 + 1], tile[16 + r * 2], tile[16 + r * 2 + 1]];
+                    for col in 0..8 {
+                        let bit = if entry & 0x4000 != 0 { col } else { 7 - col };
+                        let index = planes
+                            .iter()
+                            .enumerate()
+                            .fold(0, |acc, (p, plane)| acc | (((plane >> bit) & 1) as usize) << p);
+                        if index != 0 {
+                            self.frame_buffer[(ty * 8 + row) * SGB_WIDTH + tx * 8 + col] = palette[index];
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl SaveState for Sgb {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.receiving);
+        w.u8(self.bit as u8);
+        w.bytes(&self.packet);
+        w.bytes(&self.command);
+        w.u8(self.select);
+        w.u8(self.players);
+        w.u8(self.player);
+        for color in self.palettes.iter().flatten() {
+            w.u16(*color);
+        }
+        w.bytes(&self.attributes);
+        w.u8(self.mask as u8);
+        w.bytes(&self.system_palettes);
+        w.bytes(&self.attribute_files);
+        w.bytes(&self.border_tiles);
+        w.bytes(&self.border_map);
+        for color in self.border_palettes.iter().flatten() {
+            w.u16(*color);
+        }
+        w.u8(match self.pending {
+            None => 0,
+            Some(Transfer::Palettes) => 1,
+            Some(Transfer::Tiles { high: false }) => 2,
+            Some(Transfer::Tiles { high: true }) => 3,
+            Some(Transfer::Border) => 4,
+            Some(Transfer::Attributes) => 5,
+        });
+        for &color in &self.frame_buffer {
+            w.u16(color);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.receiving = r.bool()?;
+        self.bit = r.u8()? as usize;
+        if self.bit > 128 {
+            return Err(StateError::Invalid("SGB packet bit"));
+        }
+        r.bytes_into(&mut self.packet, "SGB packet size")?;
+        self.command = r.bytes()?.to_vec();
+        if self.command.len() > 7 * 16 {
+            return Err(StateError::Invalid("SGB command size"));
+        }
+        self.select = r.u8()? & 0x30;
+        self.players = r.u8()?;
+        self.player = r.u8()?;
+        if ![1, 2, 4].contains(&self.players) || self.player >= self.players {
+            return Err(StateError::Invalid("SGB player count"));
+        }
+        for color in self.palettes.iter_mut().flatten() {
+            *color = r.u16()?;
+        }
+        r.bytes_into(&mut self.attributes, "SGB attribute size")?;
+        // Each cell picks one of the four palettes
+        if self.attributes.iter().any(|&palette| palette > 3) {
+            return Err(StateError::Invalid("SGB attribute"));
+        }
+        self.mask = match r.u8()? {
+            0 => Mask::None,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => return Err(StateError::Invalid("SGB mask")),
+        };
+        r.bytes_into(&mut self.system_palettes, "SGB palette data size")?;
+        r.bytes_into(&mut self.attribute_files, "SGB attribute file size")?;
+        r.bytes_into(&mut self.border_tiles, "SGB border tile size")?;
+        r.bytes_into(&mut self.border_map, "SGB border map size")?;
+        for color in self.border_palettes.iter_mut().flatten() {
+            *color = r.u16()?;
+        }
+        self.pending = match r.u8()? {
+            0 => None,
+            1 => Some(Transfer::Palettes),
+            2 => Some(Transfer::Tiles { high: false }),
+            3 => Some(Transfer::Tiles { high: true }),
+            4 => Some(Transfer::Border),
+            5 => Some(Transfer::Attributes),
+            _ => return Err(StateError::Invalid("SGB transfer")),
+        };
+        for color in self.frame_buffer.iter_mut() {
+            *color = r.u16()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reload(sgb: &Sgb) -> Result<(), StateError> {
+        let mut w = StateWriter::new();
+        sgb.save_state(&mut w);
+        let state = w.into_bytes();
+        Sgb::new(&vec![0; 0x8000]).load_state(&mut StateReader::new(&state))
+    }
+
+    fn sgb() -> Sgb {
+        let mut rom = vec![0; 0x8000];
+        rom[0x146] = 0x03;
+        rom[0x14B] = 0x33;
+        Sgb::new(&rom)
+    }
+
+    // Clocks a command in through P1 the way games do, packet by packet
+    fn send(sgb: &mut Sgb, command: &[u8]) {
+        for packet in command.chunks(16) {
+            sgb.write_joypad(0x00);
+            sgb.write_joypad(0x30);
+            for i in 0..128 {
+                let bit = packet.get(i / 8).copied().unwrap_or(0) >> (i % 8) & 0x01;
+                sgb.write_joypad(if bit != 0 { 0x10 } else { 0x20 });
+                sgb.write_joypad(0x30);
+            }
+            sgb.write_joypad(0x20);
+            sgb.write_joypad(0x30);
+        }
+    }
+
+    fn attribute(sgb: &Sgb, x: usize, y: usize) -> u8 {
+        sgb.attributes[y * ATTR_COLS + x]
+    }
+
+    #[test]
+    fn assembles_packets_from_p1_writes() {
+        let mut sgb = sgb();
+        send(&mut sgb, &[0xB9, 0x02]); // MASK_EN black
+        assert_eq!(sgb.mask, Mask::Black);
+        assert_eq!(sgb.packet[..2], [0xB9, 0x02]);
+
+        // A reset pulse drops a half-sent packet
+        sgb.write_joypad(0x00);
+        sgb.write_joypad(0x30);
+        sgb.write_joypad(0x10);
+        sgb.write_joypad(0x30);
+        send(&mut sgb, &[0xB9, 0x00]);
+        assert_eq!(sgb.mask, Mask::None);
+
+        // Without the SGB flag in the header nothing is obeyed
+        let mut dmg_game = Sgb::new(&vec![0; 0x8000]);
+        send(&mut dmg_game, &[0xB9, 0x02]);
+        assert_eq!(dmg_game.mask, Mask::None);
+    }
+
+    #[test]
+    fn pal01_shares_color_0() {
+        let mut sgb = sgb();
+        let mut command = [0; 16];
+        command[0] = 0x01;
+        for (i, color) in [0x7FFF, 0x0001, 0x0002, 0x0003, 0x0011, 0x0012, 0x0013u16].iter().enumerate() {
+            command[1 + i * 2..3 + i * 2].copy_from_slice(&color.to_le_bytes());
+        }
+        send(&mut sgb, &command);
+        assert_eq!(sgb.palettes[0], [0x7FFF, 0x0001, 0x0002, 0x0003]);
+        assert_eq!(sgb.palettes[1], [0x7FFF, 0x0011, 0x0012, 0x0013]);
+        assert_eq!(sgb.palettes[2], [0x7FFF, DEFAULT_PALETTE[1], DEFAULT_PALETTE[2], DEFAULT_PALETTE[3]]);
+    }
+
+    #[test]
+    fn pal_set_picks_system_palettes_and_an_attribute_file() {
+        let mut sgb = sgb();
+        for (n, color) in [(5, 0x0100u16), (6, 0x0200), (7, 0x0300), (300, 0x0400)] {
+            for c in 0..4 {
+                let at = n * 8 + c * 2;
+                sgb.system_palettes[at..at + 2].copy_from_slice(&(color + c as u16).to_le_bytes());
+            }
+        }
+        // Attribute file 2: palette 3 in the first cell, 1 in the last
+        sgb.attribute_files[2 * ATTR_FILE_SIZE] = 0xC0;
+        sgb.attribute_files[3 * ATTR_FILE_SIZE - 1] = 0x01;
+        sgb.mask = Mask::Freeze;
+
+        // Palettes 5, 6, 7 and 300, then attribute file 2 with the mask off
+        send(&mut sgb, &[0x51, 5, 0, 6, 0, 7, 0, 0x2C, 0x01, 0xC2]);
+        assert_eq!(sgb.palettes[0], [0x0100, 0x0101, 0x0102, 0x0103]);
+        assert_eq!(sgb.palettes[3], [0x0400, 0x0401, 0x0402, 0x0403]);
+        assert_eq!(attribute(&sgb, 0, 0), 3);
+        assert_eq!(attribute(&sgb, 1, 0), 0);
+        assert_eq!(attribute(&sgb, ATTR_COLS - 1, ATTR_ROWS - 1), 1);
+        assert_eq!(sgb.mask, Mask::None);
+    }
+
+    #[test]
+    fn attr_blk_paints_inside_edge_and_outside() {
+        let mut sgb = sgb();
+        // One block from (2, 3) to (5, 6): inside 1, edge 2, outside 3
+        send(&mut sgb, &[0x21, 0x01, 0x07, 0x39, 2, 3, 5, 6]);
+        assert_eq!(attribute(&sgb, 0, 0), 3);
+        assert_eq!(attribute(&sgb, 2, 3), 2);
+        assert_eq!(attribute(&sgb, 5, 4), 2);
+        assert_eq!(attribute(&sgb, 3, 4), 1);
+        assert_eq!(attribute(&sgb, 4, 5), 1);
+        assert_eq!(attribute(&sgb, 6, 4), 3);
+
+        // Only the inside: the edge goes with it, the outside is kept
+        send(&mut sgb, &[0x21, 0x01, 0x01, 0x00, 0, 0, 1, 1]);
+        assert_eq!(attribute(&sgb, 0, 0), 0);
+        assert_eq!(attribute(&sgb, 1, 1), 0);
+        assert_eq!(attribute(&sgb, 2, 2), 3);
+        assert_eq!(attribute(&sgb, 3, 4), 1);
+    }
+
+    #[test]
+    fn attr_chr_wraps_at_the_screen_edge() {
+        let mut sgb = sgb();
+        // Two packets: 4 cells left to right from (18, 0), palettes 0-3
+        let mut command = [0; 32];
+        command[..7].copy_from_slice(&[0x3A, 18, 0, 4, 0, 0, 0x1B]);
+        sgb.attributes.fill(2);
+        send(&mut sgb, &command[..16]);
+        assert_eq!(attribute(&sgb, 18, 0), 2); // Waits for the second packet
+        send(&mut sgb, &command[16..]);
+        assert_eq!(attribute(&sgb, 18, 0), 0);
+        assert_eq!(attribute(&sgb, 19, 0), 1);
+        assert_eq!(attribute(&sgb, 0, 1), 2);
+        assert_eq!(attribute(&sgb, 1, 1), 3);
+        assert_eq!(attribute(&sgb, 2, 1), 2);
+
+        // Top to bottom wraps into the next column
+        send(&mut sgb, &[0x39, 3, ATTR_ROWS as u8 - 1, 2, 0, 1, 0x70]);
+        assert_eq!(attribute(&sgb, 3, ATTR_ROWS - 1), 1);
+        assert_eq!(attribute(&sgb, 4, 0), 3);
+    }
+
+    #[test]
+    fn mlt_req_cycles_joypad_ids() {
+        let mut sgb = sgb();
+        let mut joypad = Joypad::new();
+        joypad.select = 0x30;
+        let next = |sgb: &mut Sgb| {
+            sgb.write_joypad(0x10);
+            sgb.write_joypad(0x30);
+        };
+
+        send(&mut sgb, &[0x89, 0x03]); // Four players
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            ids.push(sgb.read_joypad(&joypad) & 0x0F);
+            next(&mut sgb);
+        }
+        assert_eq!(ids, [0x0F, 0x0E, 0x0D, 0x0C, 0x0F]);
+
+        send(&mut sgb, &[0x89, 0x01]); // Two players
+        next(&mut sgb);
+        assert_eq!(sgb.read_joypad(&joypad) & 0x0F, 0x0E);
+        next(&mut sgb);
+        assert_eq!(sgb.read_joypad(&joypad) & 0x0F, 0x0F);
+
+        send(&mut sgb, &[0x89, 0x00]); // Back to one
+        next(&mut sgb);
+        assert_eq!(sgb.players, 1);
+        assert_eq!(sgb.read_joypad(&joypad), joypad.read());
+    }
+
+    #[test]
+    fn rejects_attributes_past_the_last_palette() {
+        let mut sgb = Sgb::new(&vec![0; 0x8000]);
+        sgb.attributes[ATTR_COLS + 1] = 3;
+        assert_eq!(reload(&sgb), Ok(()));
+        sgb.attributes[ATTR_COLS + 1] = 4;
+        assert_eq!(reload(&sgb), Err(StateError::Invalid("SGB attribute")));
+    }
+}
@@ -0,0 +1,31 @@
+// Backing storage for the emulated memories
+//
+// With `alloc` the large buffers (VRAM, OAM, frame buffers) live on the heap
+// so the console stays cheap to move and clone. Without it they are
+// fixed-size arrays inside their structs, and the whole console can sit in a
+// `static` on a microcontroller. Code indexes and slices them the same way
+// either way.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+// `N` is the size in both builds; the heap version just doesn't carry it
+#[cfg(feature = "alloc")]
+pub type Buffer<T, const N: usize> = Vec<T>;
+#[cfg(not(feature = "alloc"))]
+pub type Buffer<T, const N: usize> = [T; N];
+
+pub fn buffer<T: Copy, const N: usize>(fill: T) -> Buffer<T, N> {
+    #[cfg(feature = "alloc")]
+    let buffer = alloc::vec![fill; N];
+    #[cfg(not(feature = "alloc"))]
+    let buffer = [fill; N];
+    buffer
+}
+
+// Images owned by the console, like a boot ROM. Without `alloc` they have
+// to be borrowed for good, typically from flash.
+#[cfg(feature = "alloc")]
+pub type Bytes = Vec<u8>;
+#[cfg(not(feature = "alloc"))]
+pub type Bytes = &'static [u8];
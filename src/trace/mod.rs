@@ -0,0 +1,69 @@
+// Execution tracer in the Gameboy Doctor log format
+//
+// One line per instruction, taken before it executes:
+//   A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+// These logs can be diffed line by line against reference emulators.
+// `GameBoy` only holds a tracer while tracing is on, so the disabled path
+// costs a single `Option` check per instruction.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::logging::error;
+
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+
+pub struct Tracer {
+    out: BufWriter<Box<dyn Write>>,
+    lines: u64,
+    failed: bool,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        Tracer {
+            out: BufWriter::new(writer),
+            lines: 0,
+            failed: false,
+        }
+    }
+
+    pub fn to_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(Box::new(File::create(path)?)))
+    }
+
+    // Instructions traced so far
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    pub fn trace(&mut self, cpu: &Cpu, memory: &Memory) {
+        if self.failed {
+            return;
+        }
+
+        let pc = cpu.pc;
+        let mem = |offset: u16| memory.peek(pc.wrapping_add(offset));
+        let result = writeln!(
+            self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, pc,
+            mem(0), mem(1), mem(2), mem(3)
+        );
+
+        match result {
+            Ok(()) => self.lines += 1,
+            Err(e) => {
+                // Stop tracing rather than logging the same failure every instruction
+                error!("Trace output failed after {} lines: {}", self.lines, e);
+                self.failed = true;
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
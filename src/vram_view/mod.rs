@@ -0,0 +1,251 @@
+// VRAM viewer: offscreen renderings of PPU memory for debugging
+//
+// Every view renders into its own ARGB image and only reads the PPU, so the
+// game's frame buffer is never touched. `shades` maps the four DMG shades
+// (0 = lightest) to colors.
+
+use crate::png;
+use crate::ppu::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const VIEWPORT_COLOR: u32 = 0xFFFF0000;
+const GAP_COLOR: u32 = 0xFF808080;
+
+pub struct DebugImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>, // 0xAARRGGBB
+}
+
+impl DebugImage {
+    pub fn new(width: usize, height: usize, fill: u32) -> Self {
+        DebugImage {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: u32) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: u32) {
+        for py in y..y + h {
+            for px in x..x + w {
+                self.set(px, py, color);
+            }
+        }
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode_argb(self.width, self.height, &self.pixels)
+    }
+}
+
+// Color index (0-3) of one pixel of the tile at VRAM offset `tile_addr`
+fn tile_pixel(ppu: &Ppu, tile_addr: usize, x: usize, y: usize) -> u8 {
+    let lo = ppu.vram[tile_addr + y * 2];
+    let hi = ppu.vram[tile_addr + y * 2 + 1];
+    let bit = 7 - x;
+    (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+}
+
+// VRAM offset of a tile index under either LCDC.4 addressing mode
+fn tile_addr(tile_idx: u8, signed: bool) -> usize {
+    if signed {
+        (0x1000 + (tile_idx as i8 as isize) * 16) as usize
+    } else {
+        tile_idx as usize * 16
+    }
+}
+
+fn draw_tile(img: &mut DebugImage, ppu: &Ppu, addr: usize, x0: usize, y0: usize, palette: u8, shades: &[u32; 4]) {
+    for y in 0..8 {
+        for x in 0..8 {
+            let color_idx = tile_pixel(ppu, addr, x, y);
+            let shade = (palette >> (color_idx * 2)) & 0x03;
+            img.set(x0 + x, y0 + y, shades[shade as usize]);
+        }
+    }
+}
+
+// All tiles as 16x16 grids for both addressing modes: 0x8000 on the left,
+// 0x8800 on the right, ordered by the index a tile map would use.
+// Together the two grids cover all 384 tiles; 0x8800-0x8FFF appears in both.
+pub fn render_tiles(ppu: &Ppu, shades: &[u32; 4]) -> DebugImage {
+    const GRID: usize = 16 * 8;
+    const GAP: usize = 4;
+    let mut img = DebugImage::new(GRID * 2 + GAP, GRID, GAP_COLOR);
+
+    for (panel, signed) in [false, true].into_iter().enumerate() {
+        let x_offset = panel * (GRID + GAP);
+        for idx in 0..=255u8 {
+            let (tx, ty) = (idx as usize % 16, idx as usize / 16);
+            // Identity palette: show raw color indices
+            draw_tile(&mut img, ppu, tile_addr(idx, signed), x_offset + tx * 8, ty * 8, 0xE4, shades);
+        }
+    }
+    img
+}
+
+// Full 256x256 background map at 0x9800 (`high_map` false) or 0x9C00, using
+// the current tile addressing mode and BGP, with the SCX/SCY viewport outlined
+pub fn render_bg_map(ppu: &Ppu, high_map: bool, shades: &[u32; 4]) -> DebugImage {
+    let mut img = DebugImage::new(256, 256, shades[0]);
+    let map_base = if high_map { 0x1C00 } else { 0x1800 };
+    let signed = ppu.lcdc & 0x10 == 0;
+
+    for ty in 0..32 {
+        for tx in 0..32 {
+            let tile_idx = ppu.vram[map_base + ty * 32 + tx];
+            draw_tile(&mut img, ppu, tile_addr(tile_idx, signed), tx * 8, ty * 8, ppu.bgp, shades);
+        }
+    }
+
+    // The viewport wraps around the map edges
+    let (sx, sy) = (ppu.scx as usize, ppu.scy as usize);
+    for i in 0..SCREEN_WIDTH {
+        img.set((sx + i) % 256, sy, VIEWPORT_COLOR);
+        img.set((sx + i) % 256, (sy + SCREEN_HEIGHT - 1) % 256, VIEWPORT_COLOR);
+    }
+    for i in 0..SCREEN_HEIGHT {
+        img.set(sx, (sy + i) % 256, VIEWPORT_COLOR);
+        img.set((sx + SCREEN_WIDTH - 1) % 256, (sy + i) % 256, VIEWPORT_COLOR);
+    }
+    img
+}
+
+// One decoded OAM entry
+pub struct OamEntry {
+    pub index: usize,
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+impl OamEntry {
+    pub fn behind_bg(&self) -> bool {
+        self.attributes & 0x80 != 0
+    }
+
+    pub fn y_flip(&self) -> bool {
+        self.attributes & 0x40 != 0
+    }
+
+    pub fn x_flip(&self) -> bool {
+        self.attributes & 0x20 != 0
+    }
+
+    pub fn palette(&self) -> u8 {
+        (self.attributes >> 4) & 1
+    }
+
+    // On screen at all (OAM coordinates are offset by 16/8)
+    pub fn visible(&self) -> bool {
+        self.y > 0 && self.y < 160 && self.x > 0 && self.x < 168
+    }
+}
+
+pub fn oam_entries(ppu: &Ppu) -> Vec<OamEntry> {
+    ppu.oam
+        .chunks_exact(4)
+        .enumerate()
+        .map(|(index, entry)| OamEntry {
+            index,
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            attributes: entry[3],
+        })
+        .collect()
+}
+
+// Text listing of all 40 sprites
+pub fn oam_table(ppu: &Ppu) -> String {
+    let mut out = String::from(" #   Y   X  tile attr  prio    flip  pal   visible\n");
+    for e in oam_entries(ppu) {
+        out.push_str(&format!(
+            "{:2}  {:3} {:3}  ${:02X}  ${:02X}  {:<6}  {}{}    OBP{}  {}\n",
+            e.index,
+            e.y,
+            e.x,
+            e.tile,
+            e.attributes,
+            if e.behind_bg() { "behind" } else { "front" },
+            if e.x_flip() { 'X' } else { '-' },
+            if e.y_flip() { 'Y' } else { '-' },
+            e.palette(),
+            if e.visible() { "yes" } else { "no" }
+        ));
+    }
+    out
+}
+
+// The 40 sprites in OAM order, 8 per row, with flips and OBP0/OBP1 applied.
+// Tall sprites (LCDC.2) take two tiles each.
+pub fn render_oam(ppu: &Ppu, shades: &[u32; 4]) -> DebugImage {
+    let tall = ppu.lcdc & 0x04 != 0;
+    let cell_h = if tall { 16 } else { 8 };
+    let (cols, rows) = (8, 5);
+    let (pitch_x, pitch_y) = (8 + 2, cell_h + 2);
+    let mut img = DebugImage::new(cols * pitch_x, rows * pitch_y, GAP_COLOR);
+
+    for e in oam_entries(ppu) {
+        let (x0, y0) = ((e.index % cols) * pitch_x + 1, (e.index / cols) * pitch_y + 1);
+        let palette = if e.palette() == 0 { ppu.obp0 } else { ppu.obp1 };
+        let tile = if tall { e.tile & 0xFE } else { e.tile };
+
+        for y in 0..cell_h {
+            let src_y = if e.y_flip() { cell_h - 1 - y } else { y };
+            let addr = tile_addr(tile, false) + (src_y / 8) * 16;
+            for x in 0..8 {
+                let src_x = if e.x_flip() { 7 - x } else { x };
+                let color_idx = tile_pixel(ppu, addr, src_x, src_y % 8);
+                let shade = (palette >> (color_idx * 2)) & 0x03;
+                img.set(x0 + x, y0 + y, shades[shade as usize]);
+            }
+        }
+    }
+    img
+}
+
+// BGP, OBP0 and OBP1 as rows of four 16x16 swatches (color index 0-3)
+pub fn render_palettes(ppu: &Ppu, shades: &[u32; 4]) -> DebugImage {
+    const SWATCH: usize = 16;
+    let mut img = DebugImage::new(SWATCH * 4, SWATCH * 3, GAP_COLOR);
+    for (row, palette) in [ppu.bgp, ppu.obp0, ppu.obp1].into_iter().enumerate() {
+        for color_idx in 0..4 {
+            let shade = (palette >> (color_idx * 2)) & 0x03;
+            img.fill_rect(color_idx * SWATCH + 1, row * SWATCH + 1, SWATCH - 2, SWATCH - 2, shades[shade as usize]);
+        }
+    }
+    img
+}
+
+// The four diagnostic tiles (solid, checkerboard, border, diagonal) in every
+// shade, for checking the display path without depending on VRAM contents
+pub fn render_test_pattern(shades: &[u32; 4]) -> DebugImage {
+    let rows: [fn(usize) -> u8; 4] = [
+        |_| 0xFF,
+        |y| if y % 2 == 0 { 0xAA } else { 0x55 },
+        |y| if y == 0 || y == 7 { 0xFF } else { 0x81 },
+        |y| 1 << y,
+    ];
+
+    let mut img = DebugImage::new(4 * 8, 4 * 8, shades[0]);
+    for (shade_row, &shade) in shades.iter().enumerate() {
+        for (col, row_bits) in rows.iter().enumerate() {
+            for y in 0..8 {
+                let bits = row_bits(y);
+                for x in 0..8 {
+                    let color = if bits & (0x80 >> x) != 0 { shade } else { shades[0] };
+                    img.set(col * 8 + x, shade_row * 8 + y, color);
+                }
+            }
+        }
+    }
+    img
+}
@@ -0,0 +1,186 @@
+// WebAssembly API
+//
+// A minimal C ABI for JavaScript hosts, exported from the cdylib when the
+// crate is built for wasm32-unknown-unknown without the `frontend` feature:
+//
+//     cargo build --release --lib --target wasm32-unknown-unknown --no-default-features --features std
+//
+// Byte buffers cross the boundary through the module's linear memory: the
+// host reserves space with gb_alloc, copies a ROM or save state into it and
+// passes the pointer on. Results (framebuffer, audio, save state) stay in
+// buffers owned by the core and are read through the returned pointers
+// until the next call that refills them. See wasm/headless.mjs for a host.
+//
+// The functions are plain Rust on other targets, which keeps them built
+// and linted with the rest of the crate without exporting the symbols from
+// the libretro core.
+#![allow(clippy::missing_safety_doc)]
+
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::capture::{CLOCK_HZ, SAMPLE_RATE};
+use crate::config::Config;
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::logging::warn;
+use crate::ppu::rgb555_to_argb;
+
+struct Core {
+    gameboy: GameBoy,
+    rgba: Vec<u8>,
+    audio: Vec<i16>,
+    sample_clock: u64,
+    state: Vec<u8>,
+}
+
+// The console is only `!Send` for the tracer's writer, which is never set up
+// here
+unsafe impl Send for Core {}
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn core() -> MutexGuard<'static, Option<Core>> {
+    CORE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Reserves `len` bytes of linear memory for the host to fill
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_alloc(len: usize) -> *mut u8 {
+    let mut buffer = vec![0u8; len].into_boxed_slice();
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+// Releases a buffer from gb_alloc; `len` must be the size it was made with
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub unsafe extern "C" fn gb_free(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) });
+    }
+}
+
+// Powers on with the ROM, replacing any running game. The ROM is copied.
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub unsafe extern "C" fn gb_load_rom(ptr: *const u8, len: usize) -> bool {
+    // The header must be there, and the flat mapping needs both ROM banks
+    if ptr.is_null() || len < 0x8000 {
+        return false;
+    }
+    let rom = unsafe { slice::from_raw_parts(ptr, len) };
+    let gameboy = GameBoy::with_config(rom, &Config::for_rom(rom));
+    let (width, height, _) = gameboy.screen();
+    *core() = Some(Core {
+        gameboy,
+        rgba: vec![0; width * height * 4],
+        audio: Vec::new(),
+        sample_clock: 0,
+        state: Vec::new(),
+    });
+    true
+}
+
+// Holds the buttons (joypad::BUTTON_* bits) down from the next frame on
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_set_buttons(buttons: u8) {
+    if let Some(core) = &mut *core() {
+        core.gameboy.set_buttons(buttons);
+    }
+}
+
+// Runs one frame and refills the framebuffer and audio buffers
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_run_frame() -> bool {
+    let mut core = core();
+    let Some(core) = &mut *core else {
+        return false;
+    };
+    core.gameboy.run_frame();
+
+    let (_, _, screen) = core.gameboy.screen();
+    core.rgba.clear();
+    for &color in screen.iter() {
+        let [b, g, r, a] = rgb555_to_argb(color).to_le_bytes();
+        core.rgba.extend_from_slice(&[r, g, b, a]);
+    }
+
+    // One frame's worth of stereo samples, carrying the remainder
+    core.sample_clock += SAMPLE_RATE as u64 * CYCLES_PER_FRAME as u64;
+    let frames = (core.sample_clock / CLOCK_HZ) as usize;
+    core.sample_clock %= CLOCK_HZ;
+    core.audio.clear();
+    core.audio.resize(frames * 2, 0);
+    true
+}
+
+// Size of the framebuffer, which changes when an SGB border comes and goes
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_screen_width() -> usize {
+    core().as_ref().map_or(0, |core| core.gameboy.screen().0)
+}
+
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_screen_height() -> usize {
+    core().as_ref().map_or(0, |core| core.gameboy.screen().1)
+}
+
+// The last frame as RGBA bytes, ready for an ImageData
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_framebuffer() -> *const u8 {
+    core().as_ref().map_or(std::ptr::null(), |core| core.rgba.as_ptr())
+}
+
+// The last frame's audio: interleaved stereo i16 at gb_sample_rate
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_audio() -> *const i16 {
+    core().as_ref().map_or(std::ptr::null(), |core| core.audio.as_ptr())
+}
+
+// Number of i16 samples (both channels) behind gb_audio
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_audio_len() -> usize {
+    core().as_ref().map_or(0, |core| core.audio.len())
+}
+
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_sample_rate() -> u32 {
+    SAMPLE_RATE
+}
+
+// Saves the state into the core's buffer and returns its size, 0 without a
+// game. gb_state then points at the bytes.
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_save_state() -> usize {
+    let mut core = core();
+    let Some(core) = &mut *core else {
+        return 0;
+    };
+    core.state = core.gameboy.save_state();
+    core.state.len()
+}
+
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub extern "C" fn gb_state() -> *const u8 {
+    core().as_ref().map_or(std::ptr::null(), |core| core.state.as_ptr())
+}
+
+// Restores a state from gb_save_state. A bad state leaves the game running
+// as it was.
+#[cfg_attr(target_arch = "wasm32", unsafe(no_mangle))]
+pub unsafe extern "C" fn gb_load_state(ptr: *const u8, len: usize) -> bool {
+    let mut core = core();
+    let Some(core) = &mut *core else {
+        return false;
+    };
+    if ptr.is_null() {
+        return false;
+    }
+    let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    match core.gameboy.load_state(bytes) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("{}", e);
+            false
+        }
+    }
+}
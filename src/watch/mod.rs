@@ -0,0 +1,134 @@
+// Bus-level watchpoints
+//
+// `Memory` reports every access to the ranges registered here. Hits are only
+// collected, not judged: conditions need CPU state, so the debugger drains
+// the list after each instruction and decides whether to stop. With no
+// ranges registered the check in `Memory::read`/`write` is a single branch.
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchRange {
+    pub start: u16,
+    pub end: u16, // Inclusive
+    pub read: bool,
+    pub write: bool,
+}
+
+impl WatchRange {
+    pub fn matches(&self, addr: u16, access: Access) -> bool {
+        let kind = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        kind && (self.start..=self.end).contains(&addr)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub value: u8, // Byte read, or byte about to be written
+    pub old: u8,   // Byte the write replaces; the byte read for reads
+    pub access: Access,
+}
+
+#[derive(Clone, Default)]
+pub struct Watchpoints {
+    ranges: Vec<WatchRange>,
+    hits: RefCell<Vec<WatchHit>>, // Reads go through `&self`, hence the RefCell
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_ranges(&mut self, ranges: Vec<WatchRange>) {
+        self.ranges = ranges;
+        self.hits.borrow_mut().clear();
+    }
+
+    #[inline(always)]
+    pub fn on_read(&self, addr: u16, value: u8) {
+        if !self.ranges.is_empty() {
+            self.record(addr, value, || value, Access::Read);
+        }
+    }
+
+    // `old` is only asked for when a range matches
+    #[inline(always)]
+    pub fn on_write(&self, addr: u16, value: u8, old: impl FnOnce() -> u8) {
+        if !self.ranges.is_empty() {
+            self.record(addr, value, old, Access::Write);
+        }
+    }
+
+    #[cold]
+    fn record(&self, addr: u16, value: u8, old: impl FnOnce() -> u8, access: Access) {
+        if self.ranges.iter().any(|r| r.matches(addr, access)) {
+            let old = old();
+            self.hits.borrow_mut().push(WatchHit { addr, value, old, access });
+        }
+    }
+
+    pub fn take_hits(&self) -> Vec<WatchHit> {
+        self.hits.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+    use alloc::vec;
+
+    fn watched(start: u16, end: u16, read: bool, write: bool) -> Memory {
+        let mut memory = Memory::new(&[0; 0x8000]);
+        memory.watchpoints.set_ranges(vec![WatchRange { start, end, read, write }]);
+        memory
+    }
+
+    #[test]
+    fn reads_hit_through_the_bus() {
+        let mut memory = watched(0xC000, 0xC00F, true, false);
+        memory.write(0xC008, 0x42);
+        assert!(memory.watchpoints.take_hits().is_empty());
+
+        assert_eq!(memory.read(0xC008), 0x42);
+        memory.read(0xC010);
+        memory.peek(0xC000); // Side-effect free
+        let hit = WatchHit { addr: 0xC008, value: 0x42, old: 0x42, access: Access::Read };
+        assert_eq!(memory.watchpoints.take_hits(), [hit]);
+        assert!(memory.watchpoints.take_hits().is_empty());
+    }
+
+    #[test]
+    fn writes_report_the_old_and_new_byte() {
+        let mut memory = watched(0xC000, 0xC000, false, true);
+        memory.write(0xC000, 0x11);
+        memory.write(0xC000, 0x22);
+        memory.read(0xC000);
+        memory.write(0xC001, 0x33);
+
+        let hits = memory.watchpoints.take_hits();
+        assert_eq!(hits.iter().map(|h| (h.old, h.value)).collect::<Vec<_>>(), [(0x00, 0x11), (0x11, 0x22)]);
+        assert!(hits.iter().all(|h| h.addr == 0xC000 && h.access == Access::Write));
+    }
+
+    #[test]
+    fn nothing_is_recorded_without_ranges() {
+        let mut memory = watched(0xC000, 0xC000, true, true);
+        memory.watchpoints.set_ranges(Vec::new());
+        memory.write(0xC000, 1);
+        memory.read(0xC000);
+        assert!(memory.watchpoints.take_hits().is_empty());
+    }
+}
@@ -0,0 +1,249 @@
+// libretro core test
+//
+// Loads the core's shared object the way a frontend does and drives it
+// through the libretro API: loads a ROM, runs frames with scripted input,
+// checks the video and audio callbacks, serialization round trips and the
+// memory interfaces, including that the registered memory map stays valid.
+// `cargo test` builds the cdylib next to this test's executable.
+#![cfg(unix)]
+
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr, CString};
+use std::sync::Mutex;
+
+use gb_emulator::libretro::*;
+
+const RTLD_NOW: c_int = 2;
+const RETRO_DEVICE_ID_JOYPAD_START: c_uint = 3;
+const FRAMES: u64 = 120;
+
+#[link(name = "dl")]
+unsafe extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *const c_char;
+}
+
+// What the callbacks have seen
+#[derive(Default)]
+struct Seen {
+    pixel_format: Option<c_uint>,
+    memory_map: Vec<(usize, usize, usize, u64)>, // Start, pointer, length and flags per descriptor
+    video_frames: u64,
+    video_size: (c_uint, c_uint, usize),
+    last_frame: Vec<u32>,
+    audio_frames: usize,
+    polls: u64,
+}
+
+static SEEN: Mutex<Option<Seen>> = Mutex::new(None);
+
+fn seen<T>(f: impl FnOnce(&mut Seen) -> T) -> T {
+    f(SEEN.lock().unwrap().get_or_insert_with(Seen::default))
+}
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+            let format = unsafe { *data.cast::<c_uint>() };
+            seen(|s| s.pixel_format = Some(format));
+            true
+        }
+        RETRO_ENVIRONMENT_SET_MEMORY_MAPS => {
+            let map = unsafe { &*data.cast::<RetroMemoryMap>() };
+            let descriptors = unsafe { std::slice::from_raw_parts(map.descriptors, map.num_descriptors as usize) };
+            let memory_map = descriptors.iter().map(|d| (d.start, d.ptr as usize, d.len, d.flags)).collect();
+            seen(|s| s.memory_map = memory_map);
+            true
+        }
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let pixels = unsafe { std::slice::from_raw_parts(data.cast::<u32>(), pitch / 4 * height as usize) };
+    seen(|s| {
+        s.video_frames += 1;
+        s.video_size = (width, height, pitch);
+        s.last_frame = pixels.to_vec();
+    });
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn audio_batch(_data: *const i16, frames: usize) -> usize {
+    seen(|s| s.audio_frames += frames);
+    frames
+}
+
+unsafe extern "C" fn input_poll() {
+    seen(|s| s.polls += 1);
+}
+
+// Holds Start for a few frames after the first second
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    let frame = seen(|s| s.polls);
+    (port == 0 && device == RETRO_DEVICE_JOYPAD && id == RETRO_DEVICE_ID_JOYPAD_START && (60..66).contains(&frame)) as i16
+}
+
+struct Core {
+    handle: *mut c_void,
+}
+
+impl Core {
+    // The core cargo built for this test run, next to the test executable
+    fn open() -> Core {
+        let exe = std::env::current_exe().unwrap();
+        let path = exe.parent().unwrap().join(format!("{}gb_emulator{}", DLL_PREFIX, DLL_SUFFIX));
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let handle = unsafe { dlopen(c_path.as_ptr(), RTLD_NOW) };
+        assert!(!handle.is_null(), "{}: {}", path.display(), unsafe { CStr::from_ptr(dlerror()) }.to_string_lossy());
+        Core { handle }
+    }
+
+    // Looks up `name` as a function of type `F`
+    unsafe fn get<F: Copy>(&self, name: &str) -> F {
+        let c_name = CString::new(name).unwrap();
+        let symbol = unsafe { dlsym(self.handle, c_name.as_ptr()) };
+        assert!(!symbol.is_null(), "core does not export {}", name);
+        unsafe { std::mem::transmute_copy(&symbol) }
+    }
+}
+
+// Pointer of the descriptor mapped at `start`, 0 if there is none
+fn mapped_at(start: usize) -> usize {
+    seen(|s| s.memory_map.iter().find(|d| d.0 == start).map_or(0, |d| d.1))
+}
+
+// Copies of the bytes behind every writable descriptor
+unsafe fn mapped_contents() -> Vec<Vec<u8>> {
+    let memory_map = seen(|s| s.memory_map.clone());
+    memory_map
+        .into_iter()
+        .filter(|d| d.3 & RETRO_MEMDESC_CONST == 0)
+        .map(|(_, ptr, len, _)| unsafe { std::slice::from_raw_parts(ptr as *const u8, len) }.to_vec())
+        .collect()
+}
+
+// Overwrites the writable memory through the map, like a frontend poking RAM
+unsafe fn scribble_mapped() {
+    for (_, ptr, len, flags) in seen(|s| s.memory_map.clone()) {
+        if flags & RETRO_MEMDESC_CONST == 0 {
+            unsafe { std::ptr::write_bytes(ptr as *mut u8, 0xA5, len) };
+        }
+    }
+}
+
+// NOPs from the entry point on, with 8 KiB of battery-backed cartridge RAM
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x134..0x13B].copy_from_slice(b"LIBRETR");
+    rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+    rom[0x149] = 0x02; // 8 KiB
+    rom
+}
+
+#[test]
+fn drives_the_core_like_a_frontend() {
+    let rom = rom();
+    let core = Core::open();
+
+    unsafe {
+        let api_version: extern "C" fn() -> c_uint = core.get("retro_api_version");
+        assert_eq!(api_version(), RETRO_API_VERSION);
+
+        core.get::<extern "C" fn(RetroEnvironment)>("retro_set_environment")(environment);
+        core.get::<extern "C" fn(RetroVideoRefresh)>("retro_set_video_refresh")(video_refresh);
+        core.get::<extern "C" fn(RetroAudioSample)>("retro_set_audio_sample")(audio_sample);
+        core.get::<extern "C" fn(RetroAudioSampleBatch)>("retro_set_audio_sample_batch")(audio_batch);
+        core.get::<extern "C" fn(RetroInputPoll)>("retro_set_input_poll")(input_poll);
+        core.get::<extern "C" fn(RetroInputState)>("retro_set_input_state")(input_state);
+        core.get::<extern "C" fn()>("retro_init")();
+
+        let mut info = std::mem::zeroed::<RetroSystemInfo>();
+        core.get::<unsafe extern "C" fn(*mut RetroSystemInfo)>("retro_get_system_info")(&mut info);
+        assert_eq!(CStr::from_ptr(info.library_name).to_str(), Ok("gb_emulator"));
+
+        let game = RetroGameInfo {
+            path: std::ptr::null(),
+            data: rom.as_ptr().cast(),
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        let load_game: unsafe extern "C" fn(*const RetroGameInfo) -> bool = core.get("retro_load_game");
+        assert!(load_game(&game), "retro_load_game");
+        assert_eq!(seen(|s| s.pixel_format), Some(RETRO_PIXEL_FORMAT_XRGB8888));
+        assert!(!seen(|s| s.memory_map.is_empty()), "memory map registered");
+
+        // The map is registered once, so the buffers behind it must stay
+        // where they are for as long as the game is loaded
+        let memory_data: extern "C" fn(c_uint) -> *mut c_void = core.get("retro_get_memory_data");
+        let memory_size: extern "C" fn(c_uint) -> usize = core.get("retro_get_memory_size");
+        let map_is_current = || {
+            mapped_at(0x8000) == memory_data(RETRO_MEMORY_VIDEO_RAM) as usize
+                && mapped_at(0xC000) == memory_data(RETRO_MEMORY_SYSTEM_RAM) as usize
+                && mapped_at(0xA000) == memory_data(RETRO_MEMORY_SAVE_RAM) as usize
+        };
+        assert!(map_is_current(), "memory map points at the core's buffers");
+
+        let mut av = std::mem::zeroed::<RetroSystemAvInfo>();
+        core.get::<unsafe extern "C" fn(*mut RetroSystemAvInfo)>("retro_get_system_av_info")(&mut av);
+
+        let retro_run: extern "C" fn() = core.get("retro_run");
+        for _ in 0..FRAMES {
+            retro_run();
+        }
+        let (width, height, pitch) = seen(|s| s.video_size);
+        assert_eq!(seen(|s| s.video_frames), FRAMES, "one video frame per retro_run");
+        assert_eq!((width, height, pitch), (av.geometry.base_width, av.geometry.base_height, width as usize * 4));
+        let expected_audio = (FRAMES as f64 * av.timing.sample_rate / av.timing.fps) as usize;
+        assert!(seen(|s| s.audio_frames).abs_diff(expected_audio) <= 1, "audio frames match the sample rate");
+        assert_eq!(seen(|s| s.polls), FRAMES, "input polled every frame");
+
+        let sram = memory_data(RETRO_MEMORY_SAVE_RAM);
+        assert!(!sram.is_null(), "save RAM exposed");
+        assert_eq!(memory_size(RETRO_MEMORY_SAVE_RAM), 0x2000);
+        assert!(!memory_data(RETRO_MEMORY_SYSTEM_RAM).is_null() && memory_size(RETRO_MEMORY_SYSTEM_RAM) >= 0x2000);
+
+        // Save, run ahead, restore, run the same frames again
+        let serialize_size: extern "C" fn() -> usize = core.get("retro_serialize_size");
+        let serialize: unsafe extern "C" fn(*mut c_void, usize) -> bool = core.get("retro_serialize");
+        let unserialize: unsafe extern "C" fn(*const c_void, usize) -> bool = core.get("retro_unserialize");
+        let mut state = vec![0u8; serialize_size()];
+        assert!(!state.is_empty() && serialize(state.as_mut_ptr().cast(), state.len()), "retro_serialize");
+        let saved = mapped_contents();
+        for _ in 0..30 {
+            retro_run();
+        }
+        let ahead = seen(|s| s.last_frame.clone());
+        assert!(unserialize(state.as_ptr().cast(), state.len()), "retro_unserialize");
+        assert!(map_is_current(), "memory map unchanged by unserialize");
+        scribble_mapped();
+        assert!(unserialize(state.as_ptr().cast(), state.len()), "retro_unserialize over poked memory");
+        assert!(mapped_contents() == saved, "unserialize restores the state through the memory map");
+        for _ in 0..30 {
+            retro_run();
+        }
+        assert!(seen(|s| s.last_frame.clone()) == ahead, "replay after unserialize is identical");
+        assert!(!unserialize(state.as_ptr().cast(), state.len() / 2), "truncated state is rejected");
+        assert_eq!(memory_data(RETRO_MEMORY_SAVE_RAM), sram, "save RAM stays in place across unserialize");
+
+        // Save RAM written by the frontend survives a reset, in place
+        std::ptr::write_bytes(sram.cast::<u8>(), 0x5A, 0x2000);
+        core.get::<extern "C" fn()>("retro_reset")();
+        assert!(map_is_current(), "memory map unchanged by retro_reset");
+        assert_eq!(memory_data(RETRO_MEMORY_SAVE_RAM), sram);
+        assert!(std::slice::from_raw_parts(sram.cast::<u8>(), 0x2000).iter().all(|&b| b == 0x5A), "save RAM survives retro_reset");
+        retro_run();
+
+        let cheat_reset: extern "C" fn() = core.get("retro_cheat_reset");
+        let cheat_set: unsafe extern "C" fn(c_uint, bool, *const c_char) = core.get("retro_cheat_set");
+        cheat_set(0, true, c"010238CD+00A-17B-C49".as_ptr());
+        retro_run();
+        cheat_reset();
+
+        core.get::<extern "C" fn()>("retro_unload_game")();
+        assert!(memory_data(RETRO_MEMORY_SAVE_RAM).is_null(), "no memory without a game");
+        core.get::<extern "C" fn()>("retro_deinit")();
+    }
+}
@@ -0,0 +1,67 @@
+// wasm build test
+//
+// Builds the library for wasm32-unknown-unknown the way a web page would
+// ship it and runs wasm/headless.mjs against it in Node. Needs the wasm32
+// target (`rustup target add wasm32-unknown-unknown`) and `node`; without
+// them the test says so and passes, CI installs both.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+const TARGET: &str = "wasm32-unknown-unknown";
+
+// Whether `rustc` has the standard library for the wasm target
+fn has_wasm_target() -> bool {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let Ok(output) = Command::new(rustc).args(["--print", "sysroot"]).output() else {
+        return false;
+    };
+    let sysroot = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Path::new(&sysroot).join("lib/rustlib").join(TARGET).exists()
+}
+
+fn has_node() -> bool {
+    Command::new("node").arg("--version").output().is_ok_and(|output| output.status.success())
+}
+
+#[test]
+fn headless_script_passes() {
+    if !has_wasm_target() || !has_node() {
+        eprintln!("skipping: needs the {} target and node", TARGET);
+        return;
+    }
+
+    // A separate target directory, as the outer `cargo test` holds the lock
+    // on the default one
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("wasm");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .args(["build", "--release", "--lib", "--target", TARGET, "--no-default-features", "--features", "std"])
+        .arg("--target-dir")
+        .arg(&out_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .unwrap();
+    assert!(status.success(), "wasm build failed");
+
+    // NOPs from the entry point on
+    let rom = out_dir.join("blank.gb");
+    fs::write(&rom, vec![0; 0x8000]).unwrap();
+
+    let wasm = out_dir.join(TARGET).join("release/gb_emulator.wasm");
+    let output = Command::new("node")
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("wasm/headless.mjs"))
+        .arg(&wasm)
+        .arg(&rom)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "headless.mjs failed:\n{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("All checks passed"));
+}
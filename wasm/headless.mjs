@@ -0,0 +1,91 @@
+// Headless test for the wasm build
+//
+// Loads the module in Node the way a web page would and drives the API in
+// src/wasm: loads a ROM, runs frames with a button held, checks the
+// framebuffer and audio sizes, and replays from a save state to check the
+// run is deterministic. Exits non-zero on the first failed check.
+// tests/wasm.rs runs it as part of `cargo test`; by hand:
+//
+//     cargo build --release --lib --target wasm32-unknown-unknown --no-default-features --features std
+//     node wasm/headless.mjs target/wasm32-unknown-unknown/release/gb_emulator.wasm game.gb [frames]
+
+import { readFileSync } from "node:fs";
+import { argv, exit } from "node:process";
+
+const BUTTON_START = 8;
+
+if (argv.length < 4) {
+    console.error("Usage: node headless.mjs <gb_emulator.wasm> <rom_file> [frames]");
+    exit(1);
+}
+const frames = Number(argv[4] ?? 120);
+
+function check(ok, what) {
+    if (!ok) {
+        console.error(`FAIL  ${what}`);
+        exit(1);
+    }
+    console.log(`ok    ${what}`);
+}
+
+// The core needs no host functions; anything it does import fails loudly
+const module = await WebAssembly.compile(readFileSync(argv[2]));
+const imports = {};
+for (const { module: name, name: field } of WebAssembly.Module.imports(module)) {
+    imports[name] ??= {};
+    imports[name][field] = () => {
+        throw new Error(`unexpected import ${name}.${field}`);
+    };
+}
+const { exports: gb } = await WebAssembly.instantiate(module, imports);
+
+// Views must be made after each call, since memory may have grown
+const bytes = (ptr, len) => new Uint8Array(gb.memory.buffer, ptr, len).slice();
+
+function copyIn(data) {
+    const ptr = gb.gb_alloc(data.length);
+    new Uint8Array(gb.memory.buffer, ptr, data.length).set(data);
+    return ptr;
+}
+
+function frame() {
+    const width = gb.gb_screen_width();
+    const height = gb.gb_screen_height();
+    return bytes(gb.gb_framebuffer(), width * height * 4);
+}
+
+const rom = readFileSync(argv[3]);
+const romPtr = copyIn(rom);
+check(gb.gb_load_rom(romPtr, rom.length), "gb_load_rom");
+gb.gb_free(romPtr, rom.length);
+
+let audioSamples = 0;
+for (let i = 0; i < frames; i++) {
+    gb.gb_set_buttons(i >= 60 && i < 66 ? BUTTON_START : 0);
+    gb.gb_run_frame();
+    audioSamples += gb.gb_audio_len();
+}
+const width = gb.gb_screen_width();
+const height = gb.gb_screen_height();
+console.log(`video ${width}x${height}, audio ${gb.gb_sample_rate()} Hz`);
+check(width >= 160 && height >= 144, "screen size");
+const pixels = frame();
+check(pixels.every((value, i) => i % 4 !== 3 || value === 255), "framebuffer is opaque RGBA");
+const expected = (frames * 2 * gb.gb_sample_rate() * 70224) / 4194304;
+check(Math.abs(audioSamples - expected) <= 2, "audio samples match the sample rate");
+
+// Save, run ahead, restore, run the same frames again
+const stateLen = gb.gb_save_state();
+check(stateLen > 0, "gb_save_state");
+const state = bytes(gb.gb_state(), stateLen);
+for (let i = 0; i < 30; i++) gb.gb_run_frame();
+const ahead = frame();
+
+const statePtr = copyIn(state);
+check(gb.gb_load_state(statePtr, state.length), "gb_load_state");
+for (let i = 0; i < 30; i++) gb.gb_run_frame();
+check(Buffer.compare(frame(), ahead) === 0, "replay after gb_load_state is identical");
+check(!gb.gb_load_state(statePtr, state.length >> 1), "truncated state is rejected");
+gb.gb_free(statePtr, state.length);
+
+console.log("All checks passed");